@@ -0,0 +1,11 @@
+//! Types shared by the `api-version` middleware and its clients, without any dependencies, in
+//! particular neither on axum nor on tower: the [version] semantics and the [wire] format. Client
+//! SDKs can depend on this crate alone for guaranteed wire compatibility with servers using the
+//! middleware, which re-exports both modules.
+
+#![no_std]
+
+pub mod version;
+pub mod wire;
+
+pub use version::{ApiVersions, strip_version};
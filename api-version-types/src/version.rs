@@ -0,0 +1,361 @@
+//! Core of API versioning: validated [ApiVersions] and parsing of version tokens like `"v1"`, such
+//! that clients or gateways can reuse the exact same version semantics as the `api-version`
+//! middleware.
+
+use core::{
+    error::Error,
+    fmt::{self, Display, Formatter},
+    ops::Deref,
+};
+
+/// Maximum number of [ApiVersions]; larger sets fail to compile with a readable message instead of
+/// straining the const evaluator. Use [validate] for larger sets.
+///
+/// ```compile_fail
+/// # use api_version_types::ApiVersions;
+/// const VERSIONS: ApiVersions<65> = ApiVersions::new({
+///     let mut versions = [0; 65];
+///     let mut n = 0;
+///     while n < 65 {
+///         versions[n] = n as u16;
+///         n += 1;
+///     }
+///     versions
+/// });
+/// ```
+pub const MAX_VERSIONS: usize = 64;
+
+/// API versions; a validated newtype for a `u16` array.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ApiVersions<const N: usize>([u16; N]);
+
+impl<const N: usize> ApiVersions<N> {
+    /// Create API versions. The given numbers must not be empty, must be strictly monotonically
+    /// increasing and less than `10_000`; otherwise `new` fails to compile in const contexts or
+    /// panics otherwise. More than [MAX_VERSIONS] numbers always fail to compile.
+    ///
+    /// # Examples
+    ///
+    /// Strictly monotonically versions `1` and `2` are valid:
+    ///
+    /// ```
+    /// # use api_version_types::ApiVersions;
+    /// const VERSIONS: ApiVersions<2> = ApiVersions::new([1, 2]);;
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Empty versions or such that are not strictly monotonically increasing are invalid and fail
+    /// to compile in const contexts or panic otherwise. As const panics cannot format values, the
+    /// messages only name the violated rule, e.g. duplicate versions; use [ApiVersions::try_new]
+    /// for the offending index and values.
+    ///
+    /// ```compile_fail
+    /// # use api_version_types::ApiVersions;
+    /// /// API versions must not be empty!
+    /// const VERSIONS: ApiVersions<0> = ApiVersions::new([]);
+    /// /// API versions must be strictly monotonically increasing!
+    /// const VERSIONS: ApiVersions<0> = ApiVersions::new([2, 1]);
+    /// /// API versions must be within 0u16..10_000!
+    /// const VERSIONS: ApiVersions<0> = ApiVersions::new([10_000]);
+    /// ```
+    pub const fn new(versions: [u16; N]) -> Self {
+        const {
+            assert!(
+                N <= MAX_VERSIONS,
+                "API versions must not exceed MAX_VERSIONS"
+            )
+        };
+
+        match Self::try_new(versions) {
+            Ok(versions) => versions,
+            Err(ApiVersionsError::Empty) => panic!("API versions must not be empty"),
+            Err(ApiVersionsError::Duplicate { .. }) => {
+                panic!(
+                    "API versions must be strictly monotonically increasing, but have duplicates"
+                )
+            }
+            Err(ApiVersionsError::Decreasing { .. }) => {
+                panic!("API versions must be strictly monotonically increasing, but decrease")
+            }
+            Err(ApiVersionsError::OutOfRange { .. }) => {
+                panic!("API versions must be within 0u16..10_000")
+            }
+        }
+    }
+
+    /// Create API versions like [ApiVersions::new], yet return an error describing the first
+    /// violation precisely instead of panicking.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use api_version_types::version::{ApiVersions, ApiVersionsError};
+    /// let error = ApiVersions::try_new([1, 2, 2]).unwrap_err();
+    /// assert_eq!(error, ApiVersionsError::Duplicate { index: 2, version: 2 });
+    /// ```
+    pub const fn try_new(versions: [u16; N]) -> Result<Self, ApiVersionsError> {
+        const {
+            assert!(
+                N <= MAX_VERSIONS,
+                "API versions must not exceed MAX_VERSIONS"
+            )
+        };
+
+        if N == 0 {
+            return Err(ApiVersionsError::Empty);
+        }
+
+        let mut n = 0;
+        while n < N {
+            let previous = if n > 0 { Some(versions[n - 1]) } else { None };
+            if let Err(error) = check(n, previous, versions[n]) {
+                return Err(error);
+            }
+            n += 1;
+        }
+
+        Ok(Self(versions))
+    }
+
+    /// Check whether the given version is one of these API versions; unlike `contains` on the
+    /// dereferenced array, this can be used in const contexts.
+    pub const fn has_version(&self, version: u16) -> bool {
+        let mut n = 0;
+        while n < N {
+            if self.0[n] == version {
+                return true;
+            }
+            n += 1;
+        }
+
+        false
+    }
+}
+
+/// Formats the versions like `"[v0, v1]"`.
+impl<const N: usize> Display for ApiVersions<N> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("[")?;
+        for (n, version) in self.0.iter().enumerate() {
+            if n > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "v{version}")?;
+        }
+        f.write_str("]")
+    }
+}
+
+impl<const N: usize> Deref for ApiVersions<N> {
+    type Target = [u16; N];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// Violation of the rules for [ApiVersions], see [ApiVersions::try_new].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ApiVersionsError {
+    /// The versions are empty.
+    Empty,
+
+    /// The version at the given index equals its predecessor.
+    Duplicate {
+        /// The index of the offending version.
+        index: usize,
+
+        /// The offending version.
+        version: u16,
+    },
+
+    /// The version at the given index is less than its predecessor.
+    Decreasing {
+        /// The index of the offending version.
+        index: usize,
+
+        /// The predecessor of the offending version.
+        previous: u16,
+
+        /// The offending version.
+        version: u16,
+    },
+
+    /// The version at the given index is not within `0u16..10_000`.
+    OutOfRange {
+        /// The index of the offending version.
+        index: usize,
+
+        /// The offending version.
+        version: u16,
+    },
+}
+
+impl Display for ApiVersionsError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiVersionsError::Empty => write!(f, "API versions must not be empty"),
+
+            ApiVersionsError::Duplicate { index, version } => write!(
+                f,
+                "API versions must be strictly monotonically increasing, but version {version} at \
+                 index {index} is a duplicate"
+            ),
+
+            ApiVersionsError::Decreasing {
+                index,
+                previous,
+                version,
+            } => write!(
+                f,
+                "API versions must be strictly monotonically increasing, but version {version} at \
+                 index {index} is less than its predecessor {previous}"
+            ),
+
+            ApiVersionsError::OutOfRange { index, version } => write!(
+                f,
+                "API versions must be within 0u16..10_000, but version {version} at index {index} \
+                 is not"
+            ),
+        }
+    }
+}
+
+impl Error for ApiVersionsError {}
+
+/// Validate the given versions like [ApiVersions::try_new] without any limit on their number,
+/// e.g. for large sets generated at runtime, returning their number.
+///
+/// # Examples
+///
+/// ```
+/// # use api_version_types::version::validate;
+/// assert_eq!(validate(0..1_000), Ok(1_000));
+/// assert!(validate([1, 0]).is_err());
+/// ```
+pub fn validate(versions: impl IntoIterator<Item = u16>) -> Result<usize, ApiVersionsError> {
+    let mut previous = None;
+    let mut count = 0;
+    for (index, version) in versions.into_iter().enumerate() {
+        check(index, previous, version)?;
+        previous = Some(version);
+        count += 1;
+    }
+
+    if count == 0 {
+        return Err(ApiVersionsError::Empty);
+    }
+    Ok(count)
+}
+
+/// Check the given version at the given index with the given predecessor, if any.
+const fn check(index: usize, previous: Option<u16>, version: u16) -> Result<(), ApiVersionsError> {
+    if let Some(previous) = previous {
+        if previous == version {
+            return Err(ApiVersionsError::Duplicate { index, version });
+        }
+        if previous > version {
+            return Err(ApiVersionsError::Decreasing {
+                index,
+                previous,
+                version,
+            });
+        }
+    }
+    if version >= 10_000 {
+        return Err(ApiVersionsError::OutOfRange { index, version });
+    }
+    Ok(())
+}
+
+/// Parse a version token, i.e. `"v"` followed by one to four ASCII digits, e.g. `"v0"` or
+/// `"v42"`, into the version number.
+///
+/// # Examples
+///
+/// ```
+/// # use api_version_types::version::parse;
+/// assert_eq!(parse("v42"), Some(42));
+/// assert_eq!(parse("v10000"), None);
+/// assert_eq!(parse("42"), None);
+/// ```
+pub const fn parse(token: &str) -> Option<u16> {
+    parse_bytes(token.as_bytes())
+}
+
+/// Parse a version token like [parse], yet from bytes, e.g. a raw header value, without UTF-8
+/// validation.
+///
+/// # Examples
+///
+/// ```
+/// # use api_version_types::version::parse_bytes;
+/// assert_eq!(parse_bytes(b"v42"), Some(42));
+/// assert_eq!(parse_bytes(b"v4\xff"), None);
+/// ```
+pub const fn parse_bytes(token: &[u8]) -> Option<u16> {
+    if token.len() < 2 || token.len() > 5 || token[0] != b'v' {
+        return None;
+    }
+
+    // Accumulate without branching on the digits and check their validity once at the end; even
+    // four invalid "digits" of up to 255 cannot overflow a `u32`.
+    let mut version = 0_u32;
+    let mut valid = true;
+    let mut n = 1;
+    while n < token.len() {
+        let digit = token[n].wrapping_sub(b'0');
+        valid &= digit < 10;
+        version = version * 10 + digit as u32;
+        n += 1;
+    }
+
+    if valid { Some(version as u16) } else { None }
+}
+
+/// Strip a version prefix, i.e. a canonical version token without leading zeros, e.g. `"/v1"`,
+/// followed by a `'/'`, from the given path, returning the version, if any, and the remaining
+/// path. These are the same rules the middleware uses to detect version prefixes, e.g. for logging
+/// or metrics layers normalizing versioned paths.
+///
+/// # Examples
+///
+/// ```
+/// # use api_version_types::strip_version;
+/// assert_eq!(strip_version("/v1/test"), (Some(1), "/test"));
+/// assert_eq!(strip_version("/v01/test"), (None, "/v01/test"));
+/// assert_eq!(strip_version("/v1"), (None, "/v1"));
+/// assert_eq!(strip_version("/test"), (None, "/test"));
+/// ```
+pub fn strip_version(path: &str) -> (Option<u16>, &str) {
+    let Some((token, rest)) = path
+        .strip_prefix('/')
+        .and_then(|path| path.find('/').map(|n| path.split_at(n)))
+    else {
+        return (None, path);
+    };
+
+    match parse(token) {
+        Some(version) if !token.starts_with("v0") || token == "v0" => (Some(version), rest),
+        _ => (None, path),
+    }
+}
+
+/// Check whether the given versions are strictly monotonically increasing.
+pub const fn is_monotonically_increasing<const N: usize>(versions: [u16; N]) -> bool {
+    if N < 2 {
+        return true;
+    }
+
+    let mut n = 1;
+    while n < N {
+        if versions[n - 1] >= versions[n] {
+            return false;
+        }
+        n += 1;
+    }
+
+    true
+}
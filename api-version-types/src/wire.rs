@@ -0,0 +1,69 @@
+//! Wire format of the `api-version` middleware, like [version](crate::version): header names,
+//! version tokens and error reason codes. Client SDKs can rely on these for compatibility with
+//! servers using the middleware, which uses exactly these values.
+
+pub use crate::version::{parse as parse_version, parse_bytes as parse_version_bytes};
+
+/// Name of the `"x-api-version"` request and response header carrying a version token, e.g.
+/// `"v1"`, see [parse_version].
+pub const X_API_VERSION: &str = "x-api-version";
+
+/// Name of the `"x-api-supported-versions"` response header listing the supported versions, e.g.
+/// `"v0, v1"`.
+pub const X_API_SUPPORTED_VERSIONS: &str = "x-api-supported-versions";
+
+/// Name of the `"x-api-default-version-changing"` response header announcing a change of the
+/// default version.
+pub const X_API_DEFAULT_VERSION_CHANGING: &str = "x-api-default-version-changing";
+
+/// Name of the `"x-api-version-experiment"` response header carrying the assignment by the
+/// default experiment, e.g. `"checkout=v2"`.
+pub const X_API_VERSION_EXPERIMENT: &str = "x-api-version-experiment";
+
+/// Name of the `"x-api-version-error"` response header carrying one of the reason codes below on
+/// error responses generated by the middleware.
+pub const X_API_VERSION_ERROR: &str = "x-api-version-error";
+
+/// Name of the `"deprecation"` response header (RFC 9745).
+pub const DEPRECATION: &str = "deprecation";
+
+/// Name of the `"sunset"` response header (RFC 8594).
+pub const SUNSET: &str = "sunset";
+
+/// Name of the `"x-api-sunset-in"` response header carrying the number of seconds until the
+/// sunset, e.g. `"86400"`.
+pub const X_API_SUNSET_IN: &str = "x-api-sunset-in";
+
+/// Reason code for an unknown version.
+pub const UNKNOWN_VERSION: &str = "unknown-version";
+
+/// Reason code for a retired version.
+pub const RETIRED: &str = "retired";
+
+/// Reason code for an invalid `"x-api-version"` header.
+pub const INVALID_HEADER: &str = "invalid-header";
+
+/// Reason code for a request without version in strict mode.
+pub const UNVERSIONED: &str = "unversioned";
+
+/// Reason code for a path with dot segments.
+pub const DOT_SEGMENTS: &str = "dot-segments";
+
+/// Reason code for an exceeded rate limit.
+pub const RATE_LIMITED: &str = "rate-limited";
+
+/// Reason code for a too large request body.
+pub const BODY_TOO_LARGE: &str = "body-too-large";
+
+/// Reason code for a request body without length which must be transformed.
+pub const LENGTH_REQUIRED: &str = "length-required";
+
+/// Reason code for an encoded response which must be transformed.
+pub const ENCODED_RESPONSE: &str = "encoded-response";
+
+/// Reason code for a request which timed out in the server, responded with `503 Service
+/// Unavailable`.
+pub const TIMEOUT: &str = "timeout";
+
+/// Reason code for a response of the discovery or debug endpoint which cannot be serialized.
+pub const SERIALIZATION_FAILED: &str = "serialization-failed";
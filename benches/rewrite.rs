@@ -0,0 +1,110 @@
+//! Compare building rewritten paths into an inline buffer, like [rewrite_uri] does, with building
+//! them into a presized `String` which is handed over as `Bytes`.
+//!
+//! `PathAndQuery` copies an inline buffer into its own bytes with a single allocation, whereas a
+//! `String` with spare capacity needs a further allocation to be shared, which is asserted before
+//! benchmarking.
+
+use api_version::rewrite_uri;
+use axum::{
+    body::Bytes,
+    http::{Uri, uri::PathAndQuery},
+};
+use criterion::{Criterion, criterion_group, criterion_main};
+use smallvec::SmallVec;
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    fmt::Write as _,
+    hint::black_box,
+    io::Write as _,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator(AtomicUsize::new(0));
+
+struct CountingAllocator(AtomicUsize);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.0.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+/// Rewrite the given URI building the new "path and query" into an inline buffer.
+fn rewrite_inline(uri: &Uri, base_path: &str, version: u16) -> Option<Uri> {
+    let mut parts = uri.to_owned().into_parts();
+    let path = uri.path().strip_prefix(base_path)?;
+    let query = uri.query();
+
+    let mut paq = SmallVec::<[u8; 128]>::new();
+    paq.extend_from_slice(base_path.as_bytes());
+    write!(paq, "/v{version}").ok()?;
+    paq.extend_from_slice(path.as_bytes());
+    if let Some(query) = query {
+        paq.push(b'?');
+        paq.extend_from_slice(query.as_bytes());
+    }
+
+    parts.path_and_query = Some(PathAndQuery::from_maybe_shared(paq).ok()?);
+    Uri::from_parts(parts).ok()
+}
+
+/// Rewrite the given URI building the new "path and query" into a presized `String`.
+fn rewrite_string(uri: &Uri, base_path: &str, version: u16) -> Option<Uri> {
+    let mut parts = uri.to_owned().into_parts();
+    let path = uri.path().strip_prefix(base_path)?;
+    let query = uri.query();
+
+    let capacity = base_path.len() + 6 + path.len() + query.map_or(0, |query| query.len() + 1);
+    let mut paq = String::with_capacity(capacity);
+    paq.push_str(base_path);
+    write!(paq, "/v{version}").ok()?;
+    paq.push_str(path);
+    if let Some(query) = query {
+        paq.push('?');
+        paq.push_str(query);
+    }
+
+    parts.path_and_query = Some(PathAndQuery::from_maybe_shared(Bytes::from(paq)).ok()?);
+    Uri::from_parts(parts).ok()
+}
+
+/// The number of allocations of the given function.
+fn allocations(f: impl FnOnce() -> Option<Uri>) -> usize {
+    let before = ALLOCATOR.0.load(Ordering::Relaxed);
+    let uri = f();
+    let after = ALLOCATOR.0.load(Ordering::Relaxed);
+    drop(uri);
+    after - before
+}
+
+fn bench_rewrite(c: &mut Criterion) {
+    let uri = "/api/users/42/orders?limit=10".parse::<Uri>().unwrap();
+    // The first clone of a parsed URI makes its bytes shareable, which allocates once.
+    drop(uri.clone());
+
+    let inline = allocations(|| rewrite_inline(&uri, "/api", 1));
+    let string = allocations(|| rewrite_string(&uri, "/api", 1));
+    assert_eq!((inline, string), (1, 2));
+
+    let mut group = c.benchmark_group("rewrite");
+    group.bench_function("rewrite_uri", |b| {
+        b.iter(|| rewrite_uri(black_box(&uri), "/api", 1))
+    });
+    group.bench_function("inline", |b| {
+        b.iter(|| rewrite_inline(black_box(&uri), "/api", 1))
+    });
+    group.bench_function("string", |b| {
+        b.iter(|| rewrite_string(black_box(&uri), "/api", 1))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_rewrite);
+criterion_main!(benches);
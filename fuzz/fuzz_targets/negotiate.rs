@@ -0,0 +1,60 @@
+//! Fuzz target for the negotiation: arbitrary paths, headers and configurations must neither panic
+//! nor produce invalid rewritten URIs. Run with `just fuzz`; add regressions to
+//! `test_negotiation_corpus` in `src/lib.rs`.
+
+#![no_main]
+
+use api_version::{
+    ApiVersionLayer, ApiVersions,
+    negotiation::{NegotiationInput, NegotiationReport, negotiate},
+    strip_version,
+};
+use arbitrary::Arbitrary;
+use axum::http::{HeaderMap, HeaderName, HeaderValue, Method, Uri};
+use libfuzzer_sys::fuzz_target;
+
+const API_VERSIONS: ApiVersions<3> = ApiVersions::new([0, 1, 9999]);
+
+#[derive(Debug, Arbitrary)]
+struct Input<'a> {
+    path: &'a str,
+    headers: Vec<(&'a str, &'a [u8])>,
+    strict: bool,
+    strip_base_path: bool,
+    rewrite_path: bool,
+    vendor_media_type: bool,
+}
+
+fuzz_target!(|input: Input<'_>| {
+    let _ = strip_version(input.path);
+
+    let Ok(uri) = input.path.parse::<Uri>() else {
+        return;
+    };
+    let mut headers = HeaderMap::new();
+    for (name, value) in input.headers {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(name.as_bytes()),
+            HeaderValue::from_bytes(value),
+        ) {
+            headers.append(name, value);
+        }
+    }
+
+    let mut layer = ApiVersionLayer::new("/api", API_VERSIONS)
+        .strict(input.strict)
+        .strip_base_path(input.strip_base_path)
+        .rewrite_path(input.rewrite_path);
+    if input.vendor_media_type {
+        layer = layer.vendor_media_type("acme");
+    }
+
+    let input = NegotiationInput {
+        method: &Method::GET,
+        uri: &uri,
+        headers: &headers,
+    };
+    if let NegotiationReport::Negotiated { uri: rewritten, .. } = negotiate(&input, &layer) {
+        assert!(rewritten.parse::<Uri>().is_ok(), "invalid rewritten URI");
+    }
+});
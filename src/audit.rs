@@ -0,0 +1,119 @@
+//! Audit records of negotiation decisions, e.g. for compliance environments which must retain
+//! records of API version access, see [AuditSink] and
+//! [ApiVersionLayer::audit_sink](crate::ApiVersionLayer::audit_sink).
+
+use crate::{VersionSource, VersioningError};
+use axum::http::{Method, Uri};
+use std::{future::Future, time::SystemTime};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tracing::warn;
+
+/// The number of records buffered for an [AuditSink], see
+/// [ApiVersionLayer::audit_sink](crate::ApiVersionLayer::audit_sink).
+pub const AUDIT_BUFFER_SIZE: usize = 1_024;
+
+/// Sink for [NegotiationRecord]s, see
+/// [ApiVersionLayer::audit_sink](crate::ApiVersionLayer::audit_sink). This is implemented for
+/// suitable closures.
+///
+/// # Examples
+///
+/// ```
+/// # use api_version::{ApiVersionLayer, ApiVersions, audit::NegotiationRecord};
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// const API_VERSIONS: ApiVersions<2> = ApiVersions::new([0, 1]);
+///
+/// let layer = ApiVersionLayer::new("/api", API_VERSIONS)
+///     .audit_sink(|record: NegotiationRecord| async move {
+///         println!("{record:?}"); // e.g. write to an append-only store
+///     });
+/// # }
+/// ```
+pub trait AuditSink: Send + Sync + 'static {
+    /// Record the given negotiation record.
+    fn record(&self, record: NegotiationRecord) -> impl Future<Output = ()> + Send;
+}
+
+impl<F, R> AuditSink for F
+where
+    F: Fn(NegotiationRecord) -> R + Send + Sync + 'static,
+    R: Future<Output = ()> + Send,
+{
+    fn record(&self, record: NegotiationRecord) -> impl Future<Output = ()> + Send {
+        self(record)
+    }
+}
+
+/// Record of a negotiation decision, see [AuditSink].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct NegotiationRecord {
+    /// The time of the decision according to the configured clock, see
+    /// [ApiVersionLayer::clock](crate::ApiVersionLayer::clock).
+    pub at: SystemTime,
+
+    /// The request method.
+    pub method: Method,
+
+    /// The request URI as received, i.e. before any rewriting.
+    pub uri: Uri,
+
+    /// The client key, if any, see
+    /// [ApiVersionLayer::client_key_header](crate::ApiVersionLayer::client_key_header).
+    pub client_key: Option<String>,
+
+    /// The outcome.
+    pub outcome: NegotiationOutcome,
+}
+
+/// Outcome of a negotiation, see [NegotiationRecord].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum NegotiationOutcome {
+    /// The request is not subject to versioning.
+    Skipped,
+
+    /// The request has been rejected with the given error.
+    Failed(VersioningError),
+
+    /// The given version has been negotiated from the given source.
+    Negotiated {
+        /// The resolved version.
+        version: u16,
+
+        /// The source of the resolved version.
+        source: VersionSource,
+    },
+}
+
+/// Sender of [NegotiationRecord]s to an [AuditSink] running in a background task.
+#[derive(Debug, Clone)]
+pub(crate) struct Auditor {
+    records: mpsc::Sender<NegotiationRecord>,
+}
+
+impl Auditor {
+    /// Spawn a background task passing the sent records on to the given sink, which stops once
+    /// all auditors have been dropped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a Tokio runtime.
+    pub(crate) fn spawn(sink: impl AuditSink) -> Self {
+        let (records, mut receiver) = mpsc::channel(AUDIT_BUFFER_SIZE);
+        tokio::spawn(async move {
+            while let Some(record) = receiver.recv().await {
+                sink.record(record).await;
+            }
+        });
+        Self { records }
+    }
+
+    /// Send the given record without waiting, i.e. drop it if the buffer is full.
+    pub(crate) fn send(&self, record: NegotiationRecord) {
+        if let Err(TrySendError::Full(record)) = self.records.try_send(record) {
+            warn!(uri = %record.uri, "dropping audit record, because buffer is full");
+        }
+    }
+}
@@ -0,0 +1,40 @@
+//! Report the breaking changes between two OpenAPI documents (JSON) of the same API version, see
+//! [api_version::openapi::breaking_changes], exiting with a failure if there are any.
+//!
+//! Usage: `api-version-openapi-diff <old.json> <new.json>`
+
+use api_version::openapi::breaking_changes;
+use serde_json::Value;
+use std::{env, fs, process::ExitCode};
+
+fn main() -> ExitCode {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    let [old, new] = args.as_slice() else {
+        eprintln!("usage: api-version-openapi-diff <old.json> <new.json>");
+        return ExitCode::from(2);
+    };
+
+    let (old, new) = match (read(old), read(new)) {
+        (Ok(old), Ok(new)) => (old, new),
+        (Err(error), _) | (_, Err(error)) => {
+            eprintln!("error: {error}");
+            return ExitCode::from(2);
+        }
+    };
+
+    let changes = breaking_changes(&old, &new);
+    if changes.is_empty() {
+        println!("no breaking changes");
+        return ExitCode::SUCCESS;
+    }
+    for change in &changes {
+        println!("{change}");
+    }
+    ExitCode::FAILURE
+}
+
+fn read(path: &str) -> Result<Value, String> {
+    let document =
+        fs::read_to_string(path).map_err(|error| format!("cannot read {path}: {error}"))?;
+    serde_json::from_str(&document).map_err(|error| format!("cannot parse {path}: {error}"))
+}
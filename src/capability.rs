@@ -0,0 +1,160 @@
+//! Capabilities of API versions, e.g. cursor pagination as of `v2`, as a single source of truth
+//! for shared handler code, queryable in const contexts, and for discovery, see [Capabilities].
+//! Like [version](crate::version), this only depends on `core`.
+
+use core::slice;
+
+/// Capability, identified by its name, which is supported as of a version and optionally until,
+/// i.e. excluding, a later version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capability {
+    name: &'static str,
+    since: u16,
+    until: Option<u16>,
+}
+
+impl Capability {
+    /// Create a capability with the given name supported as of the given version.
+    pub const fn new(name: &'static str, since: u16) -> Self {
+        Self {
+            name,
+            since,
+            until: None,
+        }
+    }
+
+    /// Stop supporting this capability as of the given version.
+    ///
+    /// # Panics
+    ///
+    /// Panics, i.e. fails to compile in const contexts, if the given version is not greater than
+    /// the one this capability is supported as of.
+    pub const fn until(self, until: u16) -> Self {
+        assert!(
+            until > self.since,
+            "capability must be supported for at least one version"
+        );
+        Self {
+            until: Some(until),
+            ..self
+        }
+    }
+
+    /// The name.
+    pub const fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Check whether the given version supports this capability.
+    pub const fn supports(&self, version: u16) -> bool {
+        version >= self.since
+            && match self.until {
+                Some(until) => version < until,
+                None => true,
+            }
+    }
+}
+
+/// Capability matrix of API versions with unique capability names. Queries are `const fn`, hence
+/// evaluated at compile time in const contexts. Pass the matrix to
+/// [ApiVersionLayer::capabilities](crate::ApiVersionLayer::capabilities) to export it via
+/// discovery.
+///
+/// # Examples
+///
+/// ```
+/// # use api_version::capability::{Capabilities, Capability};
+/// const CURSOR_PAGINATION: Capability = Capability::new("cursor-pagination", 2);
+/// const CAPABILITIES: Capabilities<2> = Capabilities::new([
+///     CURSOR_PAGINATION,
+///     Capability::new("legacy-ids", 0).until(2),
+/// ]);
+///
+/// assert!(const { CAPABILITIES.supports(2, "cursor-pagination") });
+/// assert!(!CAPABILITIES.supports(2, "legacy-ids"));
+/// assert!(!CURSOR_PAGINATION.supports(1));
+/// ```
+///
+/// Duplicate names fail to compile:
+///
+/// ```compile_fail
+/// # use api_version::capability::{Capabilities, Capability};
+/// const CAPABILITIES: Capabilities<2> =
+///     Capabilities::new([Capability::new("bulk", 1), Capability::new("bulk", 2)]);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities<const N: usize>([Capability; N]);
+
+impl<const N: usize> Capabilities<N> {
+    /// Create a capability matrix.
+    ///
+    /// # Panics
+    ///
+    /// Panics, i.e. fails to compile in const contexts, if capability names are not unique.
+    pub const fn new(capabilities: [Capability; N]) -> Self {
+        let mut n = 0;
+        while n < N {
+            let mut m = n + 1;
+            while m < N {
+                assert!(
+                    !str_eq(capabilities[n].name, capabilities[m].name),
+                    "capability names must be unique"
+                );
+                m += 1;
+            }
+            n += 1;
+        }
+        Self(capabilities)
+    }
+
+    /// The capability with the given name, if any.
+    pub const fn get(&self, name: &str) -> Option<&Capability> {
+        let mut n = 0;
+        while n < N {
+            if str_eq(self.0[n].name, name) {
+                return Some(&self.0[n]);
+            }
+            n += 1;
+        }
+        None
+    }
+
+    /// Check whether the given version supports the capability with the given name; unknown
+    /// capabilities are not supported.
+    pub const fn supports(&self, version: u16, name: &str) -> bool {
+        match self.get(name) {
+            Some(capability) => capability.supports(version),
+            None => false,
+        }
+    }
+
+    /// Iterate over the capabilities.
+    pub fn iter(&self) -> slice::Iter<'_, Capability> {
+        self.0.iter()
+    }
+}
+
+impl<'a, const N: usize> IntoIterator for &'a Capabilities<N> {
+    type Item = &'a Capability;
+    type IntoIter = slice::Iter<'a, Capability>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Const string equality.
+const fn str_eq(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut n = 0;
+    while n < a.len() {
+        if a[n] != b[n] {
+            return false;
+        }
+        n += 1;
+    }
+    true
+}
@@ -0,0 +1,98 @@
+//! Failure injection for resilience testing, e.g. to verify that clients handle version errors
+//! and `410 Gone` gracefully, see [ApiVersionLayer::chaos](crate::ApiVersionLayer::chaos).
+
+use crate::VersioningError;
+use axum::extract::Request;
+use std::{
+    fmt::{self, Debug, Formatter},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+/// Injection of the given fault into the given fraction of versioned requests matching a filter.
+/// The fraction is applied deterministically, e.g. every fourth matching request for `0.25`.
+///
+/// # Examples
+///
+/// ```
+/// # use api_version::{ApiVersionLayer, ApiVersions, VersioningError, chaos::{Chaos, Fault}};
+/// const API_VERSIONS: ApiVersions<2> = ApiVersions::new([0, 1]);
+///
+/// let chaos = Chaos::new(Fault::Error(VersioningError::RetiredVersion(0)), 0.1)
+///     .filter(|request| request.headers().contains_key("x-chaos"));
+/// let layer = ApiVersionLayer::new("/api", API_VERSIONS).chaos(chaos);
+/// ```
+#[derive(Clone)]
+pub struct Chaos {
+    fault: Fault,
+    fraction: f64,
+    filter: Option<Filter>,
+    count: Arc<AtomicU64>,
+}
+
+impl Chaos {
+    /// Create a failure injection for the given fault and fraction of requests.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the given fraction is not within `0.0..=1.0`.
+    pub fn new(fault: Fault, fraction: f64) -> Self {
+        assert!(
+            (0.0..=1.0).contains(&fraction),
+            "fraction must be within 0.0..=1.0"
+        );
+
+        Self {
+            fault,
+            fraction,
+            filter: None,
+            count: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Only inject the fault into requests matching the given filter.
+    pub fn filter(mut self, filter: impl Fn(&Request) -> bool + Send + Sync + 'static) -> Self {
+        self.filter = Some(Arc::new(filter));
+        self
+    }
+
+    /// The fault to be injected.
+    pub(crate) fn fault(&self) -> Fault {
+        self.fault
+    }
+
+    /// The fault to be injected into the given request, if any.
+    pub(crate) fn inject(&self, request: &Request) -> Option<Fault> {
+        if self.filter.as_ref().is_some_and(|filter| !filter(request)) {
+            return None;
+        }
+
+        let n = self.count.fetch_add(1, Ordering::Relaxed) as f64;
+        let inject = ((n + 1.0) * self.fraction).floor() > (n * self.fraction).floor();
+        inject.then_some(self.fault)
+    }
+}
+
+impl Debug for Chaos {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Chaos")
+            .field("fault", &self.fault)
+            .field("fraction", &self.fraction)
+            .finish_non_exhaustive()
+    }
+}
+
+type Filter = Arc<dyn Fn(&Request) -> bool + Send + Sync>;
+
+/// Fault injected by [Chaos].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Fault {
+    /// Reject the request with the given negotiation error.
+    Error(VersioningError),
+
+    /// Serve the request with the given version instead of the negotiated one.
+    ForceVersion(u16),
+}
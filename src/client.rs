@@ -0,0 +1,523 @@
+//! Client-side version negotiation for HTTP clients based on tower or on reqwest via
+//! `reqwest-middleware`, e.g. SDKs, which sets the `"x-api-version"` header, reports deprecations
+//! and downgrades unknown versions, see [VersionedClientLayer] and [DowngradePolicy]. Requires the
+//! `"client"` feature.
+
+use crate::{
+    DEPRECATION, SUNSET, X_API_SUPPORTED_VERSIONS, X_API_VERSION, X_API_VERSION_ERROR,
+    propagate::set_baggage, version, version_header_value, wire,
+};
+use axum::http::{Extensions, HeaderMap, HeaderName, Request, Response, StatusCode};
+use futures::future::BoxFuture;
+use httpdate::parse_http_date;
+use reqwest_middleware::{Middleware, Next, reqwest};
+use std::{
+    fmt::{self, Debug, Formatter},
+    future::{Ready, ready},
+    sync::Arc,
+    task::{Context, Poll},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tower::{
+    Layer, Service,
+    retry::{Policy, Retry, budget::Budget},
+};
+use tracing::debug;
+
+/// Tower layer for HTTP clients setting the `"x-api-version"` header to the requested version on
+/// outgoing requests; requests which already have it are passed on unchanged. Optionally,
+/// deprecations announced by the `"deprecation"` and `"sunset"` response headers are reported to
+/// a callback, and requests rejected because of an unknown version are retried once with the
+/// highest version supported by the server within a configured range via a [DowngradePolicy].
+/// Request bodies only need to be cloneable when downgrading, which turns the layer into a
+/// `VersionedClientLayer<Downgrade>`, the services of which wrap the inner ones into a retry.
+///
+/// This is also a `reqwest-middleware` [Middleware] doing the same for reqwest clients; requests
+/// with a body which cannot be cloned, i.e. a stream, are not retried, and on WebAssembly
+/// responses do not carry the [DowngradedVersion], as they have no extensions.
+///
+/// # Examples
+///
+/// ```
+/// # use api_version::client::VersionedClientLayer;
+/// # use axum::http::{Request, Response};
+/// # use std::convert::Infallible;
+/// # use tower::{Layer, service_fn};
+/// let client = VersionedClientLayer::new(3)
+///     .downgrade_to(1)
+///     .on_deprecation(|notice| eprintln!("API version v{} is deprecated", notice.version))
+///     .layer(service_fn(|request: Request<String>| async move {
+///         Ok::<_, Infallible>(Response::new(String::new()))
+///     }));
+/// ```
+///
+/// With reqwest:
+///
+/// ```
+/// # use api_version::client::VersionedClientLayer;
+/// # use reqwest_middleware::{ClientBuilder, reqwest::Client};
+/// let client = ClientBuilder::new(Client::new())
+///     .with(VersionedClientLayer::new(3).downgrade_to(1))
+///     .build();
+/// ```
+#[derive(Clone)]
+pub struct VersionedClientLayer<D = NoDowngrade> {
+    version: u16,
+    min_version: u16,
+    header: HeaderName,
+    baggage: bool,
+    on_deprecation: Option<Arc<dyn Fn(DeprecationNotice) + Send + Sync>>,
+    _downgrade: D,
+}
+
+/// Marker for a [VersionedClientLayer] which does not downgrade, hence does not need to clone
+/// request bodies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NoDowngrade;
+
+/// Marker for a [VersionedClientLayer] which downgrades, see [VersionedClientLayer::downgrade_to].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Downgrade;
+
+impl<D> Debug for VersionedClientLayer<D> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("VersionedClientLayer")
+            .field("version", &self.version)
+            .field("min_version", &self.min_version)
+            .field("header", &self.header)
+            .field("baggage", &self.baggage)
+            .field("on_deprecation", &self.on_deprecation.is_some())
+            .finish()
+    }
+}
+
+impl VersionedClientLayer {
+    /// Create a new client layer requesting the given version without downgrading.
+    pub fn new(version: u16) -> Self {
+        Self {
+            version,
+            min_version: version,
+            header: X_API_VERSION.clone(),
+            baggage: false,
+            on_deprecation: None,
+            _downgrade: NoDowngrade,
+        }
+    }
+
+    /// Retry requests rejected because of an unknown version with the highest version supported
+    /// by the server which is not lower than the given one, see [DowngradePolicy]. To combine
+    /// downgrading with a retry budget or other retry layers, use the [DowngradePolicy] with
+    /// `tower::retry` directly instead.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the given version is greater than the requested one.
+    pub fn downgrade_to(self, min_version: u16) -> VersionedClientLayer<Downgrade> {
+        assert!(
+            min_version <= self.version,
+            "minimum version must not be greater than the requested version"
+        );
+        VersionedClientLayer {
+            version: self.version,
+            min_version,
+            header: self.header,
+            baggage: self.baggage,
+            on_deprecation: self.on_deprecation,
+            _downgrade: Downgrade,
+        }
+    }
+}
+
+impl VersionedClientLayer<Downgrade> {
+    /// This layer without downgrading, for the [VersionedClient] wrapping the retrying service.
+    fn without_downgrade(&self) -> VersionedClientLayer {
+        VersionedClientLayer {
+            version: self.version,
+            min_version: self.version,
+            header: self.header.clone(),
+            baggage: self.baggage,
+            on_deprecation: self.on_deprecation.clone(),
+            _downgrade: NoDowngrade,
+        }
+    }
+}
+
+impl<D> VersionedClientLayer<D> {
+    /// Use the given header instead of `"x-api-version"`, e.g. if the server expects a different
+    /// one.
+    pub fn header(mut self, header: HeaderName) -> Self {
+        self.header = header;
+        self
+    }
+
+    /// Also set the requested version as W3C Baggage member, see [set_baggage]; disabled by
+    /// default.
+    pub fn baggage(mut self, baggage: bool) -> Self {
+        self.baggage = baggage;
+        self
+    }
+
+    /// Invoke the given callback for responses with a `"deprecation"` or `"sunset"` header, e.g.
+    /// to log a warning or record a metric.
+    pub fn on_deprecation(
+        mut self,
+        on_deprecation: impl Fn(DeprecationNotice) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_deprecation = Some(Arc::new(on_deprecation));
+        self
+    }
+}
+
+impl<S> Layer<S> for VersionedClientLayer {
+    type Service = VersionedClient<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        VersionedClient {
+            inner,
+            config: self.clone(),
+        }
+    }
+}
+
+impl<S> Layer<S> for VersionedClientLayer<Downgrade> {
+    type Service = VersionedClient<Retry<DowngradePolicy, S>>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        let policy = DowngradePolicy::new(self.min_version)
+            .header(self.header.clone())
+            .baggage(self.baggage);
+        VersionedClient {
+            inner: Retry::new(policy, inner),
+            config: self.without_downgrade(),
+        }
+    }
+}
+
+/// See [VersionedClientLayer].
+#[derive(Debug, Clone)]
+pub struct VersionedClient<S> {
+    inner: S,
+    config: VersionedClientLayer,
+}
+
+impl<S, B, C> Service<Request<B>> for VersionedClient<S>
+where
+    S: Service<Request<B>, Response = Response<C>>,
+    S::Future: Send + 'static,
+    C: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request<B>) -> Self::Future {
+        if request.headers().contains_key(&self.config.header) {
+            return Box::pin(self.inner.call(request));
+        }
+        request.headers_mut().insert(
+            self.config.header.clone(),
+            version_header_value(self.config.version),
+        );
+        if self.config.baggage {
+            set_baggage(request.headers_mut(), self.config.version);
+        }
+
+        let response = self.inner.call(request);
+        let config = self.config.clone();
+        Box::pin(async move {
+            let response = response.await?;
+
+            if let Some(on_deprecation) = &config.on_deprecation {
+                let version = response
+                    .extensions()
+                    .get::<DowngradedVersion>()
+                    .map_or(config.version, |DowngradedVersion(version)| *version);
+                if let Some(notice) = DeprecationNotice::from_headers(version, response.headers()) {
+                    on_deprecation(notice);
+                }
+            }
+
+            Ok(response)
+        })
+    }
+}
+
+#[cfg_attr(not(target_arch = "wasm32"), async_trait::async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait::async_trait(?Send))]
+impl<D> Middleware for VersionedClientLayer<D>
+where
+    D: Send + Sync + 'static,
+{
+    async fn handle(
+        &self,
+        mut request: reqwest::Request,
+        extensions: &mut Extensions,
+        next: Next<'_>,
+    ) -> reqwest_middleware::Result<reqwest::Response> {
+        if request.headers().contains_key(&self.header) {
+            return next.run(request, extensions).await;
+        }
+
+        let policy = DowngradePolicy::new(self.min_version).header(self.header.clone());
+        let mut version = self.version;
+        loop {
+            request
+                .headers_mut()
+                .insert(self.header.clone(), version_header_value(version));
+            if self.baggage {
+                set_baggage(request.headers_mut(), version);
+            }
+
+            // Every retry lowers the version, hence retrying stops at the minimum version.
+            let retry = if version > self.min_version {
+                request.try_clone()
+            } else {
+                None
+            };
+            #[cfg_attr(target_arch = "wasm32", allow(unused_mut))]
+            let mut response = next.clone().run(request, extensions).await?;
+
+            let downgraded =
+                policy.downgraded_version(version, response.status(), response.headers());
+            if let Some((downgraded, retry)) = downgraded.zip(retry) {
+                debug!(downgraded, "retrying with downgraded version");
+                version = downgraded;
+                request = retry;
+                continue;
+            }
+
+            // Responses of reqwest for WebAssembly have no extensions.
+            #[cfg(not(target_arch = "wasm32"))]
+            if version != self.version {
+                response.extensions_mut().insert(DowngradedVersion(version));
+            }
+            if let Some(on_deprecation) = &self.on_deprecation
+                && let Some(notice) = DeprecationNotice::from_headers(version, response.headers())
+            {
+                on_deprecation(notice);
+            }
+
+            return Ok(response);
+        }
+    }
+}
+
+/// Retry policy for `tower::retry` retrying requests rejected because of an unknown version, i.e.
+/// `404 Not Found` responses with the `"unknown-version"` reason, with the highest version
+/// supported by the server according to the `"x-api-supported-versions"` response header which
+/// is lower than the requested one, as given by the `"x-api-version"` request header, and not
+/// lower than a minimum version. As every retry lowers the version, retrying stops at the
+/// latest at the minimum version. Final responses to downgraded requests carry the
+/// [DowngradedVersion] as response extension.
+///
+/// Retried requests are rebuilt from the method, URI, HTTP version, headers and a clone of the
+/// body of the original request, i.e. without its extensions. Requests without version or
+/// with a version not greater than the minimum one are not cloned, hence never retried.
+///
+/// # Examples
+///
+/// ```
+/// # use api_version::client::{DowngradePolicy, VersionedClientLayer};
+/// # use axum::http::{Request, Response};
+/// # use std::{convert::Infallible, sync::Arc, time::Duration};
+/// # use tower::{
+/// #     ServiceBuilder, service_fn,
+/// #     retry::{RetryLayer, budget::TpsBudget},
+/// # };
+/// let budget = Arc::new(TpsBudget::new(Duration::from_secs(10), 10, 0.2));
+/// let client = ServiceBuilder::new()
+///     .layer(VersionedClientLayer::new(3))
+///     .layer(RetryLayer::new(DowngradePolicy::new(1).budget(budget)))
+///     .service(service_fn(|request: Request<String>| async move {
+///         Ok::<_, Infallible>(Response::new(String::new()))
+///     }));
+/// ```
+#[derive(Clone)]
+pub struct DowngradePolicy {
+    min_version: u16,
+    header: HeaderName,
+    baggage: bool,
+    budget: Option<Arc<dyn Budget + Send + Sync>>,
+    downgraded: Option<u16>,
+}
+
+impl Debug for DowngradePolicy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DowngradePolicy")
+            .field("min_version", &self.min_version)
+            .field("header", &self.header)
+            .field("baggage", &self.baggage)
+            .field("budget", &self.budget.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+impl DowngradePolicy {
+    /// Create a new downgrade policy not downgrading below the given version, without budget.
+    pub fn new(min_version: u16) -> Self {
+        Self {
+            min_version,
+            header: X_API_VERSION.clone(),
+            baggage: false,
+            budget: None,
+            downgraded: None,
+        }
+    }
+
+    /// Use the given header instead of `"x-api-version"`, see [VersionedClientLayer::header].
+    pub fn header(mut self, header: HeaderName) -> Self {
+        self.header = header;
+        self
+    }
+
+    /// Also update the W3C Baggage member of downgraded requests, see
+    /// [VersionedClientLayer::baggage]; disabled by default.
+    pub fn baggage(mut self, baggage: bool) -> Self {
+        self.baggage = baggage;
+        self
+    }
+
+    /// Only retry if the given budget, which can be shared with other retry policies, permits.
+    /// Every request not retried is deposited.
+    pub fn budget(mut self, budget: Arc<impl Budget + Send + Sync + 'static>) -> Self {
+        self.budget = Some(budget);
+        self
+    }
+
+    /// The requested version of the given request.
+    fn requested_version<B>(&self, request: &Request<B>) -> Option<u16> {
+        request
+            .headers()
+            .get(&self.header)?
+            .to_str()
+            .ok()
+            .and_then(version::parse)
+    }
+
+    /// The version to downgrade the given requested version to for a response with the given
+    /// status and headers, if rejected because of an unknown version and a suitable version is
+    /// supported by the server.
+    fn downgraded_version(
+        &self,
+        requested_version: u16,
+        status: StatusCode,
+        headers: &HeaderMap,
+    ) -> Option<u16> {
+        if status != StatusCode::NOT_FOUND
+            || headers.get(&X_API_VERSION_ERROR)? != wire::UNKNOWN_VERSION
+        {
+            return None;
+        }
+        headers
+            .get(&X_API_SUPPORTED_VERSIONS)?
+            .to_str()
+            .ok()?
+            .split(',')
+            .filter_map(|version| version::parse(version.trim()))
+            .filter(|version| (self.min_version..requested_version).contains(version))
+            .max()
+    }
+}
+
+impl<B, C, E> Policy<Request<B>, Response<C>, E> for DowngradePolicy
+where
+    B: Clone,
+{
+    type Future = Ready<()>;
+
+    fn retry(
+        &mut self,
+        request: &mut Request<B>,
+        result: &mut Result<Response<C>, E>,
+    ) -> Option<Self::Future> {
+        let response = result.as_mut().ok()?;
+        let downgraded = self.requested_version(request).and_then(|version| {
+            self.downgraded_version(version, response.status(), response.headers())
+        });
+
+        let Some(downgraded) = downgraded else {
+            if let Some(version) = self.downgraded {
+                response.extensions_mut().insert(DowngradedVersion(version));
+            }
+            if let Some(budget) = &self.budget {
+                budget.deposit();
+            }
+            return None;
+        };
+        if let Some(budget) = &self.budget
+            && !budget.withdraw()
+        {
+            debug!(
+                downgraded,
+                "not downgrading, because retry budget is exhausted"
+            );
+            return None;
+        }
+
+        debug!(downgraded, "retrying with downgraded version");
+        self.downgraded = Some(downgraded);
+        request
+            .headers_mut()
+            .insert(self.header.clone(), version_header_value(downgraded));
+        if self.baggage {
+            set_baggage(request.headers_mut(), downgraded);
+        }
+        Some(ready(()))
+    }
+
+    fn clone_request(&mut self, request: &Request<B>) -> Option<Request<B>> {
+        self.requested_version(request)
+            .filter(|&version| version > self.min_version)?;
+
+        let mut clone = Request::new(request.body().clone());
+        *clone.method_mut() = request.method().clone();
+        *clone.uri_mut() = request.uri().clone();
+        *clone.version_mut() = request.version();
+        *clone.headers_mut() = request.headers().clone();
+        Some(clone)
+    }
+}
+
+/// Response extension for the version a request has been downgraded to by a [DowngradePolicy].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DowngradedVersion(pub u16);
+
+/// Deprecation announced by a server, see [VersionedClientLayer::on_deprecation].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct DeprecationNotice {
+    /// The requested version, i.e. the downgraded one, if downgraded.
+    pub version: u16,
+
+    /// The time of the deprecation according to the `"deprecation"` header, if any.
+    pub deprecation: Option<SystemTime>,
+
+    /// The time of the sunset according to the `"sunset"` header, if any.
+    pub sunset: Option<SystemTime>,
+}
+
+impl DeprecationNotice {
+    /// Create a notice from the given response headers for the given version, if deprecated.
+    fn from_headers(version: u16, headers: &HeaderMap) -> Option<Self> {
+        let deprecation = headers
+            .get(&DEPRECATION)
+            .and_then(|deprecation| deprecation.to_str().ok())
+            .and_then(|deprecation| deprecation.strip_prefix('@'))
+            .and_then(|seconds| seconds.parse::<u64>().ok())
+            .map(|seconds| UNIX_EPOCH + Duration::from_secs(seconds));
+        let sunset = headers
+            .get(&SUNSET)
+            .and_then(|sunset| sunset.to_str().ok())
+            .and_then(|sunset| parse_http_date(sunset).ok());
+
+        (deprecation.is_some() || sunset.is_some()).then_some(Self {
+            version,
+            deprecation,
+            sunset,
+        })
+    }
+}
@@ -0,0 +1,201 @@
+//! Serializable snapshot of the configuration of an [ApiVersionLayer](crate::ApiVersionLayer),
+//! see [ApiVersionLayer::config](crate::ApiVersionLayer::config).
+
+use crate::{Config, DotSegmentPolicy, NegotiationStrategy, RedirectPolicy, RpcPolicy};
+use httpdate::fmt_http_date;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Snapshot of the declarative configuration of an [ApiVersionLayer](crate::ApiVersionLayer),
+/// e.g. to diff the running configuration against the desired one or to display it in admin
+/// tooling. Times are given as HTTP-dates, per-version maps are ordered by version. Hooks like
+/// [ApiVersionLayer::rate_limit_policy](crate::ApiVersionLayer::rate_limit_policy) and the live
+/// state, see [ApiVersionLayer::state_store](crate::ApiVersionLayer::state_store), are not
+/// included.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[non_exhaustive]
+pub struct ApiVersionConfig {
+    /// The base path, empty if none.
+    pub base_path: String,
+
+    /// The API versions.
+    pub versions: Vec<u16>,
+
+    /// The default version.
+    pub default_version: u16,
+
+    /// The scheduled default version changes as pairs of time and version, ordered by time.
+    pub default_schedule: Vec<(String, u16)>,
+
+    /// The internal versions.
+    pub internal_versions: Vec<u16>,
+
+    /// Whether the base path is stripped.
+    pub strip_base_path: bool,
+
+    /// Whether the path is rewritten.
+    pub rewrite_path: bool,
+
+    /// The rewrite template, if any.
+    pub rewrite_template: Option<String>,
+
+    /// The path aliases as pairs of path and target path by version.
+    pub path_aliases: BTreeMap<u16, Vec<(String, String)>>,
+
+    /// Whether strict mode is enabled.
+    pub strict: bool,
+
+    /// The maximum number of `"x-api-version"` header values.
+    pub max_version_header_values: usize,
+
+    /// Whether the resolved version is echoed in responses.
+    pub echo_version: bool,
+
+    /// The request header for the resolved version, if any.
+    pub version_request_header: Option<String>,
+
+    /// Whether the resolved version is set as W3C Baggage member.
+    pub version_baggage: bool,
+
+    /// The client key header, if any.
+    pub client_key_header: Option<String>,
+
+    /// Whether the `"prefer"` header is a version source.
+    pub prefer_header: bool,
+
+    /// The vendor for vendor media types, if any.
+    pub vendor: Option<String>,
+
+    /// The negotiation strategy, i.e. `"prefer_first"` or `"prefer_lowest_compatible"`.
+    pub negotiation_strategy: &'static str,
+
+    /// The skipped methods.
+    pub skipped_methods: Vec<String>,
+
+    /// The deprecations by version.
+    pub deprecations: BTreeMap<u16, String>,
+
+    /// The sunsets by version.
+    pub sunsets: BTreeMap<u16, String>,
+
+    /// Whether the countdown until the sunset is added to responses.
+    pub sunset_countdown: bool,
+
+    /// The maximum body sizes by version.
+    pub max_body_sizes: BTreeMap<u16, usize>,
+
+    /// The timeouts in milliseconds by version.
+    pub timeouts_ms: BTreeMap<u16, u64>,
+
+    /// The `"cache-control"` values by version.
+    pub cache_controls: BTreeMap<u16, String>,
+
+    /// Whether entity tags are versioned.
+    pub version_etags: bool,
+
+    /// The redirect policy, i.e. `"keep"`, `"strip"` or `"map"`.
+    pub redirect_policy: &'static str,
+
+    /// Whether the forwarded prefix is honored.
+    pub forwarded_prefix: bool,
+
+    /// The policy for gRPC-Web and Connect requests, i.e. `"version"`, `"skip"` or
+    /// `"metadata_only"`.
+    pub rpc_policy: &'static str,
+
+    /// The policy for request paths with dot segments, i.e. `"pass"`, `"normalize"` or
+    /// `"reject"`.
+    pub dot_segment_policy: &'static str,
+
+    /// Whether the debug endpoint is enabled.
+    pub debug_endpoint: bool,
+
+    /// Whether the discovery endpoint is enabled.
+    pub discovery_endpoint: bool,
+}
+
+impl<const N: usize> Config<N> {
+    /// See [ApiVersionLayer::config](crate::ApiVersionLayer::config).
+    pub(crate) fn snapshot(&self) -> ApiVersionConfig {
+        let header_name = |name: &axum::http::HeaderName| name.as_str().to_owned();
+
+        ApiVersionConfig {
+            base_path: self.base_path.clone(),
+            versions: self.versions.to_vec(),
+            default_version: self.default_version,
+            default_schedule: self
+                .default_schedule
+                .iter()
+                .map(|&(at, version)| (fmt_http_date(at), version))
+                .collect(),
+            internal_versions: self.internal_versions.clone(),
+            strip_base_path: self.strip_base_path,
+            rewrite_path: self.rewrite_path,
+            rewrite_template: self
+                .rewrite_template
+                .as_ref()
+                .map(|template| template.as_str().to_owned()),
+            path_aliases: self.path_aliases.clone().into_iter().collect(),
+            strict: self.strict,
+            max_version_header_values: self.max_version_header_values,
+            echo_version: self.echo_version,
+            version_request_header: self.version_request_header.as_ref().map(header_name),
+            version_baggage: self.version_baggage,
+            client_key_header: self.client_key_header.as_ref().map(header_name),
+            prefer_header: self.prefer_header,
+            vendor: self.vendor.clone(),
+            negotiation_strategy: match self.negotiation_strategy {
+                NegotiationStrategy::PreferFirst => "prefer_first",
+                NegotiationStrategy::PreferLowestCompatible => "prefer_lowest_compatible",
+            },
+            skipped_methods: self
+                .skipped_methods
+                .iter()
+                .map(ToString::to_string)
+                .collect(),
+            deprecations: self
+                .deprecations
+                .iter()
+                .map(|(&version, &at)| (version, fmt_http_date(at)))
+                .collect(),
+            sunsets: self
+                .sunsets
+                .iter()
+                .map(|(&version, &at)| (version, fmt_http_date(at)))
+                .collect(),
+            sunset_countdown: self.sunset_countdown,
+            max_body_sizes: self.max_body_sizes.clone().into_iter().collect(),
+            timeouts_ms: self
+                .timeouts
+                .iter()
+                .map(|(&version, timeout)| (version, timeout.as_millis() as u64))
+                .collect(),
+            cache_controls: self
+                .cache_controls
+                .iter()
+                .filter_map(|(&version, value)| {
+                    value.to_str().ok().map(|value| (version, value.to_owned()))
+                })
+                .collect(),
+            version_etags: self.version_etags,
+            redirect_policy: match self.redirect_policy {
+                RedirectPolicy::Keep => "keep",
+                RedirectPolicy::Strip => "strip",
+                RedirectPolicy::Map(_) => "map",
+            },
+            forwarded_prefix: self.forwarded_prefix,
+            rpc_policy: match self.rpc_policy {
+                RpcPolicy::Version => "version",
+                RpcPolicy::Skip => "skip",
+                RpcPolicy::MetadataOnly => "metadata_only",
+            },
+            dot_segment_policy: match self.dot_segment_policy {
+                DotSegmentPolicy::Pass => "pass",
+                DotSegmentPolicy::Normalize => "normalize",
+                DotSegmentPolicy::Reject => "reject",
+            },
+            debug_endpoint: self.debug_endpoint,
+            discovery_endpoint: self.discovery_endpoint,
+        }
+    }
+}
@@ -0,0 +1,155 @@
+//! Minimal CORS responder with policies per version, e.g. to keep the permissive contract of an
+//! old version while locking down a newer one, see [CorsPolicy] and
+//! [ApiVersionLayer::cors_policy](crate::ApiVersionLayer::cors_policy).
+
+use crate::{DEPRECATION, SUNSET, X_API_VERSION};
+use axum::{
+    body::Body,
+    http::{
+        HeaderMap, HeaderName, HeaderValue, Method, StatusCode,
+        header::{
+            ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS,
+            ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_EXPOSE_HEADERS, ACCESS_CONTROL_MAX_AGE,
+            ACCESS_CONTROL_REQUEST_HEADERS, ACCESS_CONTROL_REQUEST_METHOD, ORIGIN, VARY,
+        },
+    },
+    response::Response,
+};
+use std::time::Duration;
+
+/// CORS policy for a version, see
+/// [ApiVersionLayer::cors_policy](crate::ApiVersionLayer::cors_policy). The `"x-api-version"`
+/// request header is always allowed and the version response headers, e.g. `"deprecation"`, are
+/// always exposed. Credentials are not supported.
+///
+/// # Examples
+///
+/// ```
+/// # use api_version::{ApiVersionLayer, ApiVersions, cors::CorsPolicy};
+/// # use axum::http::{HeaderValue, Method};
+/// const API_VERSIONS: ApiVersions<2> = ApiVersions::new([0, 1]);
+///
+/// let layer = ApiVersionLayer::new("/api", API_VERSIONS)
+///     .cors_policy(0, CorsPolicy::any_origin())
+///     .cors_policy(
+///         1,
+///         CorsPolicy::allowed_origins([HeaderValue::from_static("https://app.example.com")])
+///             .allowed_methods([Method::GET, Method::PUT]),
+///     );
+/// ```
+#[derive(Debug, Clone)]
+pub struct CorsPolicy {
+    allowed_origins: Option<Vec<HeaderValue>>,
+    allowed_methods: HeaderValue,
+    allowed_headers: HeaderValue,
+    max_age: Option<Duration>,
+}
+
+impl CorsPolicy {
+    /// Create a policy allowing any origin, i.e. responding with
+    /// `"access-control-allow-origin: *"`, and the methods `GET`, `HEAD` and `POST`.
+    pub fn any_origin() -> Self {
+        Self {
+            allowed_origins: None,
+            allowed_methods: HeaderValue::from_static("GET, HEAD, POST"),
+            allowed_headers: HeaderValue::from_name(X_API_VERSION.clone()),
+            max_age: None,
+        }
+    }
+
+    /// Create a policy allowing the given origins, e.g. `"https://app.example.com"`, and the
+    /// methods `GET`, `HEAD` and `POST`.
+    pub fn allowed_origins(origins: impl IntoIterator<Item = HeaderValue>) -> Self {
+        Self {
+            allowed_origins: Some(origins.into_iter().collect()),
+            ..Self::any_origin()
+        }
+    }
+
+    /// Allow the given methods instead of `GET`, `HEAD` and `POST`.
+    pub fn allowed_methods(mut self, methods: impl IntoIterator<Item = Method>) -> Self {
+        let methods = methods
+            .into_iter()
+            .map(|method| method.to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        self.allowed_methods =
+            HeaderValue::from_str(&methods).expect("methods are a valid header value");
+        self
+    }
+
+    /// Allow the given request headers in addition to `"x-api-version"`.
+    pub fn allowed_headers(mut self, names: impl IntoIterator<Item = HeaderName>) -> Self {
+        let names = [X_API_VERSION.clone()]
+            .into_iter()
+            .chain(names)
+            .map(|name| name.to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        self.allowed_headers =
+            HeaderValue::from_str(&names).expect("header names are a valid header value");
+        self
+    }
+
+    /// Let clients cache preflight responses for the given duration.
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// The `"access-control-allow-origin"` value for the given origin, if allowed.
+    fn allowed_origin(&self, origin: &HeaderValue) -> Option<HeaderValue> {
+        match &self.allowed_origins {
+            None => Some(HeaderValue::from_static("*")),
+            Some(origins) => origins.contains(origin).then(|| origin.to_owned()),
+        }
+    }
+
+    /// Respond to the given preflight request: with the CORS headers if the origin is allowed,
+    /// else without them, which makes browsers fail the actual request.
+    pub(crate) fn preflight(&self, headers: &HeaderMap) -> Response {
+        let mut response = Response::new(Body::empty());
+        *response.status_mut() = StatusCode::NO_CONTENT;
+        let response_headers = response.headers_mut();
+        for name in [
+            ORIGIN,
+            ACCESS_CONTROL_REQUEST_METHOD,
+            ACCESS_CONTROL_REQUEST_HEADERS,
+        ] {
+            response_headers.append(VARY, HeaderValue::from_name(name));
+        }
+
+        if let Some(origin) = headers.get(ORIGIN).and_then(|o| self.allowed_origin(o)) {
+            response_headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+            response_headers.insert(ACCESS_CONTROL_ALLOW_METHODS, self.allowed_methods.clone());
+            response_headers.insert(ACCESS_CONTROL_ALLOW_HEADERS, self.allowed_headers.clone());
+            if let Some(max_age) = self.max_age {
+                response_headers.insert(ACCESS_CONTROL_MAX_AGE, max_age.as_secs().into());
+            }
+        }
+
+        response
+    }
+
+    /// Add the CORS headers to the given response for a request with the given origin, if
+    /// allowed.
+    pub(crate) fn add_headers(&self, origin: &HeaderValue, response: &mut Response) {
+        let headers = response.headers_mut();
+        headers.append(VARY, HeaderValue::from_name(ORIGIN));
+        if let Some(origin) = self.allowed_origin(origin) {
+            headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+            headers.insert(
+                ACCESS_CONTROL_EXPOSE_HEADERS,
+                HeaderValue::from_str(&format!("{X_API_VERSION}, {DEPRECATION}, {SUNSET}"))
+                    .expect("header names are a valid header value"),
+            );
+        }
+    }
+}
+
+/// Whether the given request is a CORS preflight request.
+pub(crate) fn is_preflight(method: &Method, headers: &HeaderMap) -> bool {
+    method == Method::OPTIONS
+        && headers.contains_key(ORIGIN)
+        && headers.contains_key(ACCESS_CONTROL_REQUEST_METHOD)
+}
@@ -0,0 +1,124 @@
+//! Coverage of routes across API versions, e.g. to enforce in tests that every route of a version
+//! still exists in the next one unless explicitly waived, see [RouteCoverage].
+
+use crate::{ApiVersionLayer, version};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::{self, Display, Formatter},
+};
+
+/// Matrix of routes and the API versions they exist in, created via
+/// [ApiVersionLayer::route_coverage]. Routes are given without base path and version prefix, e.g.
+/// `"/users/{id}"`. The [Display] implementation gives a table with a row per route.
+///
+/// # Examples
+///
+/// ```
+/// # use api_version::{ApiVersionLayer, ApiVersions};
+/// const API_VERSIONS: ApiVersions<2> = ApiVersions::new([1, 2]);
+/// const WAIVED: [(&str, u16); 1] = [("/users", 2)];
+///
+/// let layer = ApiVersionLayer::new("/api", API_VERSIONS);
+/// let coverage = layer.route_coverage([
+///     "/api/v1/users",
+///     "/api/v1/orders",
+///     "/api/v2/accounts",
+///     "/api/v2/orders",
+/// ]);
+///
+/// let gaps = coverage
+///     .gaps()
+///     .into_iter()
+///     .filter(|gap| !WAIVED.contains(gap))
+///     .collect::<Vec<_>>();
+/// assert!(gaps.is_empty(), "routes missing in versions: {gaps:?}\n{coverage}");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RouteCoverage {
+    versions: Vec<u16>,
+    routes: BTreeMap<String, BTreeSet<u16>>,
+}
+
+impl RouteCoverage {
+    /// The API versions.
+    pub fn versions(&self) -> &[u16] {
+        &self.versions
+    }
+
+    /// The routes with the versions they exist in, ordered by route.
+    pub fn routes(&self) -> impl Iterator<Item = (&str, &BTreeSet<u16>)> {
+        self.routes
+            .iter()
+            .map(|(route, versions)| (route.as_str(), versions))
+    }
+
+    /// Check whether the given route exists in the given version.
+    pub fn contains(&self, route: &str, version: u16) -> bool {
+        self.routes
+            .get(route)
+            .is_some_and(|versions| versions.contains(&version))
+    }
+
+    /// The gaps as pairs of route and version, i.e. routes which exist in a version, but not in
+    /// the next one, ordered by route.
+    pub fn gaps(&self) -> Vec<(&str, u16)> {
+        self.routes()
+            .flat_map(|(route, versions)| {
+                self.versions
+                    .windows(2)
+                    .filter(|pair| versions.contains(&pair[0]) && !versions.contains(&pair[1]))
+                    .map(move |pair| (route, pair[1]))
+            })
+            .collect()
+    }
+}
+
+impl Display for RouteCoverage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let width = self
+            .routes
+            .keys()
+            .map(String::len)
+            .fold("route".len(), usize::max);
+
+        write!(f, "{:width$}", "route")?;
+        for version in &self.versions {
+            write!(f, " {:>5}", format!("v{version}"))?;
+        }
+        for (route, versions) in &self.routes {
+            write!(f, "\n{route:width$}")?;
+            for version in &self.versions {
+                let mark = if versions.contains(version) { "x" } else { "-" };
+                write!(f, " {mark:>5}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<const N: usize> ApiVersionLayer<N> {
+    /// Create the [RouteCoverage] for the given route paths, e.g. the ones registered with the
+    /// inner router, like for [ApiVersionLayer::validate_routes]. Routes not under the base path
+    /// followed by the version prefix of one of the API versions are ignored.
+    pub fn route_coverage<'a>(&self, routes: impl IntoIterator<Item = &'a str>) -> RouteCoverage {
+        let mut coverage = BTreeMap::<_, BTreeSet<_>>::new();
+
+        for route in routes {
+            let Some(path) = route.strip_prefix(&self.config.base_path) else {
+                continue;
+            };
+            let (Some(version), path) = version::strip_version(path) else {
+                continue;
+            };
+            if self.config.versions.contains(&version) {
+                coverage.entry(path.to_owned()).or_default().insert(version);
+            }
+        }
+
+        RouteCoverage {
+            versions: self.config.versions.to_vec(),
+            routes: coverage,
+        }
+    }
+}
@@ -1,35 +1,98 @@
 //! Axum middleware to rewrite a request such that a version prefix, e.g. `"/v0"`, is added to the
 //! path.
+//!
+//! The version semantics and the wire format, see [version] and [wire], are re-exported from the
+//! `api-version-types` crate, which client SDKs can depend on without pulling in axum or tower.
+//!
+//! # Features
+//!
+//! - `"chaos"`: failure injection for resilience testing, see `ApiVersionLayer::chaos`.
+//! - `"client"`: client-side version negotiation for HTTP clients based on tower or on reqwest via
+//!   `reqwest-middleware`, see the `client` module.
+//! - `"dev-tools"`: detection of breaking changes between OpenAPI documents, see the `openapi`
+//!   module, also as `api-version-openapi-diff` binary.
+//! - `"json-transform"` (default): declarative per-version transformations of JSON bodies, see
+//!   [transform].
+//! - `"tokio"` (default): time-based policies, i.e. [ApiVersionLayer::timeout] and
+//!   [RateLimitDecision::Slowdown], which need the Tokio timer, [current_version], which needs a
+//!   Tokio task-local, as well as [state::InMemoryVersionStateStore::subscribe], which needs a
+//!   Tokio channel. Disable it together with axum's default features, e.g. for WebAssembly/edge
+//!   runtimes, which are checked with `wasm32-unknown-unknown` and which may need inner services
+//!   which are not `Send`, see [ApiVersionLayer::local].
+//! - `"watch-config"`: reloading the live versioning state from a file, see
+//!   `ApiVersionLayer::watch_config`.
 
+#[cfg(feature = "tokio")]
+pub mod audit;
+pub mod capability;
+#[cfg(feature = "chaos")]
+pub mod chaos;
+#[cfg(feature = "client")]
+pub mod client;
+mod config;
+pub mod cors;
+pub mod coverage;
+pub mod manifest;
+pub mod negotiation;
+#[cfg(feature = "dev-tools")]
+pub mod openapi;
+pub mod policy;
+pub mod propagate;
+pub mod report;
+pub mod serialize;
+pub mod state;
+mod template;
+pub mod testing;
+#[cfg(feature = "json-transform")]
+pub mod transform;
+
+pub use api_version_types::{ApiVersions, strip_version, version, wire};
+pub use config::ApiVersionConfig;
+
+use crate::negotiation::NegotiationInput;
 use axum::{
-    RequestExt,
-    extract::Request,
-    http::{HeaderName, HeaderValue, StatusCode, Uri, uri::PathAndQuery},
-    response::{IntoResponse, Response},
+    body::{Body, HttpBody, to_bytes},
+    extract::{FromRequestParts, Request, State},
+    http::{
+        Extensions, HeaderMap, HeaderName, HeaderValue, Method, StatusCode, Uri,
+        header::{
+            ACCEPT, ALLOW, CACHE_CONTROL, CONTENT_LENGTH, CONTENT_TYPE, ETAG, IF_MATCH,
+            IF_NONE_MATCH, LINK, LOCATION, ORIGIN, VARY,
+        },
+        request::Parts,
+        uri::PathAndQuery,
+    },
+    middleware::Next,
+    response::{Html, IntoResponse, Response},
 };
-use axum_extra::{
-    TypedHeader,
-    headers::{self, Header},
-};
-use futures::future::BoxFuture;
-use regex::Regex;
+use axum_extra::headers::{self, Header};
+use futures::future::{BoxFuture, LocalBoxFuture};
+use http_body_util::Limited;
+use httpdate::fmt_http_date;
+use serde::{Deserialize, Serialize};
+use smallvec::SmallVec;
 use std::{
-    fmt::Debug,
-    ops::Deref,
-    sync::LazyLock,
+    collections::{BTreeMap, HashMap, HashSet},
+    convert::Infallible,
+    error::Error,
+    fmt::{self, Debug, Display, Formatter},
+    io, mem,
+    sync::{
+        Arc, Mutex, PoisonError, RwLock,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
     task::{Context, Poll},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
-use tower::{Layer, Service};
-use tracing::debug;
-
-static VERSION: LazyLock<Regex> =
-    LazyLock::new(|| Regex::new(r#"^v(\d{1,4})$"#).expect("version regex is valid"));
+use tower::{Layer, Service, ServiceExt};
+use tracing::{debug, warn};
 
 /// Axum middleware to rewrite a request such that a version prefix is added to the path. This is
 /// based on a set of API versions and an optional `"x-api-version"` custom HTTP header: if no such
-/// header is present, the highest version is used. Yet this only applies to requests the URIs of
-/// which start with the given base path, e.g. "/api"; others are not rewritten.  Also, paths
-/// starting with a valid/existing version prefix, e.g. `"/api/v0"`, are not rewritten.
+/// header is present, the default version, i.e. by default the highest version, is used. Yet this
+/// only applies to requests the URIs of which start with the given base path, e.g. "/api"; others
+/// are not rewritten, unless there is no base path, see [ApiVersionLayer::without_base_path].
+/// Also, paths starting with a valid/existing version prefix, e.g. `"/api/v0"`, are not rewritten.
 ///
 /// # Examples
 ///
@@ -45,284 +108,4739 @@ static VERSION: LazyLock<Regex> =
 ///
 /// let mut app = ApiVersionLayer::new("/api", API_VERSIONS).layer(app);
 /// ```
-#[derive(Clone)]
+#[derive(Debug, Clone)]
 pub struct ApiVersionLayer<const N: usize> {
-    base_path: String,
-    versions: ApiVersions<N>,
+    config: Config<N>,
 }
 
 impl<const N: usize> ApiVersionLayer<N> {
-    /// Create a new API version layer with the given base path and api versions.
+    /// Create a new API version layer with the given base path and api versions. The base path
+    /// `"/"` means no base path, see [ApiVersionLayer::without_base_path].
     ///
     /// # Panics
     ///
-    /// Panics if base path does not start with "/" or is empty.
+    /// Panics if base path does not start with "/".
     pub fn new(base_path: impl AsRef<str>, versions: ApiVersions<N>) -> Self {
-        let base_path = base_path.as_ref().trim_end_matches('/').to_string();
+        let base_path = base_path.as_ref();
         assert!(base_path.starts_with('/'), "base path must start with '/'");
-        assert!(!base_path.len() > 1, "base path must not be empty");
+        let base_path = base_path.trim_end_matches('/').to_string();
 
         Self {
-            base_path,
-            versions,
+            config: Config {
+                base_path,
+                versions,
+                strip_base_path: false,
+                rewrite_path: true,
+                rewrite_template: None,
+                path_aliases: HashMap::new(),
+                echo_version: false,
+                version_request_header: None,
+                version_baggage: false,
+                skip_streaming_responses: false,
+                max_body_sizes: HashMap::new(),
+                timeouts: HashMap::new(),
+                client_key_header: None,
+                rate_limit_policy: None,
+                cache_controls: HashMap::new(),
+                cors_policies: HashMap::new(),
+                version_etags: false,
+                redirect_policy: RedirectPolicy::Keep,
+                forwarded_prefix: false,
+                deprecations: HashMap::new(),
+                sunsets: HashMap::new(),
+                sunset_countdown: false,
+                clock: Arc::new(SystemClock),
+                default_version: *versions.last().expect("versions is not empty"),
+                default_version_advisory: None,
+                default_schedule: vec![],
+                default_ramp: None,
+                state_store: None,
+                default_experiment: None,
+                version_health: None,
+                drain: None,
+                #[cfg(feature = "tokio")]
+                concurrency_limits: None,
+                #[cfg(feature = "tokio")]
+                deprecation_budget: None,
+                #[cfg(feature = "tokio")]
+                auditor: None,
+                internal_versions: vec![],
+                internal_access_header: None,
+                error_message: None,
+                skipped_methods: vec![],
+                rpc_policy: RpcPolicy::Version,
+                dot_segment_policy: DotSegmentPolicy::Normalize,
+                strict: false,
+                max_version_header_values: 8,
+                version_states: vec![],
+                prefer_header: false,
+                vendor: None,
+                negotiation_strategy: NegotiationStrategy::PreferFirst,
+                #[cfg(feature = "json-transform")]
+                request_transforms: HashMap::new(),
+                #[cfg(feature = "json-transform")]
+                response_transforms: HashMap::new(),
+                #[cfg(feature = "json-transform")]
+                transform_streaming_requests: true,
+                map_response: None,
+                debug_endpoint: false,
+                #[cfg(feature = "chaos")]
+                chaos: None,
+                discovery_endpoint: false,
+                html_index: None,
+                serializers: vec![],
+                changelogs: HashMap::new(),
+                capabilities: vec![],
+                migration_guides: vec![],
+                manifest_metadata: serde_json::Map::new(),
+                policies: policy::PolicyLink::defaults(),
+            },
         }
     }
-}
 
-impl<const N: usize, S> Layer<S> for ApiVersionLayer<N> {
-    type Service = ApiVersionService<N, S>;
+    /// Create a new API version layer for a service nested under the given nest path, e.g. via
+    /// `Router::nest_service`. As nesting strips the nest path from the request URI before it
+    /// reaches the nested service, the effective base path is the given base path relative to
+    /// the nest path, e.g. `"/api"` for the nest path `"/public"` and the base path
+    /// `"/public/api"`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use api_version::{ApiVersionLayer, ApiVersions};
+    /// # use axum::{Router, routing::get};
+    /// # use tower::Layer;
+    /// const API_VERSIONS: ApiVersions<2> = ApiVersions::new([0, 1]);
+    ///
+    /// let api = Router::new()
+    ///     .route("/api/v0/test", get(|| async { "0" }))
+    ///     .route("/api/v1/test", get(|| async { "1" }));
+    /// let api = ApiVersionLayer::nested("/public", "/public/api", API_VERSIONS).layer(api);
+    ///
+    /// let app = Router::<()>::new().nest_service("/public", api);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if the base path does not start with the nest path or if the effective base path is
+    /// invalid according to [ApiVersionLayer::new].
+    pub fn nested(
+        nest_path: impl AsRef<str>,
+        base_path: impl AsRef<str>,
+        versions: ApiVersions<N>,
+    ) -> Self {
+        let nest_path = nest_path.as_ref().trim_end_matches('/');
+        let base_path = base_path
+            .as_ref()
+            .trim_end_matches('/')
+            .strip_prefix(nest_path)
+            .filter(|base_path| base_path.is_empty() || base_path.starts_with('/'))
+            .expect("base path must start with nest path");
 
-    fn layer(&self, inner: S) -> Self::Service {
-        ApiVersionService {
-            inner,
-            base_path: self.base_path.clone(),
-            versions: self.versions,
-        }
+        Self::new(format!("/{}", base_path.trim_start_matches('/')), versions)
     }
-}
 
-/// API versions; a validated newtype for a `u16` array.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub struct ApiVersions<const N: usize>([u16; N]);
+    /// Create a new API version layer without base path, i.e. versioning every path, e.g.
+    /// rewriting `"/test"` to `"/v1/test"`, for services deployed behind a path-stripping
+    /// gateway. This is the same as [ApiVersionLayer::new] with the base path `"/"`.
+    pub fn without_base_path(versions: ApiVersions<N>) -> Self {
+        Self::new("/", versions)
+    }
+
+    /// Strip the base path when rewriting, i.e. rewrite `"/api/test"` to `"/v1/test"` instead of
+    /// `"/api/v1/test"`; paths already starting with a valid version prefix, e.g.
+    /// `"/api/v0/test"`, are rewritten to `"/v0/test"`. This allows for inner routers which are
+    /// written without the base path, e.g. because they are also mounted elsewhere.
+    pub fn strip_base_path(mut self, strip_base_path: bool) -> Self {
+        self.config.strip_base_path = strip_base_path;
+        self
+    }
+
+    /// Rewrite the path, which is the default. If disabled, the version is only resolved,
+    /// validated and stored in the request extensions as [ResolvedApiVersion], leaving the URI
+    /// untouched, e.g. to dispatch to different handlers based on the version. In this case
+    /// requests for the base path itself, e.g. `"/api/graphql"`, are versioned, too.
+    pub fn rewrite_path(mut self, rewrite_path: bool) -> Self {
+        self.config.rewrite_path = rewrite_path;
+        self
+    }
 
-impl<const N: usize> ApiVersions<N> {
-    /// Create API versions. The given numbers must not be empty, must be strictly monotonically
-    /// increasing and less than `10_000`; otherwise `new` fails to compile in const contexts or
-    /// panics otherwise.
+    /// Rewrite paths according to the given template instead of inserting the version prefix
+    /// after the base path, i.e. `"{base}/v{version}{path}"`, e.g. for inner routers organized as
+    /// `"/api/{resource}/v{N}"`. The placeholders are `{base}` for the base path, which is empty if
+    /// stripped, see [ApiVersionLayer::strip_base_path], `{version}` for the version number,
+    /// `{path}` for the remaining path, and `{resource}` and `{rest}` for its first segment and
+    /// the rest, e.g. `"{base}/{resource}/v{version}{rest}"` rewrites `"/api/users/42"` to
+    /// `"/api/users/v1/42"` and `"/api/"` to `"/api/v1"`, i.e. an empty resource does not leave
+    /// an empty segment. Paths starting with a valid version prefix, e.g. `"/api/v0/users"`, are
+    /// rewritten, too.
     ///
-    /// # Examples
+    /// # Panics
     ///
-    /// Strictly monotonically versions `1` and `2` are valid:
+    /// Panics if the template does not start with `"/"` or `"{base}/"`, contains unknown
+    /// placeholders, `'?'`, `'#'` or non-graphic characters, or does not contain `{version}` and
+    /// either `{path}` or `{resource}` and `{rest}` exactly once.
+    pub fn rewrite_template(mut self, template: &str) -> Self {
+        let template = template::RewriteTemplate::parse(template)
+            .unwrap_or_else(|error| panic!("invalid rewrite template '{template}': {error}"));
+        self.config.rewrite_template = Some(template);
+        self
+    }
+
+    /// Alias the given path to the given target path for the given version, e.g. `"/users"` to
+    /// `"/accounts"` for `v2`, such that `"/api/users"` is rewritten to `"/api/v2/accounts"` for
+    /// `v2` and clients keep working across renamed or moved routes. Paths are given without base
+    /// path and version prefix and match whole segments, i.e. `"/users/42"` is aliased to
+    /// `"/accounts/42"`, but `"/usersettings"` is not aliased. If several aliases match, the one
+    /// added first is applied. Requests with a valid version prefix are rewritten, too.
     ///
-    /// ```
-    /// # use api_version::ApiVersions;
-    /// const VERSIONS: ApiVersions<2> = ApiVersions::new([1, 2]);;
-    /// ```
+    /// # Panics
+    ///
+    /// Panics if the given version is not one of the API versions or if a path does not start
+    /// with "/".
+    pub fn path_alias(
+        mut self,
+        version: u16,
+        path: impl Into<String>,
+        target: impl Into<String>,
+    ) -> Self {
+        assert!(
+            self.config.versions.contains(&version),
+            "version must be one of the API versions"
+        );
+        let (path, target) = (path.into(), target.into());
+        assert!(
+            path.starts_with('/') && target.starts_with('/'),
+            "path alias must start with '/'"
+        );
+
+        let alias = (
+            path.trim_end_matches('/').to_owned(),
+            target.trim_end_matches('/').to_owned(),
+        );
+        self.config
+            .path_aliases
+            .entry(version)
+            .or_default()
+            .push(alias);
+        self
+    }
+
+    /// Echo the resolved version in the `"x-api-version"` response header and add
+    /// `"x-api-version"` to the `"vary"` response header; disabled by default. This applies to all
+    /// responses for requests with a resolved version, including error responses created by the
+    /// inner service.
+    pub fn echo_version(mut self, echo_version: bool) -> Self {
+        self.config.echo_version = echo_version;
+        self
+    }
+
+    /// Insert the resolved version, e.g. `"v1"`, into the request headers under the given name,
+    /// e.g. for a GraphQL endpoint to put it into the GraphQL context. Together with disabling
+    /// [ApiVersionLayer::rewrite_path], this allows GraphQL and REST endpoints to share one
+    /// version policy.
+    ///
+    /// With [X_API_VERSION] as name, the `"x-api-version"` request header is normalized, i.e.
+    /// always present with exactly the resolved version, also if the version has been defaulted,
+    /// taken from the path prefix or requested as `"latest"`, such that downstream middleware and
+    /// proxied upstreams can rely on it.
+    pub fn version_request_header(mut self, name: HeaderName) -> Self {
+        self.config.version_request_header = Some(name);
+        self
+    }
+
+    /// Set the resolved version as W3C Baggage member, e.g. `"baggage: api.version=2"`, on the
+    /// rewritten request, keeping all other members, such that downstream services and collectors
+    /// see the version without custom headers; disabled by default. See
+    /// [propagate::PropagateVersionLayer::baggage] for outgoing requests.
+    pub fn version_baggage(mut self, version_baggage: bool) -> Self {
+        self.config.version_baggage = version_baggage;
+        self
+    }
+
+    /// Do not add any headers to streaming responses, i.e. such with a `"text/event-stream"`
+    /// content type or a body without an exact size; disabled by default. Response headers are
+    /// always added before the body starts streaming, but some streaming protocols, e.g. such
+    /// relying on trailers, must not be touched at all.
+    pub fn skip_streaming_responses(mut self, skip_streaming_responses: bool) -> Self {
+        self.config.skip_streaming_responses = skip_streaming_responses;
+        self
+    }
+
+    /// Limit the request body size for the given version to the given number of bytes. Requests
+    /// with a larger `"content-length"` are rejected with `413 Payload Too Large`, and so are
+    /// requests the body of which exceeds the limit when extracted by the handler.
     ///
     /// # Panics
     ///
-    /// Empty versions or such that are not strictly monotonically increasing are invalid and fail
-    /// to compile in const contexts or panic otherwise.
+    /// Panics if the given version is not one of the API versions.
+    pub fn max_body_size(mut self, version: u16, max_body_size: usize) -> Self {
+        assert!(
+            self.config.versions.contains(&version),
+            "version must be one of the API versions"
+        );
+        self.config.max_body_sizes.insert(version, max_body_size);
+        self
+    }
+
+    /// Limit the time for processing requests for the given version to the given duration.
+    /// Requests taking longer are aborted with `503 Service Unavailable`, as the server, not the
+    /// client, was too slow. Requires the `"tokio"` feature.
     ///
-    /// ```compile_fail
-    /// # use api_version::ApiVersions;
-    /// /// API versions must not be empty!
-    /// const VERSIONS: ApiVersions<0> = ApiVersions::new([]);
-    /// /// API versions must be strictly monotonically increasing!
-    /// const VERSIONS: ApiVersions<0> = ApiVersions::new([2, 1]);
-    /// /// API versions must be within 0u16..10_000!
-    /// const VERSIONS: ApiVersions<0> = ApiVersions::new([10_000]);
-    /// ```
-    pub const fn new(versions: [u16; N]) -> Self {
-        assert!(!versions.is_empty(), "API versions must not be empty");
+    /// # Panics
+    ///
+    /// Panics if the given version is not one of the API versions.
+    #[cfg(feature = "tokio")]
+    pub fn timeout(mut self, version: u16, timeout: Duration) -> Self {
         assert!(
-            is_monotonically_increasing(versions),
-            "API versions must be strictly monotonically increasing"
+            self.config.versions.contains(&version),
+            "version must be one of the API versions"
         );
+        self.config.timeouts.insert(version, timeout);
+        self
+    }
+
+    /// Use the value of the given request header as client key, e.g. for rate limiting or for
+    /// ramping the default version. The header should be set by a trusted proxy, as clients can
+    /// send arbitrary values.
+    pub fn client_key_header(mut self, name: HeaderName) -> Self {
+        self.config.client_key_header = Some(name);
+        self
+    }
+
+    /// Apply the given rate limit policy, which is invoked with the client key, see
+    /// [ApiVersionLayer::client_key_header], and the resolved version, e.g. to throttle old
+    /// versions harder as part of sunset pressure. Denied requests are rejected with
+    /// `429 Too Many Requests`.
+    pub fn rate_limit_policy(mut self, rate_limit_policy: impl RateLimitPolicy) -> Self {
+        self.config.rate_limit_policy = Some(Arc::new(rate_limit_policy));
+        self
+    }
+
+    /// Record every negotiation decision, i.e. the client key, see
+    /// [ApiVersionLayer::client_key_header], and the outcome including the resolved version and
+    /// its source, with the given sink. The sink is invoked off the hot path in a background task
+    /// via a buffer for [audit::AUDIT_BUFFER_SIZE] records; records are dropped with a warning
+    /// while the buffer is full. Requires the `"tokio"` feature.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a Tokio runtime.
+    #[cfg(feature = "tokio")]
+    pub fn audit_sink(mut self, audit_sink: impl audit::AuditSink) -> Self {
+        self.config.auditor = Some(audit::Auditor::spawn(audit_sink));
+        self
+    }
+
+    /// Set the `"cache-control"` response header for the given version to the given value,
+    /// overriding any value set by the handlers.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the given version is not one of the API versions.
+    pub fn cache_control(mut self, version: u16, cache_control: HeaderValue) -> Self {
         assert!(
-            versions[N - 1] < 10_000,
-            "API versions must be within 0u16..10_000"
+            self.config.versions.contains(&version),
+            "version must be one of the API versions"
         );
+        self.config.cache_controls.insert(version, cache_control);
+        self
+    }
 
-        Self(versions)
+    /// Apply the given CORS policy to requests for the given version, e.g. to allow any origin for
+    /// an old version, but only specific ones for a newer version: preflight requests are answered
+    /// directly and the CORS headers are added to responses for requests with an `"origin"`
+    /// header. As browsers do not send the `"x-api-version"` header with preflight requests, their
+    /// version is determined by the version prefix or defaulted. Versions without CORS policy are
+    /// not affected, e.g. if CORS is handled by an outer layer.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the given version is not one of the API versions.
+    pub fn cors_policy(mut self, version: u16, cors_policy: cors::CorsPolicy) -> Self {
+        assert!(
+            self.config.versions.contains(&version),
+            "version must be one of the API versions"
+        );
+        self.config.cors_policies.insert(version, cors_policy);
+        self
     }
-}
 
-impl<const N: usize> Deref for ApiVersions<N> {
-    type Target = [u16; N];
+    /// Prefix entity tags in `"etag"` response headers set by the handlers with the version, e.g.
+    /// `"v1-abc"` for `"abc"`, such that caches cannot serve a representation for one version to
+    /// clients of another version. Accordingly, the prefix is removed from entity tags in
+    /// `"if-none-match"` and `"if-match"` request headers before they reach the handlers and
+    /// entity tags for other versions are dropped, preventing false `304 Not Modified` responses.
+    /// Disabled by default.
+    pub fn version_etags(mut self, version_etags: bool) -> Self {
+        self.config.version_etags = version_etags;
+        self
+    }
 
-    fn deref(&self) -> &Self::Target {
-        &self.0
+    /// Rewrite the version prefix in the `"location"` header of redirect responses set by the
+    /// handlers according to the given policy, e.g. to strip it from redirects to canonical
+    /// resource URLs like `"/api/v1/users/42"`. Only locations starting with the base path followed
+    /// by the version prefix of one of the API versions are rewritten. Defaults to
+    /// [RedirectPolicy::Keep].
+    pub fn redirect_policy(mut self, redirect_policy: RedirectPolicy) -> Self {
+        self.config.redirect_policy = redirect_policy;
+        self
     }
-}
 
-/// See [ApiVersionLayer].
-#[derive(Clone)]
-pub struct ApiVersionService<const N: usize, S> {
-    inner: S,
-    base_path: String,
-    versions: ApiVersions<N>,
-}
+    /// Honor the `"x-forwarded-prefix"` request header, e.g. `"/service-a"`, set by a reverse proxy
+    /// which strips this prefix: rewritten `"location"` headers, see
+    /// [ApiVersionLayer::redirect_policy], are made relative to the forwarded prefix, whether or
+    /// not the locations set by the handlers already start with it. Requests are not affected, as
+    /// the proxy has already stripped the prefix. Prefixes which are not valid paths, e.g.
+    /// `"/a b"`, are ignored. As clients can set any header, only enable this behind a proxy which
+    /// overwrites it. Disabled by default.
+    pub fn forwarded_prefix(mut self, forwarded_prefix: bool) -> Self {
+        self.config.forwarded_prefix = forwarded_prefix;
+        self
+    }
 
-impl<const N: usize, S> Service<Request> for ApiVersionService<N, S>
-where
-    S: Service<Request, Response = Response> + Clone + Send + 'static,
-    S::Future: Send + 'static,
-{
-    type Response = S::Response;
-    type Error = S::Error;
-    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+    /// Deprecate the given version as of the given time: responses for this version carry a
+    /// `"deprecation"` header (RFC 9745) with this time.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the given version is not one of the API versions.
+    pub fn deprecation(mut self, version: u16, deprecation: SystemTime) -> Self {
+        assert!(
+            self.config.versions.contains(&version),
+            "version must be one of the API versions"
+        );
+        self.config.deprecations.insert(version, deprecation);
+        self
+    }
 
-    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        self.inner.poll_ready(cx)
+    /// Retire the given version at the given time: before, responses for this version carry a
+    /// `"sunset"` header (RFC 8594) with this time; afterwards, requests for this version are
+    /// rejected with `410 Gone`. This transition happens automatically according to the clock, see
+    /// [ApiVersionLayer::clock].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the given version is not one of the API versions.
+    pub fn sunset(mut self, version: u16, sunset: SystemTime) -> Self {
+        assert!(
+            self.config.versions.contains(&version),
+            "version must be one of the API versions"
+        );
+        self.config.sunsets.insert(version, sunset);
+        self
     }
 
-    fn call(&mut self, mut request: Request) -> Self::Future {
-        let mut inner = self.inner.clone();
-        let base_path = self.base_path.clone();
-        let versions = self.versions;
+    /// Add the [X_API_SUNSET_IN] header with the number of seconds until the sunset, e.g.
+    /// `"86400"`, to all responses carrying a `"sunset"` header, see [ApiVersionLayer::sunset],
+    /// as a relative countdown is easier to alert on for some client dashboards than an absolute
+    /// date. The countdown is computed per response according to the clock, see
+    /// [ApiVersionLayer::clock]. Disabled by default.
+    pub fn sunset_countdown(mut self, sunset_countdown: bool) -> Self {
+        self.config.sunset_countdown = sunset_countdown;
+        self
+    }
 
-        Box::pin(async move {
-            // Strip base path prefix or return without rewriting.
-            let path = if let Some(path) = request.uri().path().strip_prefix(&base_path)
-                && path.starts_with('/')
-            {
-                path.to_owned()
-            } else {
-                debug!(
-                    uri = %request.uri(),
-                    "not rewriting the path, because does not start with base path"
-                );
-                return inner.call(request).await;
-            };
+    /// Use the given store for the live versioning state, which overrides the deprecations,
+    /// sunsets, default version and rollout configured here, e.g. to share the state among
+    /// multiple instances and update it at runtime.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use api_version::{
+    /// #     ApiVersionLayer, ApiVersions,
+    /// #     state::{InMemoryVersionStateStore, VersionState, VersionStateStore},
+    /// # };
+    /// const API_VERSIONS: ApiVersions<2> = ApiVersions::new([0, 1]);
+    ///
+    /// let store = InMemoryVersionStateStore::new();
+    /// let layer = ApiVersionLayer::new("/api", API_VERSIONS).state_store(store.clone());
+    ///
+    /// // E.g. from an admin endpoint: roll back the default version.
+    /// store.save(VersionState {
+    ///     default_version: Some(0),
+    ///     ..Default::default()
+    /// });
+    /// ```
+    pub fn state_store(mut self, state_store: impl state::VersionStateStore) -> Self {
+        self.config.state_store = Some(Arc::new(state_store));
+        self
+    }
 
-            // Return without rewriting if stripped path starts with valid version prefix.
-            let has_version_prefix = versions
-                .iter()
-                .any(|version| path.starts_with(&format!("/v{version}/")));
-            if has_version_prefix {
-                debug!(
-                    uri = %request.uri(),
-                    "not rewriting the path, because starts with valid version prefix"
-                );
-                return inner.call(request).await;
-            }
+    /// Use the live versioning state from the JSON file at the given path, see
+    /// [state::VersionStateConfig] for its format, and reload it whenever it changes, e.g. for
+    /// GitOps-style version management. Changes are detected via file system notifications and
+    /// applied atomically; invalid files are logged and ignored, keeping the previous state.
+    /// Overrides [ApiVersionLayer::state_store].
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a Tokio runtime.
+    #[cfg(feature = "watch-config")]
+    pub fn watch_config(self, path: impl Into<std::path::PathBuf>) -> Self {
+        let store = state::InMemoryVersionStateStore::new();
+        state::watch(path.into(), store.clone());
+        self.state_store(store)
+    }
 
-            // Determine version.
-            let version = request.extract_parts::<TypedHeader<XApiVersion>>().await;
-            let version = version
-                .as_ref()
-                .map(|TypedHeader(XApiVersion(v))| v)
-                .unwrap_or_else(|_| versions.last().expect("versions is not empty"));
-            if !versions.contains(version) {
-                let response = (
-                    StatusCode::NOT_FOUND,
-                    format!("unknown version '{version}'"),
-                );
-                return Ok(response.into_response());
-            }
-            debug!(?version, "using API version");
-
-            // Insert version prefix into request URI.
-            let mut parts = request.uri().to_owned().into_parts();
-            let paq = parts.path_and_query.expect("uri has 'path and query'");
-            let mut paq_parts = paq.as_str().split('?').skip(1);
-            let paq = match paq_parts.next() {
-                Some(query) => format!("{base_path}/v{version}{path}?{query}"),
-                None => format!("{base_path}/v{version}{path}"),
-            };
-            let paq = PathAndQuery::from_maybe_shared(paq).expect("new 'path and query' is valid");
-            parts.path_and_query = Some(paq);
-            let uri = Uri::from_parts(parts).expect("parts are valid");
+    /// Drain retired versions gracefully according to the given drain: for its window after the
+    /// sunset, clients which have used a retired version before, i.e. are pinned to it, continue
+    /// to be served, while others get `410 Gone`. Clients are identified by their client key, see
+    /// [ApiVersionLayer::client_key_header], hence requests without client key are never drained.
+    ///
+    /// As any client which sends a client key before the sunset is pinned, the client key header
+    /// must be set by a trusted proxy, e.g. from an authenticated identity, and not be taken from
+    /// clients as is. The number of pinned clients is limited, see [Drain::capacity].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use api_version::{ApiVersionLayer, ApiVersions, Drain};
+    /// # use axum::http::HeaderName;
+    /// # use std::time::Duration;
+    /// const API_VERSIONS: ApiVersions<2> = ApiVersions::new([0, 1]);
+    ///
+    /// let drain = Drain::new(Duration::from_secs(24 * 60 * 60));
+    /// let layer = ApiVersionLayer::new("/api", API_VERSIONS)
+    ///     .client_key_header(HeaderName::from_static("x-client-id"))
+    ///     .drain(drain.clone());
+    ///
+    /// // E.g. periodically for the application's metrics.
+    /// let remaining = drain.drained_requests(0);
+    /// ```
+    pub fn drain(mut self, drain: Drain) -> Self {
+        self.config.drain = Some(drain);
+        self
+    }
 
-            // Rewrite the request URI and run the downstream services.
-            debug!(original_uri = %request.uri(), %uri, "rewrote the path");
-            request.uri_mut().clone_from(&uri);
-            inner.call(request).await
-        })
+    /// Limit the number of requests in flight per version according to the given limits, such
+    /// that an expensive legacy version cannot starve newer ones. Requests beyond a limit wait
+    /// until a request for the same version has completed, i.e. its response has been produced;
+    /// streaming response bodies are not taken into account. Waiting is not subject to the
+    /// timeout, see [ApiVersionLayer::timeout]. Requires the `"tokio"` feature.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use api_version::{ApiVersionLayer, ApiVersions, ConcurrencyLimits};
+    /// const API_VERSIONS: ApiVersions<2> = ApiVersions::new([0, 1]);
+    ///
+    /// let limits = ConcurrencyLimits::new([(0, 16)]);
+    /// let layer = ApiVersionLayer::new("/api", API_VERSIONS).concurrency_limits(limits.clone());
+    ///
+    /// // E.g. periodically for the application's metrics.
+    /// let in_flight = limits.in_flight(0);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if any limited version is not one of the API versions.
+    #[cfg(feature = "tokio")]
+    pub fn concurrency_limits(mut self, concurrency_limits: ConcurrencyLimits) -> Self {
+        assert!(
+            concurrency_limits
+                .limits
+                .keys()
+                .all(|version| self.config.versions.contains(version)),
+            "version must be one of the API versions"
+        );
+        self.config.concurrency_limits = Some(concurrency_limits);
+        self
     }
-}
 
-/// Header name for the [XApiVersion] custom HTTP header.
-pub static X_API_VERSION: HeaderName = HeaderName::from_static("x-api-version");
+    /// Alert according to the given deprecation budget if the share of requests for deprecated
+    /// versions, see [ApiVersionLayer::deprecation], exceeds its threshold within a window, e.g.
+    /// to notice regressions in the migration progress. Requires the `"tokio"` feature.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use api_version::{ApiVersionLayer, ApiVersions, DeprecationAlert, DeprecationBudget};
+    /// # use std::time::{Duration, UNIX_EPOCH};
+    /// const API_VERSIONS: ApiVersions<2> = ApiVersions::new([0, 1]);
+    ///
+    /// let budget = DeprecationBudget::new(0.01, Duration::from_secs(5 * 60), |alert| async move {
+    ///     println!("{:.1}% deprecated traffic", alert.deprecated_share() * 100.0);
+    /// });
+    /// let layer = ApiVersionLayer::new("/api", API_VERSIONS)
+    ///     .deprecation(0, UNIX_EPOCH)
+    ///     .deprecation_budget(budget);
+    /// ```
+    #[cfg(feature = "tokio")]
+    pub fn deprecation_budget(mut self, deprecation_budget: DeprecationBudget) -> Self {
+        self.config.deprecation_budget = Some(deprecation_budget);
+        self
+    }
 
-/// Custom HTTP header conveying the API version, which is expected to be a version designator
-/// starting with `'v'` followed by a number within `0u16..10_000` without leading zero, e.g. `v0`.
-#[derive(Debug)]
-pub struct XApiVersion(u16);
+    /// Use the given clock for time-based features like [ApiVersionLayer::sunset] instead of the
+    /// [SystemClock], e.g. for tests or for `wasm32-unknown-unknown`, which has no system time.
+    pub fn clock(mut self, clock: impl Clock) -> Self {
+        self.config.clock = Arc::new(clock);
+        self
+    }
 
-impl Header for XApiVersion {
-    fn name() -> &'static HeaderName {
-        &X_API_VERSION
+    /// Use the given version for requests without version instead of the highest version.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the given version is not one of the API versions.
+    pub fn default_version(mut self, version: u16) -> Self {
+        assert!(
+            self.config.versions.contains(&version),
+            "version must be one of the API versions"
+        );
+        self.config.default_version = version;
+        self
     }
 
-    fn decode<'i, I>(values: &mut I) -> Result<Self, headers::Error>
-    where
-        Self: Sized,
-        I: Iterator<Item = &'i HeaderValue>,
-    {
-        values
-            .next()
-            .and_then(|v| v.to_str().ok())
-            .and_then(|s| VERSION.captures(s).and_then(|c| c.get(1)))
-            .and_then(|m| m.as_str().parse().ok())
-            .map(XApiVersion)
-            .ok_or_else(headers::Error::invalid)
+    /// Announce that the default version is going to change to the given version at the given
+    /// time: until then, responses for requests without version carry an advisory header, e.g.
+    /// `"x-api-default-version-changing: v2 on Wed, 01 Oct 2025 00:00:00 GMT"`, while still getting
+    /// the current default version. This derisks bumping the default version.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the given version is not one of the API versions.
+    pub fn default_version_advisory(mut self, version: u16, at: SystemTime) -> Self {
+        assert!(
+            self.config.versions.contains(&version),
+            "version must be one of the API versions"
+        );
+        self.config.default_version_advisory = Some((version, at));
+        self
     }
 
-    fn encode<E: Extend<HeaderValue>>(&self, _values: &mut E) {
-        // We do not yet need to encode this header.
-        unimplemented!("not yet needed");
+    /// Schedule the default version to become the given version at the given time according to
+    /// the clock, see [ApiVersionLayer::clock], such that deployments in all regions flip the
+    /// default at the same instant without coordinated deploys. Until then, responses for
+    /// requests without version carry the advisory header like for
+    /// [ApiVersionLayer::default_version_advisory]. Can be called multiple times.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the given version is not one of the API versions.
+    pub fn schedule_default_version(mut self, version: u16, at: SystemTime) -> Self {
+        assert!(
+            self.config.versions.contains(&version),
+            "version must be one of the API versions"
+        );
+        self.config.default_schedule.push((at, version));
+        self.config.default_schedule.sort_by_key(|&(at, _)| at);
+        self
+    }
+
+    /// Flag the given version as internal, e.g. for staff-only API iterations: it is only
+    /// routable for requests with internal access, i.e. an [InternalAccess] extension, e.g. set
+    /// by an authentication middleware, or the configured header, see
+    /// [ApiVersionLayer::internal_access_header]. Otherwise it behaves as unknown version. Internal
+    /// versions are never used as default and excluded from discovery. Can be called multiple
+    /// times. Internal versions can be released at runtime via the live state, see
+    /// [state::VersionState::released].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the given version is not one of the API versions or if all API versions would be
+    /// internal.
+    pub fn internal_version(mut self, version: u16) -> Self {
+        assert!(
+            self.config.versions.contains(&version),
+            "version must be one of the API versions"
+        );
+        self.config.internal_versions.push(version);
+        assert!(
+            self.config
+                .versions
+                .iter()
+                .any(|version| !self.config.internal_versions.contains(version)),
+            "not all API versions must be internal"
+        );
+        self
     }
-}
 
-const fn is_monotonically_increasing<const N: usize>(versions: [u16; N]) -> bool {
-    if N < 2 {
-        return true;
+    /// Grant internal access to requests with the given header, see
+    /// [ApiVersionLayer::internal_version]. As clients can set any header, make sure it is removed
+    /// from external requests, e.g. at the edge.
+    pub fn internal_access_header(mut self, name: HeaderName) -> Self {
+        self.config.internal_access_header = Some(name);
+        self
     }
 
-    let mut n = 1;
-    while n < N {
-        if versions[n - 1] >= versions[n] {
-            return false;
+    /// Use the given health signal for the versions: while the default version is unhealthy,
+    /// requests without version fall back to the highest lower version which is healthy and not
+    /// retired, limiting the blast radius of a bad release. Explicitly requested versions are not
+    /// affected.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use api_version::{ApiVersionLayer, ApiVersions, CircuitBreaker};
+    /// const API_VERSIONS: ApiVersions<2> = ApiVersions::new([0, 1]);
+    ///
+    /// let circuit_breaker = CircuitBreaker::new(0.05);
+    /// let layer =
+    ///     ApiVersionLayer::new("/api", API_VERSIONS).version_health(circuit_breaker.clone());
+    ///
+    /// // E.g. periodically from the application's metrics.
+    /// circuit_breaker.report_error_rate(1, 0.2);
+    /// ```
+    pub fn version_health(mut self, version_health: impl VersionHealth) -> Self {
+        self.config.version_health = Some(Arc::new(version_health));
+        self
+    }
+
+    /// Ramp the share of requests without version getting the given version instead of the
+    /// current default one linearly from 1% at the given start to 100% at the given end according
+    /// to the clock, see [ApiVersionLayer::clock]. Clients are bucketed deterministically by their
+    /// client key, see [ApiVersionLayer::client_key_header], such that a client keeps its version
+    /// while the share grows; requests without client key only get the given version after the
+    /// end. Remove the ramp to revert.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the given version is not one of the API versions or if the start is not before
+    /// the end.
+    pub fn ramp_default_version(
+        mut self,
+        version: u16,
+        start: SystemTime,
+        end: SystemTime,
+    ) -> Self {
+        assert!(
+            self.config.versions.contains(&version),
+            "version must be one of the API versions"
+        );
+        assert!(start < end, "start must be before end");
+        self.config.default_ramp = Some(state::Rollout {
+            version,
+            start,
+            end,
+        });
+        self
+    }
+
+    /// Split requests without version between the given control and treatment versions according
+    /// to their weights for an A/B experiment with the given id, e.g. to compare `v1` and `v2` for
+    /// unversioned traffic. Clients are bucketed deterministically by their client key, see
+    /// [ApiVersionLayer::client_key_header], salted with the experiment id; requests without
+    /// client key get the current default version. The assignment is recorded in the
+    /// [ExperimentAssignment] request extension and the `"x-api-version-experiment"` response
+    /// header, e.g. `"checkout=v2"`, to join results with analytics. Takes precedence over
+    /// [ApiVersionLayer::ramp_default_version].
+    ///
+    /// # Panics
+    ///
+    /// Panics if one of the given versions is not one of the API versions, if both weights are
+    /// zero or if the given id is not a valid header value.
+    pub fn default_experiment(
+        mut self,
+        id: impl Into<String>,
+        control: (u16, u32),
+        treatment: (u16, u32),
+    ) -> Self {
+        for (version, _) in [control, treatment] {
+            assert!(
+                self.config.versions.contains(&version),
+                "version must be one of the API versions"
+            );
         }
-        n += 1;
+        assert!(
+            control.1 > 0 || treatment.1 > 0,
+            "weights must not both be zero"
+        );
+        let id = id.into();
+        assert!(
+            HeaderValue::from_str(&id).is_ok(),
+            "id must be a valid header value"
+        );
+        self.config.default_experiment = Some(DefaultExperiment {
+            id: id.into(),
+            control,
+            treatment,
+        });
+        self
     }
 
-    true
-}
+    /// Use the given formatter for the messages in the bodies of negotiation error responses
+    /// instead of the default English ones, e.g. to localize them based on the
+    /// `"accept-language"` request header.
+    pub fn error_message(
+        mut self,
+        error_message: impl Fn(VersioningError, &Request) -> String + Send + Sync + 'static,
+    ) -> Self {
+        self.config.error_message = Some(Arc::new(error_message));
+        self
+    }
 
-#[cfg(test)]
-mod tests {
-    use crate::{VERSION, is_monotonically_increasing};
-    use assert_matches::assert_matches;
+    /// Do not version requests with the given method at all, e.g. `OPTIONS` if the inner router
+    /// special-cases it. Can be called multiple times to skip multiple methods.
+    pub fn skip_method(mut self, method: Method) -> Self {
+        self.config.skipped_methods.push(method);
+        self
+    }
 
-    #[test]
-    fn test_x_api_header() {
-        let version = VERSION
-            .captures("v0")
-            .and_then(|c| c.get(1))
-            .map(|m| m.as_str());
-        assert_matches!(version, Some("0"));
-
-        let version = VERSION
-            .captures("v1")
-            .and_then(|c| c.get(1))
-            .map(|m| m.as_str());
-        assert_matches!(version, Some("1"));
-
-        let version = VERSION
-            .captures("v99")
-            .and_then(|c| c.get(1))
-            .map(|m| m.as_str());
-        assert_matches!(version, Some("99"));
-
-        let version = VERSION
-            .captures("v9999")
-            .and_then(|c| c.get(1))
-            .map(|m| m.as_str());
-        assert_matches!(version, Some("9999"));
-
-        let version = VERSION
-            .captures("v10000")
-            .and_then(|c| c.get(1))
-            .map(|m| m.as_str());
-        assert_matches!(version, None);
-
-        let version = VERSION
-            .captures("vx")
-            .and_then(|c| c.get(1))
-            .map(|m| m.as_str());
-        assert_matches!(version, None);
+    /// Handle gRPC-Web and Connect requests, detected by their content type, e.g.
+    /// `"application/grpc-web+proto"` or `"application/connect+json"`, or by the
+    /// `"connect-protocol-version"` header of Connect unary requests, according to the given
+    /// policy, as rewriting their paths breaks procedure resolution. Defaults to
+    /// [RpcPolicy::Version].
+    pub fn rpc_policy(mut self, rpc_policy: RpcPolicy) -> Self {
+        self.config.rpc_policy = rpc_policy;
+        self
     }
 
-    #[test]
-    fn test_is_monotonically_increasing() {
-        assert!(is_monotonically_increasing([]));
-        assert!(is_monotonically_increasing([0]));
-        assert!(is_monotonically_increasing([0, 1]));
+    /// Handle requests under the base path, before or after removing dot segments, whose paths
+    /// contain dot segments, i.e. `"."` or `".."`, also percent-encoded, e.g.
+    /// `"/api/v1/../v0/test"`, according to the given policy, such that version prefix
+    /// detection and version-based access restrictions cannot be bypassed via path traversal.
+    /// Defaults to [DotSegmentPolicy::Normalize].
+    pub fn dot_segment_policy(mut self, dot_segment_policy: DotSegmentPolicy) -> Self {
+        self.config.dot_segment_policy = dot_segment_policy;
+        self
+    }
 
-        assert!(!is_monotonically_increasing([0, 0]));
+    /// Terminate requests under the base path which cannot be versioned unambiguously instead of
+    /// passing them on, such that no unversioned handler is reachable accidentally: requests with
+    /// an invalid `"x-api-version"` header are rejected with `400 Bad Request` instead of using
+    /// the default version, requests with an unknown version prefix, e.g. `"/api/v7/test"`, with
+    /// `404 Not Found` instead of being rewritten and, unless [ApiVersionLayer::rewrite_path] is
+    /// disabled, requests for the base path itself with `404 Not Found`. Skipped methods, see
+    /// [ApiVersionLayer::skip_method], are not affected. Defaults to `false`.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.config.strict = strict;
+        self
+    }
+
+    /// Reject requests carrying more than the given number of `"x-api-version"` header values with
+    /// `400 Bad Request`, such that clients cannot make the negotiation examine an unbounded number
+    /// of values. Counting stops right after the limit. Defaults to 8.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the given number is zero.
+    pub fn max_version_header_values(mut self, max_version_header_values: usize) -> Self {
+        assert!(
+            max_version_header_values > 0,
+            "max version header values must be positive"
+        );
+        self.config.max_version_header_values = max_version_header_values;
+        self
+    }
+
+    /// Inject the given value for the resolved version as [VersionScopedState] request extension,
+    /// e.g. a database pool for a version specific schema, such that handlers can be written once
+    /// and get version appropriate dependencies. Can be called multiple times for different types.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use api_version::{ApiVersionLayer, ApiVersions, VersionScopedState};
+    /// # use axum::{Router, routing::get};
+    /// const API_VERSIONS: ApiVersions<2> = ApiVersions::new([0, 1]);
+    ///
+    /// async fn handler(VersionScopedState(schema): VersionScopedState<&'static str>) -> String {
+    ///     format!("select * from {schema}.users")
+    /// }
+    ///
+    /// let app = Router::<()>::new()
+    ///     .route("/api/v0/users", get(handler))
+    ///     .route("/api/v1/users", get(handler))
+    ///     .layer(
+    ///         ApiVersionLayer::new("/api", API_VERSIONS).version_state([(0, "v0"), (1, "v1")]),
+    ///     );
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if any of the given versions is not one of the API versions.
+    pub fn version_state<T>(mut self, states: impl IntoIterator<Item = (u16, T)>) -> Self
+    where
+        T: Clone + Send + Sync + 'static,
+    {
+        let states = states.into_iter().collect::<HashMap<_, _>>();
+        assert!(
+            states
+                .keys()
+                .all(|version| self.config.versions.contains(version)),
+            "version must be one of the API versions"
+        );
+        self.config
+            .version_states
+            .push(Arc::new(move |version, extensions| {
+                if let Some(state) = states.get(&version) {
+                    extensions.insert(VersionScopedState(state.clone()));
+                }
+            }));
+        self
+    }
+
+    /// Contribute the resolved version to the request extensions via the given hook, e.g. for a
+    /// signing-verification middleware which needs the version as part of the canonical string of
+    /// HMAC-signed requests. Can be called multiple times.
+    ///
+    /// The hook is invoked during the negotiation, which only considers the method, URI and
+    /// headers, but never the body, and happens before any body-consuming stage of this layer,
+    /// i.e. body size limits and request transforms, see [ApiVersionLayer::max_body_size] and
+    /// [ApiVersionLayer::request_transform]. Hence inner layers, e.g. the signing verification,
+    /// see the contributed extension along with the unconsumed body; note that request transforms
+    /// change the body before it reaches inner layers.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use api_version::{ApiVersionLayer, ApiVersions};
+    /// # use axum::{
+    /// #     Router,
+    /// #     extract::Request,
+    /// #     middleware::{Next, from_fn},
+    /// #     response::Response,
+    /// #     routing::post,
+    /// # };
+    /// const API_VERSIONS: ApiVersions<2> = ApiVersions::new([0, 1]);
+    ///
+    /// #[derive(Clone)]
+    /// struct SignedVersion(String);
+    ///
+    /// async fn verify_signature(request: Request, next: Next) -> Response {
+    ///     let SignedVersion(version) = request.extensions().get().cloned().unwrap();
+    ///     // Build the canonical string from the method, path, version and body and verify the
+    ///     // signature.
+    ///     next.run(request).await
+    /// }
+    ///
+    /// let app = Router::<()>::new()
+    ///     .route("/api/v0/orders", post(|| async {}))
+    ///     .route("/api/v1/orders", post(|| async {}))
+    ///     .layer(from_fn(verify_signature))
+    ///     .layer(ApiVersionLayer::new("/api", API_VERSIONS).version_extension(
+    ///         |version, extensions| {
+    ///             extensions.insert(SignedVersion(format!("v{version}")));
+    ///         },
+    ///     ));
+    /// ```
+    pub fn version_extension(
+        mut self,
+        version_extension: impl Fn(u16, &mut Extensions) + Send + Sync + 'static,
+    ) -> Self {
+        self.config.version_states.push(Arc::new(version_extension));
+        self
+    }
+
+    /// Also negotiate the version via the `"api-version"` preference of the `"prefer"` header (RFC
+    /// 7240), e.g. `"prefer: api-version=2"` or `"prefer: api-version=v2"`, if neither a valid
+    /// version prefix nor the `"x-api-version"` header is given; invalid preferences are ignored.
+    /// Responses for versions negotiated this way carry a `"preference-applied"` header, e.g.
+    /// `"api-version=2"`. Disabled by default.
+    pub fn prefer_header(mut self, prefer_header: bool) -> Self {
+        self.config.prefer_header = prefer_header;
+        self
+    }
+
+    /// Also negotiate the version via vendor media types in the `"accept"` header, e.g.
+    /// `"application/vnd.myapp.v1+json"` for the vendor `"myapp"`, if neither a valid version
+    /// prefix nor the `"x-api-version"` header is given. The `"content-type"` of JSON responses
+    /// for versions negotiated this way is set to the matching vendor media type, such that they
+    /// round-trip through strict clients.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the given vendor is empty or contains characters other than ASCII alphanumerics,
+    /// `'-'` and `'_'`.
+    pub fn vendor_media_type(mut self, vendor: impl Into<String>) -> Self {
+        let vendor = vendor.into();
+        assert!(
+            !vendor.is_empty()
+                && vendor
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'),
+            "vendor must be non-empty and only contain ASCII alphanumerics, '-' and '_'"
+        );
+        self.config.vendor = Some(vendor);
+        self
+    }
+
+    /// Choose among multiple versions offered by the client, i.e. multiple vendor media types in
+    /// the `"accept"` header, see [ApiVersionLayer::vendor_media_type], according to the given
+    /// strategy, e.g. to let compliance-sensitive clients get the oldest version they support.
+    /// Defaults to [NegotiationStrategy::PreferFirst].
+    pub fn negotiation_strategy(mut self, negotiation_strategy: NegotiationStrategy) -> Self {
+        self.config.negotiation_strategy = negotiation_strategy;
+        self
+    }
+
+    /// Transform JSON response bodies for the given version with the given transformation, e.g.
+    /// to remove a field added in a later version, such that versions can share handlers.
+    /// Streaming responses are not transformed. Requires the `"json-transform"` feature.
+    ///
+    /// Transformations must run before response compression, i.e. a compression layer like
+    /// tower-http's `CompressionLayer` must be applied outside of, i.e. after, this layer.
+    /// Encoded responses, i.e. such with a `"content-encoding"` other than `"identity"`, for
+    /// versions with a transformation are replaced with `500 Internal Server Error` and logged as
+    /// error, such that a wrong layer order fails fast instead of silently skipping the
+    /// transformation.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use api_version::{ApiVersionLayer, ApiVersions, transform::JsonTransform};
+    /// const API_VERSIONS: ApiVersions<2> = ApiVersions::new([0, 1]);
+    ///
+    /// let layer = ApiVersionLayer::new("/api", API_VERSIONS)
+    ///     .response_transform(0, JsonTransform::new().remove("email"));
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if the given version is not one of the API versions.
+    #[cfg(feature = "json-transform")]
+    pub fn response_transform(mut self, version: u16, transform: transform::JsonTransform) -> Self {
+        assert!(
+            self.config.versions.contains(&version),
+            "version must be one of the API versions"
+        );
+        self.config.response_transforms.insert(version, transform);
+        self
+    }
+
+    /// Transform JSON request bodies for the given version with the given transformation before
+    /// they reach the handler, e.g. to rename fields or fill defaults for fields added later, such
+    /// that the newest handler can serve all versions. Request bodies are buffered up to the
+    /// maximum body size for the version, see [ApiVersionLayer::max_body_size], or 2 MiB, and
+    /// rejected with `413 Payload Too Large` if larger, without buffering at all if the
+    /// `"content-length"` exceeds the limit. Bodies of other requests, e.g. multipart uploads, are
+    /// never buffered, just like rewriting the path never touches bodies. Requires the
+    /// `"json-transform"` feature.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use api_version::{ApiVersionLayer, ApiVersions, transform::JsonTransform};
+    /// # use serde_json::json;
+    /// const API_VERSIONS: ApiVersions<2> = ApiVersions::new([0, 1]);
+    ///
+    /// let layer = ApiVersionLayer::new("/api", API_VERSIONS).request_transform(
+    ///     0,
+    ///     JsonTransform::new()
+    ///         .rename("full_name", "name")
+    ///         .fill("newsletter", json!(false)),
+    /// );
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if the given version is not one of the API versions.
+    #[cfg(feature = "json-transform")]
+    pub fn request_transform(mut self, version: u16, transform: transform::JsonTransform) -> Self {
+        assert!(
+            self.config.versions.contains(&version),
+            "version must be one of the API versions"
+        );
+        self.config.request_transforms.insert(version, transform);
+        self
+    }
+
+    /// Whether to buffer JSON request bodies without `"content-length"`, i.e. streaming ones, for
+    /// request transformations, see [ApiVersionLayer::request_transform]. If disabled, such
+    /// requests for versions with a request transformation are rejected with
+    /// `411 Length Required` instead. Defaults to `true`. Requires the `"json-transform"` feature.
+    #[cfg(feature = "json-transform")]
+    pub fn transform_streaming_requests(mut self, transform_streaming_requests: bool) -> Self {
+        self.config.transform_streaming_requests = transform_streaming_requests;
+        self
+    }
+
+    /// Turn this layer into one dispatching each request to the given service for its version
+    /// instead of rewriting the path for a single inner service, e.g. to serve versions by
+    /// completely different routers. Requests which are not versioned, e.g. because their path
+    /// does not start with the base path, are passed to the inner service.
+    ///
+    /// Unless [ApiVersionLayer::rewrite_path] is disabled, the version prefix, if any, is stripped
+    /// from the path, i.e. the services for all versions get `"/api/test"` for `"/api/v0/test"` as
+    /// well as for `"/api/test"`; if [ApiVersionLayer::strip_base_path] is enabled, they get
+    /// `"/test"`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use api_version::{ApiVersionLayer, ApiVersions};
+    /// # use axum::{Router, routing::get};
+    /// # use tower::Layer;
+    /// const API_VERSIONS: ApiVersions<2> = ApiVersions::new([0, 1]);
+    ///
+    /// let v0 = Router::<()>::new().route("/api/test", get(|| async { "0" }));
+    /// let v1 = Router::<()>::new().route("/api/test", get(|| async { "1" }));
+    /// let app = Router::<()>::new().route("/ready", get(|| async { "ready" }));
+    ///
+    /// let app = ApiVersionLayer::new("/api", API_VERSIONS)
+    ///     .dispatch([(0, v0), (1, v1)])
+    ///     .layer(app);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is not exactly one service for each API version.
+    pub fn dispatch<V>(
+        self,
+        services: impl IntoIterator<Item = (u16, V)>,
+    ) -> ApiVersionDispatchLayer<N, V> {
+        let services = services.into_iter().collect::<HashMap<_, _>>();
+        assert!(
+            services.len() == N
+                && self
+                    .config
+                    .versions
+                    .iter()
+                    .all(|version| services.contains_key(version)),
+            "there must be exactly one service for each API version"
+        );
+
+        ApiVersionDispatchLayer {
+            config: self.config,
+            services,
+        }
+    }
+
+    /// Combine this layer with the given one for a different path subtree into a single layer,
+    /// e.g. for subtrees like `"/api/billing"` and `"/api/catalog"` evolving on different version
+    /// cadences. Each request is versioned by the layer with the longest base path matching its
+    /// path, i.e. with that layer's versions, defaults and lifecycles only; others are passed to
+    /// the inner service. Unlike stacking layers, this also works for nested base paths like
+    /// `"/api"` and `"/api/billing"`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use api_version::{ApiVersionLayer, ApiVersions};
+    /// # use axum::{Router, routing::get};
+    /// # use tower::Layer;
+    /// const BILLING_VERSIONS: ApiVersions<2> = ApiVersions::new([1, 2]);
+    /// const CATALOG_VERSIONS: ApiVersions<3> = ApiVersions::new([0, 1, 2]);
+    ///
+    /// let app = Router::<()>::new()
+    ///     .route("/api/billing/v2/invoices", get(|| async { "invoices" }))
+    ///     .route("/api/catalog/v2/items", get(|| async { "items" }));
+    ///
+    /// let app = ApiVersionLayer::new("/api/billing", BILLING_VERSIONS)
+    ///     .subtree(ApiVersionLayer::new("/api/catalog", CATALOG_VERSIONS).default_version(1))
+    ///     .layer(app);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if both layers have the same base path.
+    pub fn subtree<const M: usize>(self, layer: ApiVersionLayer<M>) -> ApiVersionSubtreeLayer {
+        ApiVersionSubtreeLayer {
+            subtrees: Arc::new([]),
+        }
+        .subtree(self)
+        .subtree(layer)
+    }
+
+    /// Apply the given function to all responses for requests with a resolved version, including
+    /// error responses created by the inner service, after adding the configured response headers,
+    /// e.g. to add further version specific headers.
+    pub fn map_response(
+        mut self,
+        map_response: impl Fn(u16, &mut Response) + Send + Sync + 'static,
+    ) -> Self {
+        self.config.map_response = Some(Arc::new(map_response));
+        self
+    }
+
+    /// Inject faults into versioned requests according to the given failure injection, e.g. to
+    /// verify that clients handle version errors gracefully; disabled by default. Requires the
+    /// `"chaos"` feature.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the fault forces a version which is not one of the API versions.
+    #[cfg(feature = "chaos")]
+    pub fn chaos(mut self, chaos: chaos::Chaos) -> Self {
+        if let chaos::Fault::ForceVersion(version) = chaos.fault() {
+            assert!(
+                self.config.versions.contains(&version),
+                "version must be one of the API versions"
+            );
+        }
+        self.config.chaos = Some(chaos);
+        self
+    }
+
+    /// Serve a debug endpoint at `"{base_path}/_version-debug"`, which accepts `POST` requests
+    /// with a JSON body describing a simulated request, e.g.
+    /// `{ "method": "GET", "path": "/api/test", "headers": { "x-api-version": "v0" } }`, and
+    /// responds with the negotiation outcome as JSON, i.e. the resolved version, its source, the
+    /// rewritten URI and the applied policies, without invoking the inner service; disabled by
+    /// default. This helps triaging why a request was routed to some version.
+    pub fn debug_endpoint(mut self, debug_endpoint: bool) -> Self {
+        self.config.debug_endpoint = debug_endpoint;
+        self
+    }
+
+    /// Serve a discovery endpoint at `"{base_path}/_versions"`, which responds to `GET` requests
+    /// with the [manifest::VersionManifest] as JSON, see [manifest::JSON_SCHEMA]; disabled by
+    /// default. This lets SDKs discover the versions and their lifecycle.
+    ///
+    /// Also, `OPTIONS` requests for the base path itself are answered with `204 No Content`, an
+    /// `"allow"` header and the [X_API_SUPPORTED_VERSIONS] header, giving minimal clients a cheap
+    /// capability probe without parsing JSON.
+    pub fn discovery_endpoint(mut self, discovery_endpoint: bool) -> Self {
+        self.config.discovery_endpoint = discovery_endpoint;
+        self
+    }
+
+    /// Respond to requests for the discovery endpoint, see [ApiVersionLayer::discovery_endpoint],
+    /// whose first media range of the `"accept"` header is `"text/html"`, e.g. from browsers, with
+    /// the HTML page rendered from the [manifest::VersionManifest] by the given function instead
+    /// of the serialized manifest, e.g. [manifest::VersionManifest::to_html] for a small page
+    /// listing the versions with their status, sunset and documentation links.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use api_version::{ApiVersionLayer, ApiVersions, manifest::VersionManifest};
+    /// const API_VERSIONS: ApiVersions<2> = ApiVersions::new([0, 1]);
+    ///
+    /// let layer = ApiVersionLayer::new("/api", API_VERSIONS)
+    ///     .discovery_endpoint(true)
+    ///     .html_index(VersionManifest::to_html);
+    /// ```
+    pub fn html_index(
+        mut self,
+        html_index: impl Fn(&manifest::VersionManifest) -> String + Send + Sync + 'static,
+    ) -> Self {
+        self.config.html_index = Some(Arc::new(html_index));
+        self
+    }
+
+    /// Use the given serializer for responses of the discovery and debug endpoints, see
+    /// [ApiVersionLayer::discovery_endpoint] and [ApiVersionLayer::debug_endpoint], if the first
+    /// supported media range of the `"accept"` request header matches the given media type, e.g.
+    /// `"application/yaml"`; JSON is always supported and used by default.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the given media type is not a valid header value.
+    pub fn serializer(
+        mut self,
+        media_type: &'static str,
+        serializer: impl serialize::Serializer,
+    ) -> Self {
+        let media_type = HeaderValue::from_static(media_type);
+        self.config
+            .serializers
+            .push((media_type, Arc::new(serializer)));
+        self
+    }
+
+    /// Link the changelog at the given URL for the given version in the
+    /// [manifest::VersionManifest].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the given version is not one of the API versions.
+    pub fn changelog(mut self, version: u16, url: impl Into<String>) -> Self {
+        assert!(
+            self.config.versions.contains(&version),
+            "version must be one of the API versions"
+        );
+        self.config.changelogs.insert(version, url.into());
+        self
+    }
+
+    /// List the names of the capabilities each version supports according to the given capability
+    /// matrix in the [manifest::VersionManifest], such that the matrix used by shared handler code
+    /// is also the single source of truth for clients.
+    pub fn capabilities<const M: usize>(
+        mut self,
+        capabilities: capability::Capabilities<M>,
+    ) -> Self {
+        self.config.capabilities = capabilities.iter().copied().collect();
+        self
+    }
+
+    /// Register the migration guide at the given URL for the transition from the given version to
+    /// the given other one, e.g. `0 -> 1`. Responses for the former version carry it as
+    /// `"link"` header with `rel="deprecation"` (RFC 9745), once the version is deprecated, see
+    /// [ApiVersionLayer::deprecation], and the [manifest::VersionManifest] includes it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any of the given versions is not one of the API versions or if the URL is not a
+    /// valid header value.
+    pub fn migration_guide(mut self, from: u16, to: u16, url: impl Into<String>) -> Self {
+        assert!(
+            self.config.versions.contains(&from) && self.config.versions.contains(&to),
+            "version must be one of the API versions"
+        );
+        let url = url.into();
+        assert!(
+            HeaderValue::from_str(&url).is_ok(),
+            "URL must be a valid header value"
+        );
+        self.config
+            .migration_guides
+            .push(manifest::MigrationGuide { from, to, url });
+        self
+    }
+
+    /// Add the given metadata field to the [manifest::VersionManifest] served by the discovery
+    /// endpoint, see [ApiVersionLayer::discovery_endpoint].
+    pub fn manifest_metadata(mut self, key: impl Into<String>, value: serde_json::Value) -> Self {
+        self.config.manifest_metadata.insert(key.into(), value);
+        self
+    }
+
+    /// A serializable snapshot of this layer's configuration, see [ApiVersionConfig].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use api_version::{ApiVersionLayer, ApiVersions};
+    /// const API_VERSIONS: ApiVersions<2> = ApiVersions::new([0, 1]);
+    ///
+    /// let running = ApiVersionLayer::new("/api", API_VERSIONS);
+    /// let desired = ApiVersionLayer::new("/api", API_VERSIONS).default_version(0);
+    /// assert_ne!(running.config(), desired.config());
+    ///
+    /// let config = serde_json::to_value(desired.config()).unwrap();
+    /// assert_eq!(config["default_version"], 0);
+    /// ```
+    pub fn config(&self) -> ApiVersionConfig {
+        self.config.snapshot()
+    }
+
+    /// The current [manifest::VersionManifest] for this layer's configuration.
+    pub fn manifest(&self) -> manifest::VersionManifest {
+        self.config.manifest()
+    }
+
+    /// Turn this layer into one for inner services which are not `Send`, e.g. on single-threaded
+    /// WebAssembly/edge runtimes, the futures of which are not `Send` either.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use api_version::{ApiVersionLayer, ApiVersions};
+    /// # use axum::{extract::Request, response::{IntoResponse, Response}};
+    /// # use std::{convert::Infallible, rc::Rc};
+    /// # use tower::{Layer, service_fn};
+    /// const API_VERSIONS: ApiVersions<2> = ApiVersions::new([0, 1]);
+    ///
+    /// let service = service_fn(|request: Request| {
+    ///     let path = Rc::new(request.uri().path().to_owned());
+    ///     async move { Ok::<Response, Infallible>(path.to_string().into_response()) }
+    /// });
+    /// let service = ApiVersionLayer::new("/api", API_VERSIONS).local().layer(service);
+    /// ```
+    pub fn local(self) -> LocalApiVersionLayer<N> {
+        LocalApiVersionLayer {
+            config: Arc::new(self.config),
+        }
+    }
+
+    /// A summary of the effective versioning policy, e.g. to be logged once at startup such that
+    /// operators can verify it at a glance, see [report::PolicyReport].
+    pub fn report(&self) -> report::PolicyReport {
+        self.config.policy_report()
+    }
+
+    /// Validate the given route paths, e.g. the ones registered with the inner router, against
+    /// this layer's base path and API versions: every API version must have at least one route
+    /// under `"{base_path}/v{version}/"`. Routes under a version prefix that is not one of the API
+    /// versions are unreachable via this layer and logged as warnings. Version prefixes are
+    /// detected like by the middleware, see [strip_version], hence e.g. `"/api/v01/test"` is not
+    /// considered to be under a version prefix at all.
+    ///
+    /// As axum does not offer an API to list the routes of a [Router](axum::Router), the route
+    /// paths must be given explicitly instead of the router.
+    ///
+    /// This is intended to be called once at startup when building the layer, to catch drift
+    /// between the layer configuration and the router instead of getting 404s in production.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use api_version::{ApiVersionLayer, ApiVersions};
+    /// # use axum::{Router, routing::get};
+    /// # use tower::Layer;
+    /// const API_VERSIONS: ApiVersions<2> = ApiVersions::new([0, 1]);
+    /// const ROUTES: [&str; 2] = ["/api/v0/test", "/api/v1/test"];
+    ///
+    /// let app = Router::<()>::new()
+    ///     .route(ROUTES[0], get(|| async { "0" }))
+    ///     .route(ROUTES[1], get(|| async { "1" }));
+    /// let layer = ApiVersionLayer::new("/api", API_VERSIONS);
+    /// layer
+    ///     .validate_routes(ROUTES)
+    ///     .expect("every API version has routes");
+    /// let app = layer.layer(app);
+    ///
+    /// assert!(layer.validate_routes(["/api/v0/test"]).is_err());
+    /// ```
+    pub fn validate_routes<'a>(
+        &self,
+        routes: impl IntoIterator<Item = &'a str>,
+    ) -> Result<(), RouteValidationError> {
+        let mut versions_with_routes = Vec::with_capacity(N);
+
+        for route in routes {
+            let Some(path) = route.strip_prefix(&self.config.base_path) else {
+                continue;
+            };
+            let (Some(version), _) = version::strip_version(path) else {
+                continue;
+            };
+
+            if self.config.versions.contains(&version) {
+                versions_with_routes.push(version);
+            } else {
+                warn!(route, version, "route is under an unknown version prefix");
+            }
+        }
+
+        let versions_without_routes = self
+            .config
+            .versions
+            .iter()
+            .copied()
+            .filter(|version| !versions_with_routes.contains(version))
+            .collect::<Vec<_>>();
+        if versions_without_routes.is_empty() {
+            Ok(())
+        } else {
+            Err(RouteValidationError {
+                versions_without_routes,
+            })
+        }
+    }
+}
+
+/// Error returned by [ApiVersionLayer::validate_routes] if some API versions have no routes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RouteValidationError {
+    /// The API versions without any routes.
+    pub versions_without_routes: Vec<u16>,
+}
+
+impl Display for RouteValidationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let versions = self
+            .versions_without_routes
+            .iter()
+            .map(|version| format!("v{version}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        write!(f, "no routes for API versions {versions}")
+    }
+}
+
+impl Error for RouteValidationError {}
+
+impl<const N: usize, S> Layer<S> for ApiVersionLayer<N> {
+    type Service = ApiVersionService<N, S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ApiVersionService {
+            inner,
+            config: Arc::new(self.config.clone()),
+        }
+    }
+}
+
+/// See [ApiVersionLayer].
+#[derive(Debug, Clone)]
+pub struct ApiVersionService<const N: usize, S> {
+    inner: S,
+    config: Arc<Config<N>>,
+}
+
+impl<const N: usize, S> Service<Request> for ApiVersionService<N, S>
+where
+    S: Service<Request, Response = Response> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request) -> Self::Future {
+        // Take the service which has been polled ready, leaving a clone in its place.
+        let clone = self.inner.clone();
+        let mut inner = mem::replace(&mut self.inner, clone);
+        let config = self.config.clone();
+
+        match config.dispatch(&mut request) {
+            Dispatch::Debug => Box::pin(async move { Ok(config.debug(request).await) }),
+            Dispatch::Respond(response) => Box::pin(async move { Ok(response) }),
+            Dispatch::Skip => Box::pin(inner.call(request)),
+            Dispatch::Versioned(negotiated) => {
+                config.rewrite(&mut request, &negotiated);
+                Box::pin(async move { config.call_inner(inner, request, negotiated).await })
+            }
+        }
+    }
+}
+
+/// Like [ApiVersionLayer], but for inner services which are not `Send`, e.g. on single-threaded
+/// WebAssembly/edge runtimes, see [ApiVersionLayer::local].
+#[derive(Debug, Clone)]
+pub struct LocalApiVersionLayer<const N: usize> {
+    config: Arc<Config<N>>,
+}
+
+impl<const N: usize, S> Layer<S> for LocalApiVersionLayer<N> {
+    type Service = LocalApiVersionService<N, S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        LocalApiVersionService {
+            inner,
+            config: self.config.clone(),
+        }
+    }
+}
+
+/// See [LocalApiVersionLayer].
+#[derive(Debug, Clone)]
+pub struct LocalApiVersionService<const N: usize, S> {
+    inner: S,
+    config: Arc<Config<N>>,
+}
+
+impl<const N: usize, S> Service<Request> for LocalApiVersionService<N, S>
+where
+    S: Service<Request, Response = Response> + Clone + 'static,
+    S::Future: 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request) -> Self::Future {
+        // Take the service which has been polled ready, leaving a clone in its place.
+        let clone = self.inner.clone();
+        let mut inner = mem::replace(&mut self.inner, clone);
+        let config = self.config.clone();
+
+        match config.dispatch(&mut request) {
+            Dispatch::Debug => Box::pin(async move { Ok(config.debug(request).await) }),
+            Dispatch::Respond(response) => Box::pin(async move { Ok(response) }),
+            Dispatch::Skip => Box::pin(inner.call(request)),
+            Dispatch::Versioned(negotiated) => {
+                config.rewrite(&mut request, &negotiated);
+                Box::pin(async move { config.call_inner(inner, request, negotiated).await })
+            }
+        }
+    }
+}
+
+/// Create a function for [axum::middleware::from_fn] with the configuration of the given layer,
+/// for those who prefer function middleware over tower layers. Like the [ApiVersionLayer], the
+/// resulting middleware must wrap the router, as rewriting the path after routing has no effect.
+///
+/// # Examples
+///
+/// ```
+/// # use api_version::{ApiVersionLayer, ApiVersions, middleware_fn};
+/// # use axum::{Router, middleware::from_fn, routing::get};
+/// # use tower::Layer;
+/// const API_VERSIONS: ApiVersions<2> = ApiVersions::new([0, 1]);
+///
+/// let app = Router::<()>::new()
+///     .route("/api/v0/test", get(|| async { "0" }))
+///     .route("/api/v1/test", get(|| async { "1" }));
+///
+/// let app = from_fn(middleware_fn(ApiVersionLayer::new("/api", API_VERSIONS))).layer(app);
+/// # let request = axum::extract::Request::new(axum::body::Body::empty());
+/// # let _ = tower::ServiceExt::oneshot(app, request);
+/// ```
+pub fn middleware_fn<const N: usize>(
+    layer: ApiVersionLayer<N>,
+) -> impl Fn(Request, Next) -> BoxFuture<'static, Response> + Clone + Send + Sync + 'static {
+    let config = Arc::new(layer.config);
+
+    move |request, next| {
+        let mut service = ApiVersionService {
+            inner: next,
+            config: config.clone(),
+        };
+        Box::pin(async move {
+            match service.call(request).await {
+                Ok(response) => response,
+                Err(error) => match error {},
+            }
+        })
+    }
+}
+
+/// Shared configuration of an [ApiVersionLayer] for applications which centralize their
+/// configuration in the router state: add it to the state, provide it via
+/// [FromRef](axum::extract::FromRef) and apply
+/// [middleware_with_state] with [axum::middleware::from_fn_with_state], such that the
+/// configuration is neither duplicated nor cloned per request. Clones are cheap. This is also a
+/// [Layer] itself, like the [ApiVersionLayer] it has been created from.
+///
+/// # Examples
+///
+/// ```
+/// # use api_version::{ApiVersionLayer, ApiVersionState, ApiVersions, middleware_with_state};
+/// # use axum::{Router, extract::FromRef, middleware::from_fn_with_state, routing::get};
+/// # use tower::Layer;
+/// const API_VERSIONS: ApiVersions<2> = ApiVersions::new([0, 1]);
+///
+/// #[derive(Clone)]
+/// struct AppState {
+///     versioning: ApiVersionState<2>,
+/// }
+///
+/// impl FromRef<AppState> for ApiVersionState<2> {
+///     fn from_ref(state: &AppState) -> Self {
+///         state.versioning.clone()
+///     }
+/// }
+///
+/// let state = AppState {
+///     versioning: ApiVersionLayer::new("/api", API_VERSIONS).into(),
+/// };
+/// let app = Router::new()
+///     .route("/api/v0/test", get(|| async { "0" }))
+///     .route("/api/v1/test", get(|| async { "1" }))
+///     .with_state(state.clone());
+///
+/// let app = from_fn_with_state(state, middleware_with_state::<2>).layer(app);
+/// # let request = axum::extract::Request::new(axum::body::Body::empty());
+/// # let _ = tower::ServiceExt::oneshot(app, request);
+/// ```
+#[derive(Debug, Clone)]
+pub struct ApiVersionState<const N: usize> {
+    config: Arc<Config<N>>,
+}
+
+impl<const N: usize> From<ApiVersionLayer<N>> for ApiVersionState<N> {
+    fn from(layer: ApiVersionLayer<N>) -> Self {
+        Self {
+            config: Arc::new(layer.config),
+        }
+    }
+}
+
+impl<const N: usize, S> Layer<S> for ApiVersionState<N> {
+    type Service = ApiVersionService<N, S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ApiVersionService {
+            inner,
+            config: self.config.clone(),
+        }
+    }
+}
+
+/// Middleware function for [axum::middleware::from_fn_with_state] reading the configuration from
+/// the router state, see [ApiVersionState]. Like the [ApiVersionLayer], the resulting middleware
+/// must wrap the router, as rewriting the path after routing has no effect.
+pub async fn middleware_with_state<const N: usize>(
+    State(state): State<ApiVersionState<N>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    match state.layer(next).call(request).await {
+        Ok(response) => response,
+        Err(error) => match error {},
+    }
+}
+
+/// Axum middleware to dispatch requests to per-version services, see
+/// [ApiVersionLayer::dispatch].
+#[derive(Debug, Clone)]
+pub struct ApiVersionDispatchLayer<const N: usize, V> {
+    config: Config<N>,
+    services: HashMap<u16, V>,
+}
+
+impl<const N: usize, S, V> Layer<S> for ApiVersionDispatchLayer<N, V>
+where
+    V: Clone,
+{
+    type Service = ApiVersionDispatch<N, S, V>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ApiVersionDispatch {
+            inner,
+            services: Arc::new(self.services.clone()),
+            config: Arc::new(self.config.clone()),
+        }
+    }
+}
+
+/// See [ApiVersionDispatchLayer].
+#[derive(Debug, Clone)]
+pub struct ApiVersionDispatch<const N: usize, S, V> {
+    inner: S,
+    services: Arc<HashMap<u16, V>>,
+    config: Arc<Config<N>>,
+}
+
+impl<const N: usize, S, V> Service<Request> for ApiVersionDispatch<N, S, V>
+where
+    S: Service<Request, Response = Response> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    V: Service<Request, Response = Response, Error = S::Error> + Clone + Send + 'static,
+    V::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request) -> Self::Future {
+        // Take the service which has been polled ready, leaving a clone in its place.
+        let clone = self.inner.clone();
+        let mut inner = mem::replace(&mut self.inner, clone);
+        let config = self.config.clone();
+
+        let negotiated = match config.dispatch(&mut request) {
+            Dispatch::Debug => return Box::pin(async move { Ok(config.debug(request).await) }),
+            Dispatch::Respond(response) => return Box::pin(async move { Ok(response) }),
+            Dispatch::Skip => return Box::pin(inner.call(request)),
+            Dispatch::Versioned(negotiated) => negotiated,
+        };
+
+        // Strip the version prefix and the base path if configured.
+        if config.rewrite_path
+            && let Some(uri) = replace_path(request.uri(), config.prefix(), None, &negotiated.path)
+        {
+            debug!(original_uri = %request.uri(), %uri, "rewrote the path");
+            request.uri_mut().clone_from(&uri);
+        }
+
+        let service = self
+            .services
+            .get(&negotiated.version)
+            .expect("there is a service for each version")
+            .clone();
+        Box::pin(async move { config.call_inner(service, request, negotiated).await })
+    }
+}
+
+/// Axum middleware to version requests by the layer for their path subtree, see
+/// [ApiVersionLayer::subtree].
+#[derive(Debug, Clone)]
+pub struct ApiVersionSubtreeLayer {
+    subtrees: Arc<[Arc<dyn Subtree>]>,
+}
+
+impl ApiVersionSubtreeLayer {
+    /// Add the given layer for a further path subtree, see [ApiVersionLayer::subtree].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the base path of the given layer is already used.
+    pub fn subtree<const N: usize>(self, layer: ApiVersionLayer<N>) -> Self {
+        let base_path = &layer.config.base_path;
+        assert!(
+            self.subtrees
+                .iter()
+                .all(|subtree| subtree.base_path() != base_path),
+            "base paths of subtrees must be unique"
+        );
+
+        let subtree = Arc::new(layer.config) as Arc<dyn Subtree>;
+        let subtrees = self.subtrees.iter().cloned().chain([subtree]).collect();
+        Self { subtrees }
+    }
+}
+
+impl<S> Layer<S> for ApiVersionSubtreeLayer {
+    type Service = ApiVersionSubtreeService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ApiVersionSubtreeService {
+            inner,
+            subtrees: self.subtrees.clone(),
+        }
+    }
+}
+
+/// See [ApiVersionSubtreeLayer].
+#[derive(Debug, Clone)]
+pub struct ApiVersionSubtreeService<S> {
+    inner: S,
+    subtrees: Arc<[Arc<dyn Subtree>]>,
+}
+
+impl<S> Service<Request> for ApiVersionSubtreeService<S>
+where
+    S: Service<Request, Response = Response, Error = Infallible> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = Infallible;
+    type Future = BoxFuture<'static, Result<Response, Infallible>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        // Take the service which has been polled ready, leaving a clone in its place.
+        let clone = self.inner.clone();
+        let mut inner = mem::replace(&mut self.inner, clone);
+
+        // Nested base paths are the longer ones, hence more specific.
+        let path = request.uri().path();
+        let subtree = self
+            .subtrees
+            .iter()
+            .filter(|subtree| {
+                path.strip_prefix(subtree.base_path())
+                    .is_some_and(|path| path.is_empty() || path.starts_with('/'))
+            })
+            .max_by_key(|subtree| subtree.base_path().len());
+
+        match subtree {
+            Some(subtree) => subtree.clone().call(request, SubtreeInner::new(inner)),
+            None => Box::pin(inner.call(request)),
+        }
+    }
+}
+
+/// Versioning of a path subtree, erasing the number of versions.
+trait Subtree: Debug + Send + Sync + 'static {
+    fn base_path(&self) -> &str;
+
+    fn call(
+        self: Arc<Self>,
+        request: Request,
+        inner: SubtreeInner,
+    ) -> BoxFuture<'static, Result<Response, Infallible>>;
+}
+
+impl<const N: usize> Subtree for Config<N> {
+    fn base_path(&self) -> &str {
+        &self.base_path
+    }
+
+    fn call(
+        self: Arc<Self>,
+        request: Request,
+        inner: SubtreeInner,
+    ) -> BoxFuture<'static, Result<Response, Infallible>> {
+        ApiVersionService {
+            inner,
+            config: self,
+        }
+        .call(request)
+    }
+}
+
+/// Type-erased inner service of an [ApiVersionSubtreeService].
+#[derive(Clone)]
+struct SubtreeInner(
+    Arc<dyn Fn(Request) -> BoxFuture<'static, Result<Response, Infallible>> + Send + Sync>,
+);
+
+impl SubtreeInner {
+    fn new<S>(inner: S) -> Self
+    where
+        S: Service<Request, Response = Response, Error = Infallible> + Clone + Send + 'static,
+        S::Future: Send + 'static,
+    {
+        let inner = Mutex::new(inner);
+        Self(Arc::new(move |request| {
+            let inner = inner.lock().unwrap_or_else(PoisonError::into_inner).clone();
+            Box::pin(inner.oneshot(request))
+        }))
+    }
+}
+
+impl Service<Request> for SubtreeInner {
+    type Response = Response;
+    type Error = Infallible;
+    type Future = BoxFuture<'static, Result<Response, Infallible>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        (self.0)(request)
+    }
+}
+
+/// Simulated request for the debug endpoint, see [ApiVersionLayer::debug_endpoint].
+#[derive(Debug, Deserialize)]
+struct DebugRequest {
+    #[serde(default = "default_debug_method")]
+    method: String,
+    path: String,
+    #[serde(default)]
+    headers: HashMap<String, String>,
+}
+
+fn default_debug_method() -> String {
+    Method::GET.to_string()
+}
+
+/// Request negotiated by [Config::decide].
+struct Negotiated {
+    version: u16,
+    source: VersionSource,
+    /// The path with the base path and version prefix, if any, stripped.
+    path: String,
+    /// Whether the path has been aliased, see [ApiVersionLayer::path_alias].
+    aliased: bool,
+    /// Whether the request is a gRPC-Web or Connect request, see [RpcPolicy].
+    rpc: bool,
+}
+
+/// Outcome of [Config::negotiate].
+enum Negotiation {
+    /// The request is not subject to versioning.
+    Skipped,
+
+    /// The negotiation failed with the given response.
+    Failed(Box<Response>),
+
+    /// The negotiation succeeded.
+    Negotiated(Negotiated),
+}
+
+/// Outcome of [Config::dispatch].
+enum Dispatch {
+    /// Respond via the debug endpoint, see [ApiVersionLayer::debug_endpoint].
+    Debug,
+
+    /// Respond with the given response without calling the inner service.
+    Respond(Response),
+
+    /// Call the inner service with the unchanged request.
+    Skip,
+
+    /// Call the inner service with the request for the negotiated version, rewritten as needed.
+    Versioned(Negotiated),
+}
+
+/// Negotiation errors, see [ApiVersionLayer::error_message]; the [Display] implementation gives
+/// the default English message.
+///
+/// Negotiation error responses carry the error as response extension, such that outer layers can
+/// pattern-match and convert it, e.g. into the application's own error type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum VersioningError {
+    /// The requested version is not one of the API versions.
+    UnknownVersion(u16),
+
+    /// The requested version is retired, see [ApiVersionLayer::sunset].
+    RetiredVersion(u16),
+
+    /// The `"x-api-version"` header is invalid, see [ApiVersionLayer::strict].
+    InvalidVersionHeader,
+
+    /// The request is for the base path itself, see [ApiVersionLayer::strict].
+    Unversioned,
+
+    /// The request path contains dot segments, see [ApiVersionLayer::dot_segment_policy].
+    DotSegments,
+}
+
+impl VersioningError {
+    /// The status code for responses for this kind of error.
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            VersioningError::UnknownVersion(_) => StatusCode::NOT_FOUND,
+            VersioningError::RetiredVersion(_) => StatusCode::GONE,
+            VersioningError::InvalidVersionHeader => StatusCode::BAD_REQUEST,
+            VersioningError::Unversioned => StatusCode::NOT_FOUND,
+            VersioningError::DotSegments => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    /// The machine-readable reason code for this kind of error, see [X_API_VERSION_ERROR].
+    pub fn reason(&self) -> &'static str {
+        match self {
+            VersioningError::UnknownVersion(_) => wire::UNKNOWN_VERSION,
+            VersioningError::RetiredVersion(_) => wire::RETIRED,
+            VersioningError::InvalidVersionHeader => wire::INVALID_HEADER,
+            VersioningError::Unversioned => wire::UNVERSIONED,
+            VersioningError::DotSegments => wire::DOT_SEGMENTS,
+        }
+    }
+}
+
+impl Error for VersioningError {}
+
+impl IntoResponse for VersioningError {
+    fn into_response(self) -> Response {
+        let mut response = error_response(self.status_code(), self.reason(), self.to_string());
+        response.extensions_mut().insert(self);
+        response
+    }
+}
+
+impl Display for VersioningError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            VersioningError::UnknownVersion(version) => write!(f, "unknown version '{version}'"),
+            VersioningError::RetiredVersion(version) => write!(f, "retired version '{version}'"),
+            VersioningError::InvalidVersionHeader => write!(f, "invalid version header"),
+            VersioningError::Unversioned => write!(f, "unversioned path"),
+            VersioningError::DotSegments => write!(f, "path with dot segments"),
+        }
+    }
+}
+
+/// Rate limit policy invoked with the client key, if any, and the resolved version, see
+/// [ApiVersionLayer::rate_limit_policy]. This is implemented for suitable closures.
+pub trait RateLimitPolicy: Send + Sync + 'static {
+    /// Decide whether a request from the given client for the given version is allowed.
+    fn check(&self, client_key: Option<&str>, version: u16) -> RateLimitDecision;
+}
+
+impl<F> RateLimitPolicy for F
+where
+    F: Fn(Option<&str>, u16) -> RateLimitDecision + Send + Sync + 'static,
+{
+    fn check(&self, client_key: Option<&str>, version: u16) -> RateLimitDecision {
+        self(client_key, version)
+    }
+}
+
+/// Policy for the version prefix in the `"location"` header of redirect responses, see
+/// [ApiVersionLayer::redirect_policy].
+#[derive(Debug, Clone, Copy)]
+#[non_exhaustive]
+pub enum RedirectPolicy {
+    /// Keep the version prefix, e.g. `"/api/v1/test"`.
+    Keep,
+
+    /// Strip the version prefix, e.g. `"/api/test"` for `"/api/v1/test"`.
+    Strip,
+
+    /// Replace the version prefix with the path segment the given function returns for the
+    /// version, e.g. `"/api/2024-01/test"` for `"/api/v1/test"`. The version prefix is kept if
+    /// the returned segment is empty or not a valid path segment, e.g. `"release 1"`.
+    Map(fn(u16) -> String),
+}
+
+/// Policy for gRPC-Web and Connect requests, see [ApiVersionLayer::rpc_policy].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum RpcPolicy {
+    /// Version these requests like any other request.
+    Version,
+
+    /// Do not version these requests at all.
+    Skip,
+
+    /// Negotiate the version, i.e. add the request extensions and response headers, but do not
+    /// rewrite the path.
+    MetadataOnly,
+}
+
+/// Strategy for choosing among multiple versions offered by the client, see
+/// [ApiVersionLayer::negotiation_strategy].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum NegotiationStrategy {
+    /// Choose the first offered version, whether supported or not.
+    PreferFirst,
+
+    /// Choose the lowest offered version which is supported, i.e. one of the API versions and
+    /// not retired, falling back to the first offered version if none is supported.
+    PreferLowestCompatible,
+}
+
+/// Policy for request paths with dot segments, see [ApiVersionLayer::dot_segment_policy].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DotSegmentPolicy {
+    /// Pass these requests on unchanged, e.g. if an outer layer already takes care of them.
+    Pass,
+
+    /// Remove the dot segments (RFC 3986, section 5.2.4) before versioning, e.g.
+    /// `"/api/v0/test"` for `"/api/v1/../v0/test"`.
+    Normalize,
+
+    /// Reject these requests with `400 Bad Request`.
+    Reject,
+}
+
+/// Decision of a [RateLimitPolicy].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum RateLimitDecision {
+    /// Allow the request.
+    Allow,
+
+    /// Deny the request with `429 Too Many Requests`.
+    Deny,
+
+    /// Allow the request after the given delay. Requires the `"tokio"` feature.
+    #[cfg(feature = "tokio")]
+    Slowdown(Duration),
+}
+
+/// Clock for time-based features like [ApiVersionLayer::sunset], see [ApiVersionLayer::clock].
+/// This is implemented for suitable closures, which allows for controlling time in tests.
+///
+/// # Examples
+///
+/// ```
+/// # use api_version::{ApiVersionLayer, ApiVersions};
+/// # use std::time::{Duration, UNIX_EPOCH};
+/// const API_VERSIONS: ApiVersions<2> = ApiVersions::new([0, 1]);
+///
+/// let layer = ApiVersionLayer::new("/api", API_VERSIONS)
+///     .clock(|| UNIX_EPOCH + Duration::from_secs(1_800_000_000));
+/// ```
+pub trait Clock: Send + Sync + 'static {
+    /// The current time.
+    fn now(&self) -> SystemTime;
+}
+
+impl<F> Clock for F
+where
+    F: Fn() -> SystemTime + Send + Sync + 'static,
+{
+    fn now(&self) -> SystemTime {
+        self()
+    }
+}
+
+/// The default [Clock] based on the system time.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// Health signal for versions, see [ApiVersionLayer::version_health]. This is implemented for
+/// suitable closures.
+pub trait VersionHealth: Send + Sync + 'static {
+    /// Check whether the given version is healthy.
+    fn is_healthy(&self, version: u16) -> bool;
+}
+
+impl<F> VersionHealth for F
+where
+    F: Fn(u16) -> bool + Send + Sync + 'static,
+{
+    fn is_healthy(&self, version: u16) -> bool {
+        self(version)
+    }
+}
+
+/// [VersionHealth] based on error rates reported by the application: a version is unhealthy while
+/// its last reported error rate exceeds the threshold. Clones share their state.
+#[derive(Clone)]
+pub struct CircuitBreaker {
+    threshold: f64,
+    error_rates: Arc<RwLock<HashMap<u16, f64>>>,
+}
+
+impl CircuitBreaker {
+    /// Create a circuit breaker with the given error rate threshold, e.g. `0.05` for 5%.
+    pub fn new(threshold: f64) -> Self {
+        Self {
+            threshold,
+            error_rates: Arc::default(),
+        }
+    }
+
+    /// Report the current error rate for the given version.
+    pub fn report_error_rate(&self, version: u16, error_rate: f64) {
+        self.error_rates
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(version, error_rate);
+    }
+}
+
+impl Debug for CircuitBreaker {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let error_rates = self
+            .error_rates
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        f.debug_struct("CircuitBreaker")
+            .field("threshold", &self.threshold)
+            .field("error_rates", &sorted(&error_rates))
+            .finish()
+    }
+}
+
+impl VersionHealth for CircuitBreaker {
+    fn is_healthy(&self, version: u16) -> bool {
+        self.error_rates
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&version)
+            .is_none_or(|&error_rate| error_rate <= self.threshold)
+    }
+}
+
+/// Graceful drain of retired versions, see [ApiVersionLayer::drain]. Clones share the same pinned
+/// clients and metrics.
+///
+/// At most [Drain::capacity] clients are pinned, such that clients sending a new client key with
+/// every request cannot grow the pinned clients without limit. The pinned clients of a version
+/// are dropped once its drain window has passed.
+#[derive(Clone)]
+pub struct Drain {
+    window: Duration,
+    capacity: usize,
+    pinned_clients: Arc<RwLock<HashMap<u16, HashSet<String>>>>,
+    pinned_count: Arc<AtomicUsize>,
+    drained_requests: Arc<RwLock<HashMap<u16, AtomicU64>>>,
+}
+
+impl Debug for Drain {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Drain")
+            .field("window", &self.window)
+            .field("capacity", &self.capacity)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drain {
+    /// Default maximum number of pinned clients, see [Drain::capacity].
+    pub const DEFAULT_CAPACITY: usize = 10_000;
+
+    /// Create a drain with the given window after the sunset.
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            capacity: Self::DEFAULT_CAPACITY,
+            pinned_clients: Arc::default(),
+            pinned_count: Arc::default(),
+            drained_requests: Arc::default(),
+        }
+    }
+
+    /// Pin at most the given number of clients across all versions, defaults to
+    /// [Drain::DEFAULT_CAPACITY]. Once reached, further clients are not pinned, hence get `410
+    /// Gone` after the sunset.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// The number of clients pinned to the given version.
+    pub fn pinned_clients(&self, version: u16) -> usize {
+        self.pinned_clients
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&version)
+            .map_or(0, HashSet::len)
+    }
+
+    /// Pin the client with the given client key to the given version, unless the capacity is
+    /// reached. Clients already pinned only take the read lock.
+    fn pin(&self, version: u16, client_key: &str) {
+        if self.is_pinned(version, client_key) || self.is_full() {
+            return;
+        }
+
+        // The count is only changed while holding the write lock, hence checked again under it.
+        let mut pinned_clients = self
+            .pinned_clients
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        if !self.is_full()
+            && pinned_clients
+                .entry(version)
+                .or_default()
+                .insert(client_key.to_owned())
+        {
+            self.pinned_count.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Check whether the capacity is reached.
+    fn is_full(&self) -> bool {
+        self.pinned_count.load(Ordering::Relaxed) >= self.capacity
+    }
+
+    /// Check whether the client with the given client key is pinned to the given version.
+    fn is_pinned(&self, version: u16, client_key: &str) -> bool {
+        self.pinned_clients
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&version)
+            .is_some_and(|clients| clients.contains(client_key))
+    }
+
+    /// Drop the clients pinned to the given version, e.g. once its drain window has passed.
+    fn unpin_all(&self, version: u16) {
+        let is_pinned = self
+            .pinned_clients
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .contains_key(&version);
+        if !is_pinned {
+            return;
+        }
+
+        let clients = self
+            .pinned_clients
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&version);
+        if let Some(clients) = clients {
+            self.pinned_count
+                .fetch_sub(clients.len(), Ordering::Relaxed);
+        }
+    }
+
+    /// Count a request for the given version served after its sunset. Only the first request for
+    /// a version takes the write lock.
+    fn count_drained_request(&self, version: u16) {
+        if let Some(count) = self
+            .drained_requests
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&version)
+        {
+            count.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+
+        self.drained_requests
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry(version)
+            .or_default()
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The number of requests for the given version served after its sunset, i.e. the remaining
+    /// traffic to be drained.
+    pub fn drained_requests(&self, version: u16) -> u64 {
+        self.drained_requests
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&version)
+            .map_or(0, |count| count.load(Ordering::Relaxed))
+    }
+}
+
+/// Per-version limits of requests in flight, see [ApiVersionLayer::concurrency_limits]. Clones
+/// share the same requests in flight. Requires the `"tokio"` feature.
+#[cfg(feature = "tokio")]
+#[derive(Clone)]
+pub struct ConcurrencyLimits {
+    limits: Arc<HashMap<u16, (usize, Arc<tokio::sync::Semaphore>)>>,
+}
+
+#[cfg(feature = "tokio")]
+impl Debug for ConcurrencyLimits {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let limits = self
+            .limits
+            .iter()
+            .map(|(&version, &(limit, _))| (version, limit))
+            .collect::<BTreeMap<_, _>>();
+        f.debug_struct("ConcurrencyLimits")
+            .field("limits", &limits)
+            .finish()
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl ConcurrencyLimits {
+    /// Create limits from the given pairs of version and maximum number of requests in flight.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any limit is zero.
+    pub fn new(limits: impl IntoIterator<Item = (u16, usize)>) -> Self {
+        let limits = limits
+            .into_iter()
+            .map(|(version, limit)| {
+                assert!(limit > 0, "concurrency limit must be positive");
+                let semaphore = Arc::new(tokio::sync::Semaphore::new(limit));
+                (version, (limit, semaphore))
+            })
+            .collect();
+        Self {
+            limits: Arc::new(limits),
+        }
+    }
+
+    /// The number of requests for the given version currently in flight, i.e. holding one of
+    /// its permits, if limited.
+    pub fn in_flight(&self, version: u16) -> usize {
+        self.limits
+            .get(&version)
+            .map(|(limit, semaphore)| limit - semaphore.available_permits())
+            .unwrap_or_default()
+    }
+
+    /// Wait for a permit for a request for the given version, if limited.
+    async fn acquire(&self, version: u16) -> Option<tokio::sync::OwnedSemaphorePermit> {
+        let (_, semaphore) = self.limits.get(&version)?;
+        semaphore.clone().acquire_owned().await.ok()
+    }
+}
+
+/// Budget for requests for deprecated versions, see [ApiVersionLayer::deprecation_budget]:
+/// versioned requests are counted in consecutive windows and if the share of requests for
+/// deprecated versions within a window exceeds the threshold, the alert is invoked in a
+/// background task with a [DeprecationAlert]. A window is evaluated with the first request after
+/// it has ended. Clones share the same windows. Requires the `"tokio"` feature.
+#[cfg(feature = "tokio")]
+#[derive(Clone)]
+pub struct DeprecationBudget {
+    threshold: f64,
+    window: Duration,
+    alert: Arc<dyn Fn(DeprecationAlert) -> BoxFuture<'static, ()> + Send + Sync>,
+    usage: Arc<Mutex<Option<DeprecationAlert>>>,
+}
+
+#[cfg(feature = "tokio")]
+impl Debug for DeprecationBudget {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DeprecationBudget")
+            .field("threshold", &self.threshold)
+            .field("window", &self.window)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl DeprecationBudget {
+    /// Create a budget with the given threshold, i.e. the share of requests for deprecated
+    /// versions, e.g. `0.01` for 1%, the given window and the given alert.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the threshold is not between 0 and 1 or the window is zero.
+    pub fn new<F>(
+        threshold: f64,
+        window: Duration,
+        alert: impl Fn(DeprecationAlert) -> F + Send + Sync + 'static,
+    ) -> Self
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        assert!(
+            (0.0..=1.0).contains(&threshold),
+            "deprecation budget threshold must be between 0 and 1"
+        );
+        assert!(
+            !window.is_zero(),
+            "deprecation budget window must not be zero"
+        );
+        Self {
+            threshold,
+            window,
+            alert: Arc::new(move |summary| Box::pin(alert(summary))),
+            usage: Arc::default(),
+        }
+    }
+
+    /// Count a request for the given version at the given time, alerting for the previous window
+    /// if it has ended and exceeded the budget.
+    fn track(&self, now: SystemTime, version: u16, deprecated: bool) {
+        let mut usage = self.usage.lock().unwrap_or_else(PoisonError::into_inner);
+
+        let ended = usage.as_ref().is_some_and(|usage| {
+            now.duration_since(usage.start)
+                .is_ok_and(|elapsed| elapsed >= self.window)
+        });
+        if ended
+            && let Some(summary) = usage.take()
+            && summary.deprecated_share() > self.threshold
+        {
+            debug!(?summary, "deprecation budget exceeded");
+            tokio::spawn((self.alert)(summary));
+        }
+
+        let usage = usage.get_or_insert_with(|| DeprecationAlert {
+            start: now,
+            window: self.window,
+            requests: 0,
+            deprecated_requests: BTreeMap::new(),
+        });
+        usage.requests += 1;
+        if deprecated {
+            *usage.deprecated_requests.entry(version).or_default() += 1;
+        }
+    }
+}
+
+/// Summary of a window which has exceeded its [DeprecationBudget].
+#[cfg(feature = "tokio")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct DeprecationAlert {
+    /// The start of the window, i.e. the time of its first request.
+    pub start: SystemTime,
+
+    /// The length of the window.
+    pub window: Duration,
+
+    /// The number of versioned requests within the window.
+    pub requests: u64,
+
+    /// The number of requests for deprecated versions within the window by version.
+    pub deprecated_requests: BTreeMap<u16, u64>,
+}
+
+#[cfg(feature = "tokio")]
+impl DeprecationAlert {
+    /// The share of requests for deprecated versions within the window.
+    pub fn deprecated_share(&self) -> f64 {
+        let deprecated_requests = self.deprecated_requests.values().sum::<u64>();
+        deprecated_requests as f64 / self.requests.max(1) as f64
+    }
+}
+
+#[derive(Clone)]
+struct Config<const N: usize> {
+    base_path: String,
+    versions: ApiVersions<N>,
+    strip_base_path: bool,
+    rewrite_path: bool,
+    rewrite_template: Option<template::RewriteTemplate>,
+    path_aliases: HashMap<u16, Vec<(String, String)>>,
+    echo_version: bool,
+    version_request_header: Option<HeaderName>,
+    version_baggage: bool,
+    skip_streaming_responses: bool,
+    max_body_sizes: HashMap<u16, usize>,
+    timeouts: HashMap<u16, Duration>,
+    client_key_header: Option<HeaderName>,
+    rate_limit_policy: Option<Arc<dyn RateLimitPolicy>>,
+    cache_controls: HashMap<u16, HeaderValue>,
+    cors_policies: HashMap<u16, cors::CorsPolicy>,
+    version_etags: bool,
+    redirect_policy: RedirectPolicy,
+    forwarded_prefix: bool,
+    deprecations: HashMap<u16, SystemTime>,
+    sunsets: HashMap<u16, SystemTime>,
+    sunset_countdown: bool,
+    clock: Arc<dyn Clock>,
+    default_version: u16,
+    default_version_advisory: Option<(u16, SystemTime)>,
+    default_schedule: Vec<(SystemTime, u16)>,
+    default_ramp: Option<state::Rollout>,
+    default_experiment: Option<DefaultExperiment>,
+    state_store: Option<Arc<dyn state::VersionStateStore>>,
+    version_health: Option<Arc<dyn VersionHealth>>,
+    drain: Option<Drain>,
+    #[cfg(feature = "tokio")]
+    concurrency_limits: Option<ConcurrencyLimits>,
+    #[cfg(feature = "tokio")]
+    deprecation_budget: Option<DeprecationBudget>,
+    #[cfg(feature = "tokio")]
+    auditor: Option<audit::Auditor>,
+    internal_versions: Vec<u16>,
+    internal_access_header: Option<HeaderName>,
+    error_message: Option<ErrorMessage>,
+    skipped_methods: Vec<Method>,
+    rpc_policy: RpcPolicy,
+    dot_segment_policy: DotSegmentPolicy,
+    strict: bool,
+    max_version_header_values: usize,
+    version_states: Vec<VersionStateInjector>,
+    prefer_header: bool,
+    vendor: Option<String>,
+    negotiation_strategy: NegotiationStrategy,
+    #[cfg(feature = "json-transform")]
+    request_transforms: HashMap<u16, transform::JsonTransform>,
+    #[cfg(feature = "json-transform")]
+    response_transforms: HashMap<u16, transform::JsonTransform>,
+    #[cfg(feature = "json-transform")]
+    transform_streaming_requests: bool,
+    map_response: Option<MapResponse>,
+    debug_endpoint: bool,
+    #[cfg(feature = "chaos")]
+    chaos: Option<chaos::Chaos>,
+    discovery_endpoint: bool,
+    html_index: Option<HtmlIndex>,
+    serializers: Vec<(HeaderValue, Arc<dyn serialize::Serializer>)>,
+    changelogs: HashMap<u16, String>,
+    capabilities: Vec<capability::Capability>,
+    migration_guides: Vec<manifest::MigrationGuide>,
+    manifest_metadata: serde_json::Map<String, serde_json::Value>,
+    policies: Vec<policy::PolicyLink>,
+}
+
+impl<const N: usize> Debug for Config<N> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let mut f = f.debug_struct("Config");
+        f.field("base_path", &self.base_path)
+            .field("versions", &self.versions)
+            .field("strip_base_path", &self.strip_base_path)
+            .field("rewrite_path", &self.rewrite_path)
+            .field(
+                "rewrite_template",
+                &self.rewrite_template.as_ref().map(|t| t.as_str()),
+            )
+            .field("path_aliases", &sorted(&self.path_aliases))
+            .field("echo_version", &self.echo_version)
+            .field("version_request_header", &self.version_request_header)
+            .field("version_baggage", &self.version_baggage)
+            .field("skip_streaming_responses", &self.skip_streaming_responses)
+            .field("max_body_sizes", &sorted(&self.max_body_sizes))
+            .field("timeouts", &sorted(&self.timeouts))
+            .field("client_key_header", &self.client_key_header)
+            .field("rate_limit_policy", &self.rate_limit_policy.is_some())
+            .field("cache_controls", &sorted(&self.cache_controls))
+            .field("cors_policies", &sorted(&self.cors_policies))
+            .field("version_etags", &self.version_etags)
+            .field("redirect_policy", &self.redirect_policy)
+            .field("forwarded_prefix", &self.forwarded_prefix)
+            .field("deprecations", &sorted(&self.deprecations))
+            .field("sunsets", &sorted(&self.sunsets))
+            .field("sunset_countdown", &self.sunset_countdown)
+            .field("default_version", &self.default_version)
+            .field("default_version_advisory", &self.default_version_advisory)
+            .field("default_schedule", &self.default_schedule)
+            .field("default_ramp", &self.default_ramp)
+            .field("default_experiment", &self.default_experiment)
+            .field("state_store", &self.state_store.is_some())
+            .field("version_health", &self.version_health.is_some())
+            .field("drain", &self.drain)
+            .field("internal_versions", &self.internal_versions)
+            .field("internal_access_header", &self.internal_access_header)
+            .field("error_message", &self.error_message.is_some())
+            .field("skipped_methods", &self.skipped_methods)
+            .field("rpc_policy", &self.rpc_policy)
+            .field("dot_segment_policy", &self.dot_segment_policy)
+            .field("strict", &self.strict)
+            .field("max_version_header_values", &self.max_version_header_values)
+            .field("version_states", &self.version_states.len())
+            .field("prefer_header", &self.prefer_header)
+            .field("vendor", &self.vendor)
+            .field("negotiation_strategy", &self.negotiation_strategy);
+        #[cfg(feature = "tokio")]
+        f.field("concurrency_limits", &self.concurrency_limits)
+            .field("deprecation_budget", &self.deprecation_budget)
+            .field("auditor", &self.auditor.is_some());
+        #[cfg(feature = "json-transform")]
+        f.field("request_transforms", &sorted(&self.request_transforms))
+            .field("response_transforms", &sorted(&self.response_transforms))
+            .field(
+                "transform_streaming_requests",
+                &self.transform_streaming_requests,
+            );
+        f.field("map_response", &self.map_response.is_some())
+            .field("debug_endpoint", &self.debug_endpoint);
+        #[cfg(feature = "chaos")]
+        f.field("chaos", &self.chaos);
+        f.field("discovery_endpoint", &self.discovery_endpoint)
+            .field("html_index", &self.html_index.is_some())
+            .field(
+                "serializers",
+                &self
+                    .serializers
+                    .iter()
+                    .map(|(media_type, _)| media_type)
+                    .collect::<Vec<_>>(),
+            )
+            .field("changelogs", &sorted(&self.changelogs))
+            .field("capabilities", &self.capabilities)
+            .field("migration_guides", &self.migration_guides)
+            .field("manifest_metadata", &self.manifest_metadata)
+            .field("policies", &self.policies)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The given map sorted by version, e.g. for deterministic `Debug` output.
+fn sorted<V>(map: &HashMap<u16, V>) -> BTreeMap<u16, &V> {
+    map.iter()
+        .map(|(&version, value)| (version, value))
+        .collect()
+}
+
+type ErrorMessage = Arc<dyn Fn(VersioningError, &Request) -> String + Send + Sync>;
+
+/// Default limit for buffering request bodies to be transformed, see
+/// [ApiVersionLayer::request_transform].
+#[cfg(feature = "json-transform")]
+const DEFAULT_TRANSFORM_LIMIT: usize = 2 * 1024 * 1024;
+
+type VersionStateInjector = Arc<dyn Fn(u16, &mut Extensions) + Send + Sync>;
+
+type MapResponse = Arc<dyn Fn(u16, &mut Response) + Send + Sync>;
+
+type HtmlIndex = Arc<dyn Fn(&manifest::VersionManifest) -> String + Send + Sync>;
+
+impl<const N: usize> Config<N> {
+    /// The prefix for rewritten paths, i.e. the base path unless to be stripped.
+    fn prefix(&self) -> &str {
+        if self.strip_base_path {
+            ""
+        } else {
+            &self.base_path
+        }
+    }
+
+    /// Decide whether a request with the given method, URI and headers is versioned and if so,
+    /// for which version, see [policy].
+    fn decide(
+        &self,
+        method: &Method,
+        uri: &Uri,
+        headers: &HeaderMap,
+        internal: bool,
+    ) -> Result<Option<Negotiated>, VersioningError> {
+        let Some((path, version, source)) = self.apply_policies(method, uri, headers, internal)?
+        else {
+            return Ok(None);
+        };
+        let rpc = is_rpc(headers);
+
+        let (path, aliased) = match self.aliased_path(version, &path) {
+            Some(aliased_path) => (aliased_path, true),
+            None => (path, false),
+        };
+
+        Ok(Some(Negotiated {
+            version,
+            source,
+            path,
+            aliased,
+            rpc,
+        }))
+    }
+
+    /// The given path, i.e. without base path and version prefix, with the first matching path
+    /// alias for the given version applied, if any, see [ApiVersionLayer::path_alias].
+    fn aliased_path(&self, version: u16, path: &str) -> Option<String> {
+        self.path_aliases
+            .get(&version)?
+            .iter()
+            .find_map(|(alias, target)| {
+                path.strip_prefix(alias.as_str())
+                    .filter(|rest| rest.is_empty() || rest.starts_with('/'))
+                    .map(|rest| format!("{target}{rest}"))
+            })
+    }
+
+    /// The given URI without dot segments, if it has to be normalized, see
+    /// [ApiVersionLayer::dot_segment_policy].
+    fn without_dot_segments(&self, uri: &Uri) -> Result<Option<Uri>, VersioningError> {
+        if self.dot_segment_policy == DotSegmentPolicy::Pass {
+            return Ok(None);
+        }
+        let Some(path) = remove_dot_segments(uri.path()) else {
+            return Ok(None);
+        };
+
+        let is_versioned = |path: &str| {
+            path.strip_prefix(self.base_path.as_str())
+                .is_some_and(|path| path.is_empty() || path.starts_with('/'))
+        };
+        if !is_versioned(uri.path()) && !is_versioned(&path) {
+            return Ok(None);
+        }
+
+        match self.dot_segment_policy {
+            DotSegmentPolicy::Reject => {
+                debug!(%uri, "rejecting path with dot segments");
+                Err(VersioningError::DotSegments)
+            }
+
+            _ => {
+                let Some(normalized_uri) = replace_path(uri, "", None, &path) else {
+                    return Ok(None);
+                };
+                debug!(original_uri = %uri, uri = %normalized_uri, "removed dot segments");
+                Ok(Some(normalized_uri))
+            }
+        }
+    }
+
+    /// The version of the `"api-version"` preference of the `"prefer"` header, if enabled.
+    fn preferred_version(&self, headers: &HeaderMap) -> Option<u16> {
+        if !self.prefer_header {
+            return None;
+        }
+        headers
+            .get_all(&PREFER)
+            .iter()
+            .filter_map(|prefer| prefer.to_str().ok())
+            .flat_map(|prefer| prefer.split(','))
+            .find_map(|preference| {
+                let (token, value) = preference.split(';').next()?.split_once('=')?;
+                if !token.trim().eq_ignore_ascii_case("api-version") {
+                    return None;
+                }
+                let value = value.trim().trim_matches('"');
+                if value.starts_with('v') {
+                    version::parse(value)
+                } else {
+                    version::parse(&format!("v{value}"))
+                }
+            })
+    }
+
+    /// The version of the vendor media types in the `"accept"` header chosen according to the
+    /// negotiation strategy, if configured.
+    fn media_type_version(&self, headers: &HeaderMap, internal: bool) -> Option<u16> {
+        let vendor = self.vendor.as_deref()?;
+        let mut versions = headers
+            .get_all(ACCEPT)
+            .iter()
+            .filter_map(|accept| accept.to_str().ok())
+            .flat_map(|accept| accept.split(','))
+            .filter_map(|media_range| {
+                let media_type = media_range.split(';').next()?.trim();
+                media_type
+                    .strip_prefix("application/vnd.")?
+                    .strip_prefix(vendor)?
+                    .strip_prefix('.')?
+                    .strip_suffix("+json")
+                    .and_then(version::parse)
+            });
+
+        match self.negotiation_strategy {
+            NegotiationStrategy::PreferFirst => versions.next(),
+
+            NegotiationStrategy::PreferLowestCompatible => {
+                let first = versions.next()?;
+                [first]
+                    .into_iter()
+                    .chain(versions)
+                    .filter(|version| {
+                        self.versions.contains(version)
+                            && self.is_visible(*version, internal)
+                            && !self.is_retired(*version)
+                    })
+                    .min()
+                    .or(Some(first))
+            }
+        }
+    }
+
+    /// Decide how to handle the given request: respond via the debug or discovery endpoints, skip
+    /// it or negotiate its version. Rewriting the request is left to the caller, as it depends on
+    /// whether the version is inserted into or stripped from the path.
+    fn dispatch(&self, request: &mut Request) -> Dispatch {
+        if self.debug_endpoint
+            && request.method() == Method::POST
+            && request.uri().path() == format!("{}/_version-debug", self.base_path)
+        {
+            return Dispatch::Debug;
+        }
+
+        if self.discovery_endpoint
+            && request.method() == Method::GET
+            && request.uri().path() == format!("{}/_versions", self.base_path)
+        {
+            let response = match &self.html_index {
+                Some(html_index) if serialize::prefers(request.headers(), "text/html") => {
+                    Html(html_index(&self.manifest())).into_response()
+                }
+
+                _ => serialize::respond(&self.serializers, request.headers(), self.manifest()),
+            };
+            return Dispatch::Respond(response);
+        }
+
+        if self.discovery_endpoint
+            && request.method() == Method::OPTIONS
+            && request.uri().path().trim_end_matches('/') == self.base_path
+            && !cors::is_preflight(request.method(), request.headers())
+        {
+            return Dispatch::Respond(self.capabilities_response());
+        }
+
+        match self.negotiate(request) {
+            Negotiation::Skipped => Dispatch::Skip,
+            Negotiation::Failed(response) => Dispatch::Respond(*response),
+            Negotiation::Negotiated(negotiated) => Dispatch::Versioned(negotiated),
+        }
+    }
+
+    /// Negotiate the version for the given request, see [Config::negotiate_unaudited], and record
+    /// the decision, if configured, see [ApiVersionLayer::audit_sink].
+    fn negotiate(&self, request: &mut Request) -> Negotiation {
+        #[cfg(feature = "tokio")]
+        if let Some(auditor) = &self.auditor {
+            let method = request.method().to_owned();
+            let uri = request.uri().to_owned();
+            let client_key = self.client_key(request.headers()).map(ToOwned::to_owned);
+            let negotiation = self.negotiate_unaudited(request);
+            let outcome = match &negotiation {
+                Negotiation::Skipped => audit::NegotiationOutcome::Skipped,
+
+                Negotiation::Failed(response) => audit::NegotiationOutcome::Failed(
+                    *response
+                        .extensions()
+                        .get()
+                        .expect("negotiation error response carries error"),
+                ),
+
+                Negotiation::Negotiated(negotiated) => audit::NegotiationOutcome::Negotiated {
+                    version: negotiated.version,
+                    source: negotiated.source,
+                },
+            };
+            auditor.send(audit::NegotiationRecord {
+                at: self.clock.now(),
+                method,
+                uri,
+                client_key,
+                outcome,
+            });
+            return negotiation;
+        }
+
+        self.negotiate_unaudited(request)
+    }
+
+    /// Negotiate the version for the given request and add the [ResolvedApiVersion] and
+    /// [VersionSource] extensions as well as the version request header, if configured.
+    fn negotiate_unaudited(&self, request: &mut Request) -> Negotiation {
+        if request.extensions().get::<NoVersioning>().is_some() {
+            debug!(uri = %request.uri(), "not rewriting the path, because opted out");
+            return Negotiation::Skipped;
+        }
+
+        // Installing the layer twice, e.g. at the root and nested, would rewrite the path twice.
+        if request.extensions().get::<Versioned>().is_some() {
+            warn!(
+                uri = %request.uri(),
+                "not rewriting the path, because already versioned by an outer ApiVersionLayer"
+            );
+            return Negotiation::Skipped;
+        }
+
+        match self.without_dot_segments(request.uri()) {
+            Ok(None) => {}
+            Ok(Some(uri)) => *request.uri_mut() = uri,
+            Err(kind) => {
+                let response = self.negotiation_error(kind, request);
+                return Negotiation::Failed(Box::new(response));
+            }
+        }
+
+        let internal = request.extensions().get::<InternalAccess>().is_some()
+            || self.has_internal_access_header(request.headers());
+        let negotiated =
+            match self.decide(request.method(), request.uri(), request.headers(), internal) {
+                Ok(None) => return Negotiation::Skipped,
+                Ok(Some(negotiated)) => negotiated,
+                Err(kind) => {
+                    let response = self.negotiation_error(kind, request);
+                    return Negotiation::Failed(Box::new(response));
+                }
+            };
+
+        #[cfg(feature = "chaos")]
+        let negotiated = match self.chaos.as_ref().and_then(|chaos| chaos.inject(request)) {
+            None => negotiated,
+
+            Some(chaos::Fault::Error(kind)) => {
+                debug!(?kind, "injecting negotiation error");
+                let response = self.negotiation_error(kind, request);
+                return Negotiation::Failed(Box::new(response));
+            }
+
+            Some(chaos::Fault::ForceVersion(version)) => {
+                debug!(?version, "injecting forced version");
+                Negotiated {
+                    version,
+                    ..negotiated
+                }
+            }
+        };
+
+        let Negotiated {
+            version, source, ..
+        } = negotiated;
+        debug!(?version, ?source, "using API version");
+        self.track_drain(version, request.headers());
+        #[cfg(feature = "tokio")]
+        if let Some(deprecation_budget) = &self.deprecation_budget {
+            let deprecated = self.status(version) == manifest::VersionStatus::Deprecated;
+            deprecation_budget.track(self.clock.now(), version, deprecated);
+        }
+        let uri = request.uri().to_owned();
+        request.extensions_mut().insert(OriginalRequestUri(uri));
+        request.extensions_mut().insert(ResolvedApiVersion(version));
+        request.extensions_mut().insert(source);
+        request.extensions_mut().insert(Versioned);
+        if source == VersionSource::Defaulted
+            && let Some(experiment) = &self.default_experiment
+            && self.experiment_version(request.headers()) == Some(version)
+        {
+            let assignment = ExperimentAssignment {
+                experiment: experiment.id.clone(),
+                version,
+            };
+            request.extensions_mut().insert(assignment);
+        }
+        for inject in &self.version_states {
+            inject(version, request.extensions_mut());
+        }
+        if let Some(name) = &self.version_request_header {
+            request
+                .headers_mut()
+                .insert(name.clone(), version_header_value(version));
+        }
+        if self.version_baggage {
+            propagate::set_baggage(request.headers_mut(), version);
+        }
+
+        Negotiation::Negotiated(negotiated)
+    }
+
+    /// The rewritten URI for the given negotiated URI, if to be rewritten.
+    fn rewritten_uri(&self, uri: &Uri, negotiated: &Negotiated) -> Option<Uri> {
+        if !self.rewrite_path {
+            debug!(%uri, "not rewriting the path, because disabled");
+            None
+        } else if negotiated.rpc && self.rpc_policy == RpcPolicy::MetadataOnly {
+            debug!(%uri, "not rewriting the path, because RPC request");
+            None
+        } else if let Some(template) = &self.rewrite_template {
+            let path = template.expand(self.prefix(), negotiated.version, &negotiated.path);
+            let rewritten_uri = replace_path(uri, "", None, &path)?;
+            debug!(original_uri = %uri, uri = %rewritten_uri, "rewrote the path with template");
+            Some(rewritten_uri)
+        } else if negotiated.source == VersionSource::PathPrefix
+            && !self.strip_base_path
+            && !negotiated.aliased
+        {
+            debug!(%uri, "not rewriting the path, because starts with valid version prefix");
+            None
+        } else {
+            // Insert version prefix into request URI and strip the base path if configured.
+            let rewritten_uri = replace_path(
+                uri,
+                self.prefix(),
+                Some(negotiated.version),
+                &negotiated.path,
+            )?;
+            debug!(original_uri = %uri, uri = %rewritten_uri, "rewrote the path");
+            Some(rewritten_uri)
+        }
+    }
+
+    /// Rewrite the path of the given negotiated request unless disabled.
+    fn rewrite(&self, request: &mut Request, negotiated: &Negotiated) {
+        if let Some(uri) = self.rewritten_uri(request.uri(), negotiated) {
+            *request.uri_mut() = uri;
+        }
+    }
+
+    /// Handle a request for the debug endpoint, see [ApiVersionLayer::debug_endpoint].
+    async fn debug(&self, request: Request) -> Response {
+        let (parts, body) = request.into_parts();
+        let body = match to_bytes(body, 64 * 1_024).await {
+            Ok(body) => body,
+            Err(error) => return (StatusCode::BAD_REQUEST, error.to_string()).into_response(),
+        };
+        let debug_request = match serde_json::from_slice::<DebugRequest>(&body) {
+            Ok(debug_request) => debug_request,
+            Err(error) => return (StatusCode::BAD_REQUEST, error.to_string()).into_response(),
+        };
+
+        // Simulate the request.
+        let method = Method::from_bytes(debug_request.method.as_bytes());
+        let uri = debug_request.path.parse::<Uri>();
+        let headers = HeaderMap::try_from(&debug_request.headers);
+        let (Ok(method), Ok(uri), Ok(headers)) = (method, uri, headers) else {
+            let response = (StatusCode::BAD_REQUEST, "invalid method, path or headers");
+            return response.into_response();
+        };
+        let input = NegotiationInput {
+            method: &method,
+            uri: &uri,
+            headers: &headers,
+        };
+
+        serialize::respond(&self.serializers, &parts.headers, self.report(&input))
+    }
+
+    /// Call the given inner service for the given negotiated request via
+    /// [Config::call_guarded] and add the configured response headers, also to responses not
+    /// created by the inner service, e.g. because of a timeout.
+    async fn call_inner<S>(
+        &self,
+        inner: S,
+        mut request: Request,
+        negotiated: Negotiated,
+    ) -> Result<Response, S::Error>
+    where
+        S: Service<Request, Response = Response>,
+    {
+        let Negotiated {
+            version, source, ..
+        } = negotiated;
+
+        if self.version_etags {
+            for name in [IF_NONE_MATCH, IF_MATCH] {
+                let etags = request
+                    .headers()
+                    .get(&name)
+                    .and_then(|etags| etags.to_str().ok())
+                    .map(|etags| unversion_etags(etags, version));
+                match etags {
+                    Some(Some(etags)) => {
+                        request.headers_mut().insert(name, etags);
+                    }
+
+                    Some(None) => {
+                        request.headers_mut().remove(name);
+                    }
+
+                    None => {}
+                }
+            }
+        }
+
+        let cors_policy = self.cors_policies.get(&version);
+        if let Some(cors_policy) = cors_policy
+            && cors::is_preflight(request.method(), request.headers())
+        {
+            debug!(?version, "responding to preflight request");
+            return Ok(cors_policy.preflight(request.headers()));
+        }
+        let origin = cors_policy.and(request.headers().get(ORIGIN).cloned());
+
+        let assignment = request.extensions().get::<ExperimentAssignment>().cloned();
+        let forwarded_prefix = self.forwarded_prefix(request.headers());
+
+        #[cfg(feature = "tokio")]
+        let mut response = CURRENT_VERSION
+            .scope(version, self.call_guarded(inner, request, version))
+            .await?;
+        #[cfg(not(feature = "tokio"))]
+        let mut response = self.call_guarded(inner, request, version).await?;
+        #[cfg(feature = "json-transform")]
+        if let Some(transform) = self.response_transforms.get(&version) {
+            if transform::is_encoded(response.headers()) {
+                // Fail loudly instead of skipping the transformation silently, as encoded
+                // responses are usually streaming, too.
+                tracing::error!(
+                    ?version,
+                    "cannot transform encoded response: the compression layer must be applied \
+                     outside of the ApiVersionLayer"
+                );
+                response = error_response(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    wire::ENCODED_RESPONSE,
+                    format!("cannot transform encoded response for version '{version}'"),
+                );
+            } else if !is_streaming(&response) {
+                response = transform::transform_response(transform, response).await;
+            }
+        }
+        if let Some(cors_policy) = cors_policy
+            && let Some(origin) = &origin
+        {
+            cors_policy.add_headers(origin, &mut response);
+        }
+        if self.skip_streaming_responses && is_streaming(&response) {
+            debug!("not adding response headers, because streaming response");
+            return Ok(response);
+        }
+        self.add_response_headers(version, source, forwarded_prefix.as_deref(), &mut response);
+        if let Some(ExperimentAssignment {
+            experiment,
+            version,
+        }) = assignment
+        {
+            let assignment = HeaderValue::from_str(&format!("{experiment}=v{version}"))
+                .expect("experiment assignment is a valid header value");
+            response
+                .headers_mut()
+                .insert(X_API_VERSION_EXPERIMENT.clone(), assignment);
+        }
+        if let Some(map_response) = &self.map_response {
+            map_response(version, &mut response);
+        }
+        Ok(response)
+    }
+
+    /// Call the given inner service for the given request for the given version, applying the
+    /// configured rate limit policy, body size limit and timeout.
+    async fn call_guarded<S>(
+        &self,
+        inner: S,
+        mut request: Request,
+        version: u16,
+    ) -> Result<Response, S::Error>
+    where
+        S: Service<Request, Response = Response>,
+    {
+        // Apply the rate limit policy if configured.
+        if let Some(rate_limit_policy) = &self.rate_limit_policy {
+            let client_key = self.client_key(request.headers());
+            match rate_limit_policy.check(client_key, version) {
+                RateLimitDecision::Allow => {}
+
+                RateLimitDecision::Deny => {
+                    debug!(client_key, ?version, "rate limit exceeded");
+                    return Ok(error_response(
+                        StatusCode::TOO_MANY_REQUESTS,
+                        wire::RATE_LIMITED,
+                        format!("rate limit exceeded for version '{version}'"),
+                    ));
+                }
+
+                #[cfg(feature = "tokio")]
+                RateLimitDecision::Slowdown(delay) => {
+                    debug!(client_key, ?version, ?delay, "slowing down request");
+                    tokio::time::sleep(delay).await;
+                }
+            }
+        }
+
+        // Limit the body size if configured.
+        if let Some(&max_body_size) = self.max_body_sizes.get(&version) {
+            let content_length = request
+                .headers()
+                .get(CONTENT_LENGTH)
+                .and_then(|content_length| content_length.to_str().ok())
+                .and_then(|content_length| content_length.parse::<usize>().ok());
+            if content_length.is_some_and(|content_length| content_length > max_body_size) {
+                return Ok(error_response(
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    wire::BODY_TOO_LARGE,
+                    format!("request body too large for version '{version}'"),
+                ));
+            }
+            let (parts, body) = request.into_parts();
+            request = Request::from_parts(parts, Body::new(Limited::new(body, max_body_size)));
+        }
+
+        // Transform the request body if configured.
+        #[cfg(feature = "json-transform")]
+        if let Some(transform) = self.request_transforms.get(&version) {
+            let limit = self
+                .max_body_sizes
+                .get(&version)
+                .copied()
+                .unwrap_or(DEFAULT_TRANSFORM_LIMIT);
+            let streaming = self.transform_streaming_requests;
+            request =
+                match transform::transform_request(transform, request, version, limit, streaming)
+                    .await
+                {
+                    Ok(request) => request,
+                    Err(response) => return Ok(response),
+                };
+        }
+
+        // Wait for a permit if the version's concurrency is limited.
+        #[cfg(feature = "tokio")]
+        let _permit = match &self.concurrency_limits {
+            Some(concurrency_limits) => concurrency_limits.acquire(version).await,
+            None => None,
+        };
+
+        // Run the downstream services, limiting the time if configured.
+        #[cfg(feature = "tokio")]
+        if let Some(&timeout) = self.timeouts.get(&version) {
+            let Ok(response) = tokio::time::timeout(timeout, inner.oneshot(request)).await else {
+                debug!(?version, "request timed out");
+                return Ok(error_response(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    wire::TIMEOUT,
+                    format!("request timed out for version '{version}'"),
+                ));
+            };
+            return response;
+        }
+
+        inner.oneshot(request).await
+    }
+
+    /// The default version at the current time, i.e. the last scheduled one which is due, if any,
+    /// or the configured one.
+    fn current_default_version(&self) -> u16 {
+        let now = self.clock.now();
+        self.default_schedule
+            .iter()
+            .rev()
+            .find(|&&(at, _)| at <= now)
+            .map(|&(_, version)| version)
+            .unwrap_or(self.configured_default_version())
+    }
+
+    /// The default version for a request with the given headers, taking a ramp into account.
+    fn default_version_for(&self, headers: &HeaderMap) -> u16 {
+        let version = self
+            .experiment_version(headers)
+            .unwrap_or_else(|| self.ramped_default_version(headers));
+
+        let version = self.external_version(version);
+
+        match &self.version_health {
+            Some(version_health) if !version_health.is_healthy(version) => {
+                let fallback = self.versions.iter().rev().copied().find(|&fallback| {
+                    fallback < version
+                        && !self.is_internal(fallback)
+                        && version_health.is_healthy(fallback)
+                        && !self.is_retired(fallback)
+                });
+                debug!(?version, ?fallback, "default version is unhealthy");
+                fallback.unwrap_or(version)
+            }
+
+            _ => version,
+        }
+    }
+
+    /// The default version for a request with the given headers, taking a ramp into account.
+    fn ramped_default_version(&self, headers: &HeaderMap) -> u16 {
+        if let Some(state::Rollout {
+            version,
+            start,
+            end,
+        }) = self.rollout()
+        {
+            let now = self.clock.now();
+            let share = if now < start {
+                0
+            } else if now >= end {
+                BUCKETS
+            } else {
+                let elapsed = now.duration_since(start).unwrap_or_default().as_secs_f64();
+                let window = end.duration_since(start).unwrap_or_default().as_secs_f64();
+                BUCKETS / 100 + ((BUCKETS - BUCKETS / 100) as f64 * elapsed / window) as u16
+            };
+            let salt = format!("v{version}");
+            if share >= BUCKETS
+                || self
+                    .client_key(headers)
+                    .is_some_and(|client_key| bucket(client_key, &salt) < share)
+            {
+                return version;
+            }
+        }
+
+        self.current_default_version()
+    }
+
+    /// The version assigned by the default experiment for a request with the given headers, if
+    /// any, see [ApiVersionLayer::default_experiment].
+    fn experiment_version(&self, headers: &HeaderMap) -> Option<u16> {
+        let experiment = self.default_experiment.as_ref()?;
+        let client_key = self.client_key(headers)?;
+
+        let (control, control_weight) = experiment.control;
+        let (treatment, treatment_weight) = experiment.treatment;
+        let share = BUCKETS as u64 * control_weight as u64
+            / (control_weight as u64 + treatment_weight as u64);
+        let version = if (bucket(client_key, &experiment.id) as u64) < share {
+            control
+        } else {
+            treatment
+        };
+
+        Some(version)
+    }
+
+    /// The given version if not internal, else the highest external version, as internal versions
+    /// are never used as default.
+    fn external_version(&self, version: u16) -> u16 {
+        if self.is_internal(version) {
+            self.versions
+                .iter()
+                .rev()
+                .copied()
+                .find(|&version| !self.is_internal(version))
+                .expect("not all versions are internal")
+        } else {
+            version
+        }
+    }
+
+    /// Check whether the given version is visible, i.e. not internal or with internal access.
+    fn is_visible(&self, version: u16, internal: bool) -> bool {
+        internal || !self.is_internal(version)
+    }
+
+    /// The highest visible and not retired version, if any, else the highest version, for
+    /// requests for the latest version, see [XApiVersion].
+    fn latest_version(&self, internal: bool) -> u16 {
+        self.versions
+            .iter()
+            .rev()
+            .copied()
+            .find(|&version| self.is_visible(version, internal) && !self.is_retired(version))
+            .unwrap_or_else(|| *self.versions.last().expect("versions is not empty"))
+    }
+
+    /// Check whether the given headers contain the internal access header, if configured.
+    fn has_internal_access_header(&self, headers: &HeaderMap) -> bool {
+        self.internal_access_header
+            .as_ref()
+            .is_some_and(|name| headers.contains_key(name))
+    }
+
+    /// The client key of a request with the given headers, if configured and present.
+    fn client_key<'a>(&self, headers: &'a HeaderMap) -> Option<&'a str> {
+        self.client_key_header
+            .as_ref()
+            .and_then(|name| headers.get(name))
+            .and_then(|client_key| client_key.to_str().ok())
+    }
+
+    /// The next announced change of the default version, if any.
+    fn next_default_version(&self) -> Option<(u16, SystemTime)> {
+        let now = self.clock.now();
+        self.default_version_advisory
+            .filter(|&(_, at)| now < at)
+            .or_else(|| {
+                self.default_schedule
+                    .iter()
+                    .find(|&&(at, _)| now < at)
+                    .map(|&(at, version)| (version, at))
+            })
+    }
+
+    /// The normalized forwarded prefix, e.g. `"/service-a"`, from the given headers, if enabled,
+    /// see [ApiVersionLayer::forwarded_prefix].
+    fn forwarded_prefix(&self, headers: &HeaderMap) -> Option<String> {
+        if !self.forwarded_prefix {
+            return None;
+        }
+
+        headers
+            .get(&X_FORWARDED_PREFIX)
+            .and_then(|prefix| prefix.to_str().ok())
+            .map(|prefix| prefix.trim().trim_end_matches('/'))
+            .filter(|prefix| prefix.starts_with('/') && is_valid_path(prefix))
+            .map(ToOwned::to_owned)
+    }
+
+    /// Check whether the given retired version is draining for a request with the given headers,
+    /// i.e. the drain window has not passed and its client is pinned to it. Once the drain window
+    /// has passed, the clients pinned to the given version are dropped.
+    fn is_draining(&self, version: u16, headers: &HeaderMap) -> bool {
+        let (Some(drain), Some(sunset)) = (&self.drain, self.sunset(version)) else {
+            return false;
+        };
+
+        if sunset + drain.window <= self.clock.now() {
+            drain.unpin_all(version);
+            return false;
+        }
+
+        self.client_key(headers)
+            .is_some_and(|client_key| drain.is_pinned(version, client_key))
+    }
+
+    /// Pin the client of a request with the given headers to the given version, if it has a
+    /// sunset, or count the request as drained, if it is retired, see [ApiVersionLayer::drain].
+    fn track_drain(&self, version: u16, headers: &HeaderMap) {
+        let (Some(drain), Some(sunset)) = (&self.drain, self.sunset(version)) else {
+            return;
+        };
+
+        if sunset <= self.clock.now() {
+            drain.count_drained_request(version);
+        } else if let Some(client_key) = self.client_key(headers) {
+            drain.pin(version, client_key);
+        }
+    }
+
+    /// Check whether the given version is retired, i.e. its sunset is not in the future.
+    fn is_retired(&self, version: u16) -> bool {
+        self.sunset(version)
+            .is_some_and(|sunset| sunset <= self.clock.now())
+    }
+
+    /// Create a response for the given negotiation error for the given request via the decision
+    /// chain, see [policy::PolicyStage::Render], carrying the error as response extension.
+    fn negotiation_error(&self, kind: VersioningError, request: &Request) -> Response {
+        let mut response = self
+            .render_policies(kind, request)
+            .unwrap_or_else(|| self.render_error(kind, request));
+        response.extensions_mut().insert(kind);
+        response
+    }
+
+    /// Create a response for the given negotiation error for the given request with the message
+    /// from the error message formatter, if any, carrying the supported, i.e. not retired,
+    /// versions in the `"x-api-supported-versions"` header.
+    fn render_error(&self, kind: VersioningError, request: &Request) -> Response {
+        let message = match &self.error_message {
+            Some(error_message) => error_message(kind, request),
+            None => kind.to_string(),
+        };
+
+        let mut response = error_response(kind.status_code(), kind.reason(), message);
+        response
+            .headers_mut()
+            .insert(X_API_SUPPORTED_VERSIONS.clone(), self.supported_versions());
+        response
+    }
+
+    /// Response to an `OPTIONS` request for the base path, see
+    /// [ApiVersionLayer::discovery_endpoint].
+    fn capabilities_response(&self) -> Response {
+        let mut response = Response::new(Body::empty());
+        *response.status_mut() = StatusCode::NO_CONTENT;
+        let headers = response.headers_mut();
+        headers.insert(ALLOW, HeaderValue::from_static("OPTIONS"));
+        headers.insert(X_API_SUPPORTED_VERSIONS.clone(), self.supported_versions());
+        response
+    }
+
+    /// The [X_API_SUPPORTED_VERSIONS] header value, i.e. the versions which are neither retired
+    /// nor internal.
+    fn supported_versions(&self) -> HeaderValue {
+        let supported_versions = self
+            .versions
+            .iter()
+            .filter(|&&version| !self.is_retired(version) && self.is_visible(version, false))
+            .map(|version| format!("v{version}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        HeaderValue::from_str(&supported_versions)
+            .expect("supported versions are a valid header value")
+    }
+
+    /// The given location rewritten according to the redirect policy, if it starts with the base
+    /// path followed by a valid version prefix.
+    fn rewritten_location(
+        &self,
+        location: &HeaderValue,
+        forwarded_prefix: Option<&str>,
+    ) -> Option<HeaderValue> {
+        let uri = location.to_str().ok()?.parse::<Uri>().ok()?;
+        let forwarded_prefix = forwarded_prefix.unwrap_or_default();
+        let path = uri.path();
+        let path = path
+            .strip_prefix(forwarded_prefix)
+            .filter(|path| path.starts_with('/'))
+            .unwrap_or(path);
+        let path = path.strip_prefix(self.base_path.as_str())?;
+        let (segment, path) = match path.strip_prefix('/')?.split_once('/') {
+            Some((segment, _)) => (segment, &path[1 + segment.len()..]),
+            None => (&path[1..], ""),
+        };
+        let version = version::parse(segment).filter(|version| self.versions.contains(version))?;
+
+        let uri = match self.redirect_policy {
+            RedirectPolicy::Keep => return None,
+            RedirectPolicy::Strip => {
+                let prefix = format!("{forwarded_prefix}{}", self.base_path);
+                replace_path(&uri, &prefix, None, path)?
+            }
+            RedirectPolicy::Map(map) => {
+                let segment = map(version);
+                if segment.is_empty()
+                    || segment.contains('/')
+                    || !is_valid_path(&format!("/{segment}"))
+                {
+                    debug!(
+                        segment,
+                        "not rewriting the location, because invalid path segment"
+                    );
+                    return None;
+                }
+                let prefix = format!("{forwarded_prefix}{}/{segment}", self.base_path);
+                replace_path(&uri, &prefix, None, path)?
+            }
+        };
+        HeaderValue::from_str(&uri.to_string()).ok()
+    }
+
+    /// Add the configured response headers for the given version from the given source to the
+    /// given response.
+    fn add_response_headers(
+        &self,
+        version: u16,
+        source: VersionSource,
+        forwarded_prefix: Option<&str>,
+        response: &mut Response,
+    ) {
+        let is_redirection = response.status().is_redirection();
+        let headers = response.headers_mut();
+
+        if is_redirection
+            && !matches!(self.redirect_policy, RedirectPolicy::Keep)
+            && let Some(location) = headers.get(LOCATION)
+            && let Some(location) = self.rewritten_location(location, forwarded_prefix)
+        {
+            headers.insert(LOCATION, location);
+        }
+
+        if source == VersionSource::Defaulted
+            && let Some((next_version, at)) = self.next_default_version()
+        {
+            let advisory = format!("v{next_version} on {}", fmt_http_date(at));
+            let advisory =
+                HeaderValue::from_str(&advisory).expect("advisory is a valid header value");
+            headers.insert(X_API_DEFAULT_VERSION_CHANGING.clone(), advisory);
+        }
+
+        if source == VersionSource::Preference {
+            let preference_applied = HeaderValue::from_str(&format!("api-version={version}"))
+                .expect("preference is a valid header value");
+            headers.append(&PREFERENCE_APPLIED, preference_applied);
+        }
+
+        if source == VersionSource::MediaType
+            && let Some(vendor) = &self.vendor
+            && headers
+                .get(CONTENT_TYPE)
+                .and_then(|content_type| content_type.to_str().ok())
+                .and_then(|content_type| content_type.split(';').next())
+                .is_some_and(|mime| mime.trim() == "application/json")
+        {
+            let content_type =
+                HeaderValue::from_str(&format!("application/vnd.{vendor}.v{version}+json"))
+                    .expect("vendor media type is a valid header value");
+            headers.insert(CONTENT_TYPE, content_type);
+            headers.append(VARY, HeaderValue::from_static("accept"));
+        }
+
+        if self.echo_version {
+            headers.insert(X_API_VERSION.clone(), version_header_value(version));
+            headers.append(VARY, HeaderValue::from_static(wire::X_API_VERSION));
+        }
+
+        if let Some(deprecation) = self.deprecation(version) {
+            let deprecation = deprecation
+                .duration_since(UNIX_EPOCH)
+                .map(|deprecation| deprecation.as_secs())
+                .unwrap_or_default();
+            let deprecation = HeaderValue::from_str(&format!("@{deprecation}"))
+                .expect("deprecation is a valid header value");
+            headers.insert(DEPRECATION.clone(), deprecation);
+
+            for guide in self
+                .migration_guides
+                .iter()
+                .filter(|guide| guide.from == version)
+            {
+                let link = HeaderValue::from_str(&format!("<{}>; rel=\"deprecation\"", guide.url))
+                    .expect("link is a valid header value");
+                headers.append(LINK, link);
+            }
+        }
+
+        if let Some(sunset) = self.sunset(version) {
+            if self.sunset_countdown {
+                let sunset_in = sunset
+                    .duration_since(self.clock.now())
+                    .map(|sunset_in| sunset_in.as_secs())
+                    .unwrap_or_default();
+                headers.insert(X_API_SUNSET_IN.clone(), HeaderValue::from(sunset_in));
+            }
+            let sunset = HeaderValue::from_str(&fmt_http_date(sunset))
+                .expect("sunset is a valid header value");
+            headers.insert(SUNSET.clone(), sunset);
+        }
+
+        if let Some(cache_control) = self.cache_controls.get(&version) {
+            headers.insert(CACHE_CONTROL, cache_control.clone());
+        }
+
+        if self.version_etags
+            && let Some(etag) = headers.get(ETAG).and_then(|etag| etag.to_str().ok())
+            && let Some(etag) = version_etag(etag, version)
+        {
+            headers.insert(ETAG, etag);
+        }
+    }
+}
+
+/// Prefix the opaque tag of the given strong or weak entity tag with the given version, e.g.
+/// `W/"v1-abc"` for `W/"abc"`.
+fn version_etag(etag: &str, version: u16) -> Option<HeaderValue> {
+    let (weak, tag) = match etag.strip_prefix("W/") {
+        Some(tag) => ("W/", tag),
+        None => ("", etag),
+    };
+    let tag = tag.strip_prefix('"')?.strip_suffix('"')?;
+    HeaderValue::from_str(&format!("{weak}\"v{version}-{tag}\"")).ok()
+}
+
+/// Remove the version prefix for the given version from the entity tags of the given
+/// `"if-none-match"` or `"if-match"` header value, dropping entity tags for other versions, e.g.
+/// `"abc"` for `"v1-abc", "v0-def"`; `None` if no entity tags remain.
+fn unversion_etags(etags: &str, version: u16) -> Option<HeaderValue> {
+    if etags.trim() == "*" {
+        return Some(HeaderValue::from_static("*"));
+    }
+
+    let prefix = format!("\"v{version}-");
+    let etags = etags
+        .split(',')
+        .filter_map(|etag| {
+            let etag = etag.trim();
+            let (weak, tag) = match etag.strip_prefix("W/") {
+                Some(tag) => ("W/", tag),
+                None => ("", etag),
+            };
+            tag.strip_prefix(&prefix)
+                .map(|tag| format!("{weak}\"{tag}"))
+        })
+        .collect::<Vec<_>>();
+
+    (!etags.is_empty())
+        .then(|| HeaderValue::from_str(&etags.join(", ")).ok())
+        .flatten()
+}
+
+/// Request extension for the API version resolved by [ApiVersionService], either from a valid
+/// version prefix, the `"x-api-version"` header or by defaulting to the highest version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ResolvedApiVersion(pub u16);
+
+/// Request extension for the URI of a versioned request before rewriting, see [ResolvedApiVersion].
+/// Like the other negotiation extensions, this is inserted before rewriting, hence it is also
+/// available to fallback handlers and error handlers, e.g. for a version-aware `404 Not Found`.
+///
+/// # Examples
+///
+/// ```
+/// # use api_version::{OriginalRequestUri, ResolvedApiVersion};
+/// # use axum::{Extension, http::StatusCode};
+/// async fn not_found(
+///     Extension(ResolvedApiVersion(version)): Extension<ResolvedApiVersion>,
+///     Extension(OriginalRequestUri(uri)): Extension<OriginalRequestUri>,
+/// ) -> (StatusCode, String) {
+///     (StatusCode::NOT_FOUND, format!("{uri} not found in v{version}"))
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OriginalRequestUri(pub Uri);
+
+#[cfg(feature = "tokio")]
+tokio::task_local! {
+    static CURRENT_VERSION: u16;
+}
+
+/// The API version resolved for the request currently handled by the inner service, if any, for
+/// code far from the request object, e.g. database query builders choosing a schema. This is
+/// task-local, i.e. tasks spawned by a handler must capture it before spawning.
+///
+/// # Examples
+///
+/// ```
+/// # use api_version::current_version;
+/// async fn handler() -> String {
+///     let version = current_version().expect("versioned request");
+///     tokio::spawn(async move { format!("background job for v{version}") })
+///         .await
+///         .unwrap()
+/// }
+/// ```
+#[cfg(feature = "tokio")]
+pub fn current_version() -> Option<u16> {
+    CURRENT_VERSION.try_with(|version| *version).ok()
+}
+
+/// Request extension and extractor for a value injected for the resolved version, see
+/// [ApiVersionLayer::version_state]. Extracting it fails with `500 Internal Server Error`, if no
+/// value has been injected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VersionScopedState<T>(pub T);
+
+impl<T, S> FromRequestParts<S> for VersionScopedState<T>
+where
+    T: Clone + Send + Sync + 'static,
+    S: Send + Sync,
+{
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<VersionScopedState<T>>()
+            .cloned()
+            .ok_or((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "missing version scoped state",
+            ))
+    }
+}
+
+/// Configuration of [ApiVersionLayer::default_experiment].
+#[derive(Debug, Clone)]
+struct DefaultExperiment {
+    id: Arc<str>,
+    control: (u16, u32),
+    treatment: (u16, u32),
+}
+
+/// Request extension for the assignment of a request without version to a version by the default
+/// experiment, see [ApiVersionLayer::default_experiment].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ExperimentAssignment {
+    /// The id of the experiment.
+    pub experiment: Arc<str>,
+
+    /// The assigned version.
+    pub version: u16,
+}
+
+/// Request extension granting internal access, see [ApiVersionLayer::internal_version]. As
+/// [ApiVersionService] negotiates the version before routing, this extension must be inserted by
+/// a layer outside of it, e.g. an authentication middleware.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct InternalAccess;
+
+/// Request extension to exempt a request from versioning, e.g. for a webhooks endpoint under
+/// `"/api/webhooks"`, as an alternative to centralized filters like
+/// [ApiVersionLayer::skip_method].
+///
+/// As [ApiVersionService] rewrites the path before routing, this extension must be inserted by a
+/// layer outside of it, e.g. `axum::Extension(NoVersioning)` or a middleware deciding per request;
+/// route layers of the inner router are too late.
+///
+/// # Examples
+///
+/// ```
+/// # use api_version::{ApiVersionLayer, ApiVersions, NoVersioning};
+/// # use axum::{Extension, Router, routing::post};
+/// const API_VERSIONS: ApiVersions<2> = ApiVersions::new([0, 1]);
+///
+/// let webhooks = Router::<()>::new()
+///     .route("/api/webhooks", post(|| async { "ok" }))
+///     .layer(ApiVersionLayer::new("/api", API_VERSIONS))
+///     .layer(Extension(NoVersioning));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct NoVersioning;
+
+/// Request extension marking requests already versioned, such that nested layers skip them.
+#[derive(Debug, Clone, Copy)]
+struct Versioned;
+
+/// Check whether a request with the given headers is a gRPC-Web or Connect request.
+fn is_rpc(headers: &HeaderMap) -> bool {
+    let is_rpc_content_type = headers
+        .get(CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .is_some_and(|content_type| {
+            content_type.starts_with("application/grpc-web")
+                || content_type.starts_with("application/connect+")
+        });
+    is_rpc_content_type || headers.contains_key("connect-protocol-version")
+}
+
+/// Check whether the given response is streaming, i.e. has a `"text/event-stream"` content type or
+/// a body without an exact size.
+fn is_streaming(response: &Response) -> bool {
+    let is_event_stream = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .is_some_and(|content_type| content_type.starts_with("text/event-stream"));
+    is_event_stream || response.body().size_hint().exact().is_none()
+}
+
+/// Request extension for the source of the [ResolvedApiVersion], e.g. to distinguish explicitly
+/// requested versions from defaulted ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[non_exhaustive]
+pub enum VersionSource {
+    /// The version was given by a valid version prefix of the path.
+    PathPrefix,
+
+    /// The version was given by the `"x-api-version"` header.
+    Header,
+
+    /// The version was given by the `"api-version"` preference of the `"prefer"` header, see
+    /// [ApiVersionLayer::prefer_header].
+    Preference,
+
+    /// The version was given by a vendor media type in the `"accept"` header, see
+    /// [ApiVersionLayer::vendor_media_type].
+    MediaType,
+
+    /// No version was given, hence the default version was used.
+    Defaulted,
+
+    /// The version was set by a custom policy, see [ApiVersionLayer::policy_before].
+    Policy,
+}
+
+/// Number of buckets for [bucket].
+pub const BUCKETS: u16 = 10_000;
+
+/// Deterministically assign the given client key to one of [BUCKETS] buckets, salted with the
+/// given salt, using the 64-bit FNV-1a hash, which is stable across processes and platforms. This
+/// is what [ApiVersionLayer::ramp_default_version] uses with the salt `"v{version}"`, such that
+/// application code and dashboards can predict which version a client gets: it gets the ramped
+/// version once the ramp's share in basis points exceeds its bucket.
+///
+/// # Examples
+///
+/// ```
+/// # use api_version::{BUCKETS, bucket};
+/// let bucket = bucket("client-42", "v2");
+/// assert!(bucket < BUCKETS);
+/// ```
+pub fn bucket(client_key: &str, salt: &str) -> u16 {
+    let mut hash = 0xcbf2_9ce4_8422_2325_u64;
+    for byte in salt.bytes().chain([0]).chain(client_key.bytes()) {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    (hash % u64::from(BUCKETS)) as u16
+}
+
+/// Create an error response with the given status code, reason code and message.
+fn error_response(status: StatusCode, reason: &'static str, message: String) -> Response {
+    let reason = [(
+        X_API_VERSION_ERROR.clone(),
+        HeaderValue::from_static(reason),
+    )];
+    (status, reason, message).into_response()
+}
+
+/// Create a header value for the given version, e.g. `"v1"`.
+fn version_header_value(version: u16) -> HeaderValue {
+    HeaderValue::from_str(&format!("v{version}")).expect("version is a valid header value")
+}
+
+/// Rewrite the given URI by inserting the version prefix for the given version after the given
+/// base path, preserving the query, exactly like the [ApiVersionService] does, e.g. for gateways or
+/// tests.
+///
+/// # Examples
+///
+/// ```
+/// # use api_version::rewrite_uri;
+/// # use axum::http::Uri;
+/// let uri = Uri::from_static("/api/test?foo=bar");
+/// let uri = rewrite_uri(&uri, "/api", 1).unwrap();
+/// assert_eq!(uri, "/api/v1/test?foo=bar");
+/// ```
+///
+/// # Errors
+///
+/// Fails if the given URI has no path, e.g. `"example.com:80"`, if the given base path is not a
+/// valid path, if the path of the given URI does not start with the given base path or if the
+/// given version is not less than `10_000`.
+pub fn rewrite_uri(uri: &Uri, base_path: &str, version: u16) -> Result<Uri, RewriteError> {
+    if version >= 10_000 {
+        return Err(RewriteError::InvalidVersion(version));
+    }
+    if uri.path_and_query().is_none() {
+        return Err(RewriteError::MissingPath);
+    }
+    if !is_valid_path(base_path) {
+        return Err(RewriteError::InvalidBasePath(base_path.to_owned()));
+    }
+
+    let path = uri
+        .path()
+        .strip_prefix(base_path)
+        .filter(|path| path.is_empty() || path.starts_with('/'))
+        .ok_or_else(|| RewriteError::NotUnderBasePath(uri.path().to_owned()))?;
+
+    replace_path(uri, base_path, Some(version), path)
+        .ok_or_else(|| RewriteError::InvalidBasePath(base_path.to_owned()))
+}
+
+/// Error returned by [rewrite_uri].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum RewriteError {
+    /// The path does not start with the base path.
+    NotUnderBasePath(String),
+
+    /// The version is not within `0u16..10_000`.
+    InvalidVersion(u16),
+
+    /// The URI has no path, e.g. `"example.com:80"`.
+    MissingPath,
+
+    /// The base path is not a valid path.
+    InvalidBasePath(String),
+}
+
+impl Display for RewriteError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            RewriteError::NotUnderBasePath(path) => {
+                write!(f, "path '{path}' does not start with the base path")
+            }
+            RewriteError::InvalidVersion(version) => write!(f, "invalid version '{version}'"),
+            RewriteError::MissingPath => write!(f, "uri has no path"),
+            RewriteError::InvalidBasePath(base_path) => {
+                write!(f, "invalid base path '{base_path}'")
+            }
+        }
+    }
+}
+
+impl Error for RewriteError {}
+
+/// Check whether the given string is empty or a valid path without query, starting with "/".
+fn is_valid_path(path: &str) -> bool {
+    path.is_empty()
+        || (path.starts_with('/')
+            && PathAndQuery::try_from(path)
+                .is_ok_and(|paq| paq.query().is_none() && paq.as_str() == path))
+}
+
+/// Replace the path of the given URI with the given prefix, the version prefix for the given
+/// version, if any, and the given path, preserving the query. Returns `None` if the given URI has
+/// no path or if the new path is invalid.
+fn replace_path(uri: &Uri, prefix: &str, version: Option<u16>, path: &str) -> Option<Uri> {
+    let mut parts = uri.to_owned().into_parts();
+    let query = parts.path_and_query.as_ref()?.query();
+
+    // Build the new "path and query" into an inline buffer, which only spills onto the heap for
+    // long paths: "PathAndQuery" copies it into its own bytes with a single allocation, whereas a
+    // presized string would need a further allocation to be shared, see the "rewrite" benchmark.
+    let mut paq = SmallVec::<[u8; 128]>::new();
+    paq.extend_from_slice(prefix.as_bytes());
+    if let Some(version) = version {
+        io::Write::write_fmt(&mut paq, format_args!("/v{version}")).expect("can write to buffer");
+    }
+    paq.extend_from_slice(path.as_bytes());
+    if let Some(query) = query {
+        paq.push(b'?');
+        paq.extend_from_slice(query.as_bytes());
+    }
+
+    let paq = PathAndQuery::from_maybe_shared(paq).ok()?;
+    parts.path_and_query = Some(paq);
+    Uri::from_parts(parts).ok()
+}
+
+/// The given path with its dot segments removed (RFC 3986, section 5.2.4), if it has any. Dot
+/// segments are also recognized if percent-encoded, e.g. `"%2e%2E"`.
+fn remove_dot_segments(path: &str) -> Option<String> {
+    // Some(true) for "..", Some(false) for ".".
+    fn dot_segment(segment: &str) -> Option<bool> {
+        if segment.len() > 6 {
+            return None;
+        }
+        match segment.to_ascii_lowercase().replace("%2e", ".").as_str() {
+            "." => Some(false),
+            ".." => Some(true),
+            _ => None,
+        }
+    }
+
+    let segments = path.split('/').skip(1);
+    if !segments
+        .clone()
+        .any(|segment| dot_segment(segment).is_some())
+    {
+        return None;
+    }
+
+    let mut normalized = Vec::new();
+    let mut trailing_slash = false;
+    for segment in segments {
+        match dot_segment(segment) {
+            Some(parent) => {
+                if parent {
+                    normalized.pop();
+                }
+                trailing_slash = true;
+            }
+
+            None => {
+                normalized.push(segment);
+                trailing_slash = false;
+            }
+        }
+    }
+
+    let mut path = String::with_capacity(path.len());
+    for segment in normalized {
+        path.push('/');
+        path.push_str(segment);
+    }
+    if trailing_slash || path.is_empty() {
+        path.push('/');
+    }
+    Some(path)
+}
+
+/// Header name for the `"x-forwarded-prefix"` HTTP header, see
+/// [ApiVersionLayer::forwarded_prefix].
+static X_FORWARDED_PREFIX: HeaderName = HeaderName::from_static("x-forwarded-prefix");
+
+/// Header name for the `"prefer"` HTTP header (RFC 7240), see [ApiVersionLayer::prefer_header].
+static PREFER: HeaderName = HeaderName::from_static("prefer");
+
+/// Header name for the `"preference-applied"` HTTP header (RFC 7240), see
+/// [ApiVersionLayer::prefer_header].
+static PREFERENCE_APPLIED: HeaderName = HeaderName::from_static("preference-applied");
+
+/// Header name for the `"deprecation"` HTTP header (RFC 9745).
+pub static DEPRECATION: HeaderName = HeaderName::from_static(wire::DEPRECATION);
+
+/// Header name for the `"sunset"` HTTP header (RFC 8594).
+pub static SUNSET: HeaderName = HeaderName::from_static(wire::SUNSET);
+
+/// Header name for the custom HTTP header carrying the number of seconds until the sunset, see
+/// [ApiVersionLayer::sunset_countdown].
+pub static X_API_SUNSET_IN: HeaderName = HeaderName::from_static(wire::X_API_SUNSET_IN);
+
+/// Header name for the custom HTTP header announcing a change of the default version, see
+/// [ApiVersionLayer::default_version_advisory].
+pub static X_API_DEFAULT_VERSION_CHANGING: HeaderName =
+    HeaderName::from_static(wire::X_API_DEFAULT_VERSION_CHANGING);
+
+/// Header name for the custom HTTP header listing the supported versions, e.g. `"v0, v1"`, which
+/// is added to negotiation error responses.
+pub static X_API_SUPPORTED_VERSIONS: HeaderName =
+    HeaderName::from_static(wire::X_API_SUPPORTED_VERSIONS);
+
+/// Header name for the `"x-api-version-experiment"` custom HTTP header carrying the assignment by
+/// the default experiment, e.g. `"checkout=v2"`, see [ApiVersionLayer::default_experiment].
+pub static X_API_VERSION_EXPERIMENT: HeaderName =
+    HeaderName::from_static(wire::X_API_VERSION_EXPERIMENT);
+
+/// Header name for the `"x-api-version-error"` custom HTTP header carrying a machine-readable
+/// reason code on error responses generated by the middleware, e.g. `"unknown-version"`, see
+/// [VersioningError::reason], `"rate-limited"`, `"body-too-large"`, `"length-required"`,
+/// `"encoded-response"` or `"timeout"`, see [wire].
+pub static X_API_VERSION_ERROR: HeaderName = HeaderName::from_static(wire::X_API_VERSION_ERROR);
+
+/// Header name for the [XApiVersion] custom HTTP header.
+pub static X_API_VERSION: HeaderName = HeaderName::from_static(wire::X_API_VERSION);
+
+/// Custom HTTP header conveying the API version, which is expected to be a version designator
+/// starting with `'v'` followed by a number within `0u16..10_000` without leading zero, e.g. `v0`,
+/// or `*` or `latest` for the latest version, i.e. the highest one which is neither internal nor
+/// retired. Unlike omitting the header, which gives the default version, this lets clients opt
+/// into rolling forward explicitly.
+#[derive(Debug)]
+pub struct XApiVersion(RequestedVersion);
+
+/// Version requested via the [XApiVersion] header.
+#[derive(Debug, Clone, Copy)]
+enum RequestedVersion {
+    Version(u16),
+    Latest,
+}
+
+impl Header for XApiVersion {
+    fn name() -> &'static HeaderName {
+        &X_API_VERSION
+    }
+
+    fn decode<'i, I>(values: &mut I) -> Result<Self, headers::Error>
+    where
+        Self: Sized,
+        I: Iterator<Item = &'i HeaderValue>,
+    {
+        values
+            .next()
+            .and_then(|v| match v.as_bytes() {
+                b"*" | b"latest" => Some(RequestedVersion::Latest),
+                v => version::parse_bytes(v).map(RequestedVersion::Version),
+            })
+            .map(XApiVersion)
+            .ok_or_else(headers::Error::invalid)
+    }
+
+    fn encode<E: Extend<HeaderValue>>(&self, _values: &mut E) {
+        // We do not yet need to encode this header.
+        unimplemented!("not yet needed");
+    }
+}
+
+/// Define versioned routes along with the matching [ApiVersionLayer], evaluating to a tuple of an
+/// axum `Router` and the layer. Each route is given as `(version, path, method_router)` and
+/// registered under `"{base_path}/v{version}{path}"`. Routes referencing a version which is not
+/// one of the given API versions fail to compile.
+///
+/// # Examples
+///
+/// ```
+/// # use api_version::versioned_routes;
+/// # use axum::{Router, routing::get};
+/// # use tower::Layer;
+/// let (app, layer): (Router, _) = versioned_routes! {
+///     base_path: "/api",
+///     versions: [0, 1],
+///     routes: [
+///         (0, "/test", get(|| async { "0" })),
+///         (1, "/test", get(|| async { "1" })),
+///     ],
+/// };
+///
+/// let app = layer.layer(app);
+/// ```
+///
+/// Referencing an unknown version fails to compile:
+///
+/// ```compile_fail
+/// # use api_version::versioned_routes;
+/// # use axum::{Router, routing::get};
+/// let (app, layer): (Router, _) = versioned_routes! {
+///     base_path: "/api",
+///     versions: [0, 1],
+///     routes: [
+///         (2, "/test", get(|| async { "2" })),
+///     ],
+/// };
+/// ```
+#[macro_export]
+macro_rules! versioned_routes {
+    (
+        base_path: $base_path:literal,
+        versions: [$($version:literal),+ $(,)?],
+        routes: [$(($route_version:literal, $path:literal, $method_router:expr)),* $(,)?] $(,)?
+    ) => {{
+        const API_VERSIONS: $crate::ApiVersions<{ [$($version),+].len() }> =
+            $crate::ApiVersions::new([$($version),+]);
+
+        $(
+            const {
+                assert!(
+                    API_VERSIONS.has_version($route_version),
+                    concat!("route references unknown API version ", $route_version)
+                );
+            }
+        )*
+
+        let router = $crate::__private::axum::Router::new()
+            $(.route(concat!($base_path, "/v", $route_version, $path), $method_router))*;
+        let layer = $crate::ApiVersionLayer::new($base_path, API_VERSIONS);
+
+        (router, layer)
+    }};
+}
+
+/// Define [VersionedPath] constants for paths relative to the given base path, valid for the given
+/// API versions, such that handlers and tests can build versioned URLs without duplicating
+/// strings. Invalid API versions fail to compile, as do versioned paths for other versions built
+/// in a const context.
+///
+/// # Examples
+///
+/// ```
+/// # use api_version::versioned_paths;
+/// versioned_paths! {
+///     base_path: "/api",
+///     versions: [0, 1],
+///     paths: {
+///         TEST = "/test",
+///         pub USERS = "/users",
+///     },
+/// }
+///
+/// const TEST_V1: &str = TEST.v(1);
+/// assert_eq!(TEST_V1, "/api/v1/test");
+/// assert_eq!(USERS.get(0), Some("/api/v0/users"));
+/// assert_eq!(USERS.get(2), None);
+/// ```
+///
+/// ```compile_fail
+/// # use api_version::versioned_paths;
+/// versioned_paths! {
+///     base_path: "/api",
+///     versions: [0, 1],
+///     paths: { TEST = "/test" },
+/// }
+///
+/// const TEST_V2: &str = TEST.v(2);
+/// ```
+#[macro_export]
+macro_rules! versioned_paths {
+    (
+        base_path: $base_path:literal,
+        versions: $versions:tt,
+        paths: { $($vis:vis $name:ident = $path:literal),* $(,)? } $(,)?
+    ) => {
+        $(
+            $vis const $name: $crate::VersionedPath =
+                $crate::versioned_paths!(@path $base_path, $versions, $path);
+        )*
+    };
+
+    (@path $base_path:literal, [$($version:literal),+ $(,)?], $path:literal) => {{
+        let _ = $crate::ApiVersions::new([$($version),+]);
+        $crate::VersionedPath::new(
+            $path,
+            &[$(($version, concat!($base_path, "/v", $version, $path))),+],
+        )
+    }};
+}
+
+/// Path relative to a base path, valid for a set of API versions, usually defined via
+/// [versioned_paths].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VersionedPath {
+    path: &'static str,
+    versioned_paths: &'static [(u16, &'static str)],
+}
+
+impl VersionedPath {
+    /// Create a versioned path for the given path relative to a base path with the given pairs of
+    /// API version and full path for that version, e.g. `(1, "/api/v1/test")`.
+    pub const fn new(path: &'static str, versioned_paths: &'static [(u16, &'static str)]) -> Self {
+        Self {
+            path,
+            versioned_paths,
+        }
+    }
+
+    /// The path relative to the base path, e.g. `"/test"`.
+    pub const fn path(&self) -> &'static str {
+        self.path
+    }
+
+    /// The path for the given version, e.g. `"/api/v1/test"`, if it is one of the API versions.
+    pub const fn get(&self, version: u16) -> Option<&'static str> {
+        let mut n = 0;
+        while n < self.versioned_paths.len() {
+            if self.versioned_paths[n].0 == version {
+                return Some(self.versioned_paths[n].1);
+            }
+            n += 1;
+        }
+        None
+    }
+
+    /// The path for the given version, e.g. `"/api/v1/test"`. Used in a const context, an invalid
+    /// version fails to compile, else see [VersionedPath::get].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the given version is not one of the API versions.
+    pub const fn v(&self, version: u16) -> &'static str {
+        match self.get(version) {
+            Some(path) => path,
+            None => panic!("version must be one of the API versions"),
+        }
+    }
+}
+
+#[doc(hidden)]
+pub mod __private {
+    pub use axum;
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        ApiVersionLayer, ApiVersions, BUCKETS, RewriteError, RouteValidationError, X_API_VERSION,
+        bucket,
+        negotiation::{NegotiationInput, NegotiationReport, negotiate},
+        remove_dot_segments, replace_path, rewrite_uri, strip_version,
+        template::RewriteTemplate,
+        unversion_etags, version,
+        version::{ApiVersionsError, is_monotonically_increasing},
+        version_etag,
+    };
+    use assert_matches::assert_matches;
+    use axum::http::{HeaderMap, HeaderValue, Method, Request, Uri, header::ACCEPT};
+    use std::time::UNIX_EPOCH;
+
+    #[test]
+    fn test_x_api_header() {
+        assert_matches!(version::parse("v0"), Some(0));
+        assert_matches!(version::parse("v1"), Some(1));
+        assert_matches!(version::parse("v99"), Some(99));
+        assert_matches!(version::parse("v9999"), Some(9999));
+        assert_matches!(version::parse("v10000"), None);
+        assert_matches!(version::parse("vx"), None);
+        assert_matches!(version::parse("v"), None);
+        assert_matches!(version::parse("v-1"), None);
+        assert_matches!(version::parse_bytes(b"v\xff\xff\xff\xff"), None);
+        assert_matches!(version::parse_bytes(b"v9:"), None);
+        assert_matches!(version::parse_bytes(b"v/"), None);
+    }
+
+    #[test]
+    fn test_display_and_debug() {
+        let versions = ApiVersions::new([0, 1, 42]);
+        assert_eq!(versions.to_string(), "[v0, v1, v42]");
+
+        let layer = ApiVersionLayer::new("/api", versions)
+            .max_body_size(42, 1)
+            .max_body_size(0, 2)
+            .max_body_size(1, 3);
+        let debug = format!("{layer:?}");
+        assert!(debug.contains(r#"base_path: "/api""#));
+        assert!(debug.contains("max_body_sizes: {0: 2, 1: 3, 42: 1}"));
+    }
+
+    #[test]
+    fn test_strip_version() {
+        assert_eq!(strip_version("/v0/test"), (Some(0), "/test"));
+        assert_eq!(strip_version("/v42/"), (Some(42), "/"));
+        assert_eq!(strip_version("/v1/v2/test"), (Some(1), "/v2/test"));
+        assert_eq!(strip_version("/v00/test"), (None, "/v00/test"));
+        assert_eq!(strip_version("/v1"), (None, "/v1"));
+        assert_eq!(strip_version("/vx/test"), (None, "/vx/test"));
+        assert_eq!(strip_version("v1/test"), (None, "v1/test"));
+        assert_eq!(strip_version(""), (None, ""));
+    }
+
+    #[test]
+    fn test_remove_dot_segments() {
+        assert_eq!(remove_dot_segments("/api/v1/test"), None);
+        assert_eq!(remove_dot_segments("/api/v1.0/.well-known"), None);
+        assert_eq!(
+            remove_dot_segments("/api/v1/../v0/test"),
+            Some("/api/v0/test".to_string())
+        );
+        assert_eq!(
+            remove_dot_segments("/api/./v1/test"),
+            Some("/api/v1/test".to_string())
+        );
+        assert_eq!(
+            remove_dot_segments("/api/v1/%2E%2e/v0/test"),
+            Some("/api/v0/test".to_string())
+        );
+        assert_eq!(remove_dot_segments("/api/v1/.."), Some("/api/".to_string()));
+        assert_eq!(remove_dot_segments("/../../api"), Some("/api".to_string()));
+        assert_eq!(remove_dot_segments("/.."), Some("/".to_string()));
+    }
+
+    #[test]
+    fn test_rewrite_template() {
+        let template = RewriteTemplate::parse("{base}/{resource}/v{version}{rest}").unwrap();
+        assert_eq!(template.expand("/api", 1, "/users/42"), "/api/users/v1/42");
+        assert_eq!(template.expand("", 1, "/users"), "/users/v1");
+        assert_eq!(template.expand("/api", 1, "/"), "/api/v1");
+        assert_eq!(template.expand("/api", 1, ""), "/api/v1");
+
+        let template = RewriteTemplate::parse("/{resource}/v{version}{rest}").unwrap();
+        assert_eq!(template.expand("", 1, "/"), "/v1");
+
+        let template = RewriteTemplate::parse("/v{version}{base}{path}").unwrap();
+        assert_eq!(template.expand("/api", 2, "/users"), "/v2/api/users");
+
+        for template in [
+            "{base}/v{version}",
+            "{base}/v{path}",
+            "{base}/v{version}/v{version}{path}",
+            "{base}/{resource}/v{version}{path}",
+            "{base}/v{version}{path}{foo}",
+            "{base}/v{version}{path",
+            "{base}v{version}{path}",
+            "api/v{version}{path}",
+            "{base}/v{version}{path}?x=1",
+            "{base}/ v{version}{path}",
+        ] {
+            assert!(RewriteTemplate::parse(template).is_err(), "{template}");
+        }
+    }
+
+    #[test]
+    fn test_is_monotonically_increasing() {
+        assert!(is_monotonically_increasing([]));
+        assert!(is_monotonically_increasing([0]));
+        assert!(is_monotonically_increasing([0, 1]));
+
+        assert!(!is_monotonically_increasing([0, 0]));
         assert!(!is_monotonically_increasing([1, 0]));
     }
+
+    #[test]
+    fn test_try_new() {
+        assert_matches!(ApiVersions::try_new([0, 1, 2]), Ok(_));
+        assert_matches!(ApiVersions::try_new([]), Err(ApiVersionsError::Empty));
+        assert_matches!(
+            ApiVersions::try_new([0, 1, 1]),
+            Err(ApiVersionsError::Duplicate {
+                index: 2,
+                version: 1
+            })
+        );
+        assert_matches!(
+            ApiVersions::try_new([0, 2, 1]),
+            Err(ApiVersionsError::Decreasing {
+                index: 2,
+                previous: 2,
+                version: 1
+            })
+        );
+        assert_matches!(
+            ApiVersions::try_new([0, 10_000]),
+            Err(ApiVersionsError::OutOfRange {
+                index: 1,
+                version: 10_000
+            })
+        );
+        assert_eq!(
+            ApiVersions::try_new([0, 0]).unwrap_err().to_string(),
+            "API versions must be strictly monotonically increasing, but version 0 at index 1 is a \
+             duplicate"
+        );
+    }
+
+    #[test]
+    fn test_bucket() {
+        // Buckets must be stable across releases, processes and platforms.
+        assert_eq!(bucket("client-42", "v2"), 9_149);
+        assert_eq!(bucket("client-42", "v1"), 9_716);
+        assert_eq!(bucket("", ""), 7_455);
+
+        // Buckets should be roughly uniformly distributed.
+        let low = (0..10_000)
+            .filter(|n| bucket(&format!("client-{n}"), "v1") < BUCKETS / 2)
+            .count();
+        assert!((4_500..5_500).contains(&low));
+    }
+
+    #[test]
+    fn test_validate_routes() {
+        let layer = ApiVersionLayer::new("/api", ApiVersions::new([0, 1, 2]));
+
+        let result = layer.validate_routes([
+            "/ready",
+            "/api/v0/test",
+            "/api/v2/test",
+            "/api/v3/test",
+            "/api/v01/test",
+            "/api/test",
+        ]);
+        assert_matches!(
+            result,
+            Err(RouteValidationError { versions_without_routes }) if versions_without_routes == [1]
+        );
+
+        let result = layer.validate_routes(["/api/v0/test", "/api/v1/test", "/api/v2/{id}"]);
+        assert_matches!(result, Ok(()));
+    }
+
+    #[test]
+    fn test_route_coverage() {
+        let layer = ApiVersionLayer::new("/api", ApiVersions::new([0, 1, 2]));
+
+        let coverage = layer.route_coverage([
+            "/ready",
+            "/api/v0/users",
+            "/api/v0/users/{id}",
+            "/api/v1/users",
+            "/api/v2/users",
+            "/api/v2/users/{id}",
+            "/api/v3/users",
+            "/api/test",
+        ]);
+        assert!(coverage.contains("/users/{id}", 2));
+        assert!(!coverage.contains("/users/{id}", 1));
+        assert_eq!(coverage.gaps(), [("/users/{id}", 1)]);
+        assert_eq!(
+            coverage.to_string(),
+            "route          v0    v1    v2\n\
+             /users          x     x     x\n\
+             /users/{id}     x     -     x"
+        );
+    }
+
+    #[test]
+    fn test_version_etag() {
+        let etag = version_etag(r#""abc""#, 1);
+        assert_matches!(etag, Some(etag) if etag == r#""v1-abc""#);
+
+        let etag = version_etag(r#"W/"abc""#, 1);
+        assert_matches!(etag, Some(etag) if etag == r#"W/"v1-abc""#);
+
+        let etag = version_etag("abc", 1);
+        assert_matches!(etag, None);
+    }
+
+    #[test]
+    fn test_unversion_etags() {
+        let etags = unversion_etags(r#""v1-abc""#, 1);
+        assert_matches!(etags, Some(etags) if etags == r#""abc""#);
+
+        let etags = unversion_etags(r#"W/"v1-abc", "v0-def", "v1-ghi""#, 1);
+        assert_matches!(etags, Some(etags) if etags == r#"W/"abc", "ghi""#);
+
+        let etags = unversion_etags(r#""v0-abc""#, 1);
+        assert_matches!(etags, None);
+
+        let etags = unversion_etags("*", 1);
+        assert_matches!(etags, Some(etags) if etags == "*");
+    }
+
+    #[test]
+    fn test_replace_path() {
+        let uri = Uri::from_static("http://localhost/api/test?foo=bar");
+        let uri = replace_path(&uri, "/api", Some(1), "/test");
+        assert_matches!(uri, Some(uri) if uri == "http://localhost/api/v1/test?foo=bar");
+
+        let uri = Uri::from_static("/api/v0/test");
+        let uri = replace_path(&uri, "", None, "/v0/test");
+        assert_matches!(uri, Some(uri) if uri == "/v0/test");
+
+        let uri = Uri::from_static("/api/test");
+        let uri = replace_path(&uri, "/a b", None, "/test");
+        assert_matches!(uri, None);
+
+        let uri = Uri::from_static("example.com:80");
+        let uri = replace_path(&uri, "", Some(1), "");
+        assert_matches!(uri, None);
+    }
+
+    /// Regression test for the `negotiate` fuzz target: deterministically generated paths and
+    /// headers must neither panic nor produce invalid URIs for various configurations.
+    #[test]
+    fn test_negotiation_corpus() {
+        const PATH_TOKENS: [&str; 18] = [
+            "/api",
+            "/v0",
+            "/v1",
+            "/v9999",
+            "/v10000",
+            "/v",
+            "/v01",
+            "//",
+            "/.",
+            "/..",
+            "/%2F",
+            "/x",
+            "?q=1",
+            "/",
+            "/_versions",
+            "/_version-debug",
+            "/api/",
+            "v1",
+        ];
+        const HEADER_TOKENS: [&str; 10] = [
+            "v0",
+            "v1",
+            "v",
+            "v01",
+            " v1",
+            "v1, v2",
+            "v99999",
+            "application/vnd.acme.v1+json",
+            "application/vnd.acme.v+json",
+            "",
+        ];
+
+        let layers = [
+            ApiVersionLayer::new("/api", ApiVersions::new([0, 1])),
+            ApiVersionLayer::new("/api", ApiVersions::new([0, 1]))
+                .strict(true)
+                .vendor_media_type("acme"),
+            ApiVersionLayer::new("/api", ApiVersions::new([0, 1])).strip_base_path(true),
+            ApiVersionLayer::new("/api", ApiVersions::new([0, 1])).rewrite_path(false),
+            ApiVersionLayer::new("/api", ApiVersions::new([0, 1])).internal_version(1),
+        ];
+
+        // Xorshift, such that the corpus is deterministic.
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let mut next = |n: usize| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as usize % n
+        };
+
+        for _ in 0..2_000 {
+            let path = (0..1 + next(5))
+                .map(|_| PATH_TOKENS[next(PATH_TOKENS.len())])
+                .collect::<String>();
+            let Ok(uri) = path.parse::<Uri>() else {
+                continue;
+            };
+            let mut headers = HeaderMap::new();
+            if next(2) == 0 {
+                let value = HeaderValue::from_static(HEADER_TOKENS[next(HEADER_TOKENS.len())]);
+                headers.insert("x-api-version", value);
+            }
+            if next(2) == 0 {
+                let value = HeaderValue::from_static(HEADER_TOKENS[next(HEADER_TOKENS.len())]);
+                headers.insert(ACCEPT, value);
+            }
+
+            for layer in &layers {
+                let input = NegotiationInput {
+                    method: &Method::GET,
+                    uri: &uri,
+                    headers: &headers,
+                };
+                if let NegotiationReport::Negotiated { uri: rewritten, .. } =
+                    negotiate(&input, layer)
+                {
+                    assert!(
+                        rewritten.parse::<Uri>().is_ok(),
+                        "invalid rewritten URI {rewritten} for {uri}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_simulate() {
+        let layer = ApiVersionLayer::new("/api", ApiVersions::new([1, 2, 3])).sunset(1, UNIX_EPOCH);
+
+        let request = Request::get("/api/foo").header(&X_API_VERSION, "v3");
+        assert_matches!(
+            layer.simulate(&request.body(()).unwrap()),
+            NegotiationReport::Negotiated { version: 3, ref uri, .. } if uri == "/api/v3/foo"
+        );
+
+        let request = Request::get("/api/foo").header(&X_API_VERSION, "v4");
+        assert_matches!(
+            layer.simulate(&request.body(()).unwrap()),
+            NegotiationReport::Failed { status: 404, .. }
+        );
+
+        let request = Request::get("/api/v1/foo");
+        assert_matches!(
+            layer.simulate(&request.body(()).unwrap()),
+            NegotiationReport::Failed { status: 410, .. }
+        );
+
+        let request = Request::get("/foo");
+        assert_matches!(
+            layer.simulate(&request.body(()).unwrap()),
+            NegotiationReport::Skipped
+        );
+    }
+
+    #[test]
+    fn test_rewrite_uri() {
+        let uri = Uri::from_static("http://localhost/api/test?foo=bar");
+        let uri = rewrite_uri(&uri, "/api", 1);
+        assert_matches!(uri, Ok(uri) if uri == "http://localhost/api/v1/test?foo=bar");
+
+        let uri = Uri::from_static("/api");
+        let uri = rewrite_uri(&uri, "/api", 0);
+        assert_matches!(uri, Ok(uri) if uri == "/api/v0");
+
+        let uri = Uri::from_static("/apidoc/test");
+        let uri = rewrite_uri(&uri, "/api", 1);
+        assert_matches!(uri, Err(RewriteError::NotUnderBasePath(path)) if path == "/apidoc/test");
+
+        let uri = Uri::from_static("/api/test");
+        let uri = rewrite_uri(&uri, "/api", 10_000);
+        assert_matches!(uri, Err(RewriteError::InvalidVersion(10_000)));
+
+        let uri = Uri::from_static("example.com:80");
+        let uri = rewrite_uri(&uri, "", 1);
+        assert_matches!(uri, Err(RewriteError::MissingPath));
+
+        let uri = Uri::from_static("/api/test");
+        let uri = rewrite_uri(&uri, "/a b", 1);
+        assert_matches!(uri, Err(RewriteError::InvalidBasePath(base_path)) if base_path == "/a b");
+
+        let uri = Uri::from_static("/api/test");
+        let uri = rewrite_uri(&uri, "/api?x", 1);
+        assert_matches!(uri, Err(RewriteError::InvalidBasePath(_)));
+    }
 }
@@ -0,0 +1,264 @@
+//! Discovery of the API versions and their lifecycle, e.g. for SDKs choosing a version, see
+//! [ApiVersionLayer::discovery_endpoint](crate::ApiVersionLayer::discovery_endpoint).
+
+use crate::Config;
+use httpdate::fmt_http_date;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::fmt::Write;
+
+/// JSON Schema (draft 2020-12) for [VersionManifest]s as served by the discovery endpoint.
+pub const JSON_SCHEMA: &str = r#"{
+  "$schema": "https://json-schema.org/draft/2020-12/schema",
+  "title": "VersionManifest",
+  "type": "object",
+  "required": ["versions", "default_version"],
+  "properties": {
+    "versions": {
+      "type": "array",
+      "items": {
+        "type": "object",
+        "required": ["version", "status"],
+        "properties": {
+          "version": { "type": "integer", "minimum": 0, "maximum": 9999 },
+          "status": { "enum": ["active", "deprecated", "retired"] },
+          "deprecation": { "type": "string", "description": "HTTP-date" },
+          "sunset": { "type": "string", "description": "HTTP-date" },
+          "changelog": { "type": "string", "format": "uri-reference" },
+          "capabilities": { "type": "array", "items": { "type": "string" } },
+          "migration_guides": {
+            "type": "array",
+            "items": {
+              "type": "object",
+              "required": ["from", "to", "url"],
+              "properties": {
+                "from": { "type": "integer", "minimum": 0, "maximum": 9999 },
+                "to": { "type": "integer", "minimum": 0, "maximum": 9999 },
+                "url": { "type": "string", "format": "uri-reference" }
+              }
+            }
+          }
+        }
+      }
+    },
+    "default_version": { "type": "integer", "minimum": 0, "maximum": 9999 }
+  },
+  "additionalProperties": true
+}"#;
+
+/// Manifest of the API versions, see [JSON_SCHEMA]. Applications can extend it with their own
+/// metadata before serialization.
+///
+/// # Examples
+///
+/// ```
+/// # use api_version::{ApiVersionLayer, ApiVersions};
+/// # use serde_json::json;
+/// const API_VERSIONS: ApiVersions<2> = ApiVersions::new([0, 1]);
+///
+/// let layer = ApiVersionLayer::new("/api", API_VERSIONS);
+/// let manifest = layer.manifest().with_metadata("service", json!("users"));
+/// assert_eq!(
+///     serde_json::to_value(manifest).unwrap(),
+///     json!({
+///         "versions": [
+///             { "version": 0, "status": "active" },
+///             { "version": 1, "status": "active" }
+///         ],
+///         "default_version": 1,
+///         "service": "users"
+///     })
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct VersionManifest {
+    /// The API versions.
+    pub versions: Vec<VersionInfo>,
+
+    /// The default version.
+    pub default_version: u16,
+
+    /// Additional metadata, serialized as top-level fields.
+    #[serde(flatten)]
+    pub metadata: Map<String, Value>,
+}
+
+impl VersionManifest {
+    /// Add the given metadata field.
+    pub fn with_metadata(mut self, key: impl Into<String>, value: Value) -> Self {
+        self.metadata.insert(key.into(), value);
+        self
+    }
+
+    /// Render a small human-readable HTML page listing the versions with their status, sunset,
+    /// changelog and migration guides, see
+    /// [ApiVersionLayer::html_index](crate::ApiVersionLayer::html_index).
+    pub fn to_html(&self) -> String {
+        let mut html = String::from(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+             <title>API versions</title>\n</head>\n<body>\n<h1>API versions</h1>\n<table>\n\
+             <tr><th>Version</th><th>Status</th><th>Sunset</th><th>Documentation</th></tr>\n",
+        );
+
+        for info in &self.versions {
+            let status = match info.status {
+                VersionStatus::Active => "active",
+                VersionStatus::Deprecated => "deprecated",
+                VersionStatus::Retired => "retired",
+            };
+            let sunset = info.sunset.as_deref().map(escape).unwrap_or_default();
+            let links = info
+                .changelog
+                .iter()
+                .map(|url| format!("<a href=\"{}\">changelog</a>", escape(url)))
+                .chain(info.migration_guides.iter().map(|guide| {
+                    format!(
+                        "<a href=\"{}\">migration to v{}</a>",
+                        escape(&guide.url),
+                        guide.to
+                    )
+                }))
+                .collect::<Vec<_>>()
+                .join(", ");
+            let default = if info.version == self.default_version {
+                " (default)"
+            } else {
+                ""
+            };
+            let _ = writeln!(
+                html,
+                "<tr><td>v{}{default}</td><td>{status}</td><td>{sunset}</td><td>{links}</td></tr>",
+                info.version
+            );
+        }
+
+        html.push_str("</table>\n</body>\n</html>\n");
+        html
+    }
+}
+
+/// Escape the given text for HTML.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Lifecycle information for a single API version.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct VersionInfo {
+    /// The version.
+    pub version: u16,
+
+    /// The lifecycle status.
+    pub status: VersionStatus,
+
+    /// The deprecation as HTTP-date, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deprecation: Option<String>,
+
+    /// The sunset as HTTP-date, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sunset: Option<String>,
+
+    /// The changelog URL, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub changelog: Option<String>,
+
+    /// The names of the supported capabilities, see
+    /// [ApiVersionLayer::capabilities](crate::ApiVersionLayer::capabilities).
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub capabilities: Vec<String>,
+
+    /// The migration guides from this version to other ones.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub migration_guides: Vec<MigrationGuide>,
+}
+
+/// Migration guide for the transition from one version to another, see
+/// [ApiVersionLayer::migration_guide](crate::ApiVersionLayer::migration_guide).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MigrationGuide {
+    /// The version to migrate from.
+    pub from: u16,
+
+    /// The version to migrate to.
+    pub to: u16,
+
+    /// The URL of the migration guide.
+    pub url: String,
+}
+
+/// Lifecycle status of an API version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum VersionStatus {
+    /// The version is neither deprecated nor retired.
+    Active,
+
+    /// The version is deprecated, but not yet retired.
+    Deprecated,
+
+    /// The version is retired, i.e. requests are rejected.
+    Retired,
+}
+
+impl<const N: usize> Config<N> {
+    /// See [ApiVersionLayer::manifest](crate::ApiVersionLayer::manifest).
+    pub(crate) fn manifest(&self) -> VersionManifest {
+        let versions = self
+            .versions
+            .iter()
+            .filter(|&&version| !self.is_internal(version))
+            .map(|&version| VersionInfo {
+                version,
+                status: self.status(version),
+                deprecation: self.deprecation(version).map(fmt_http_date),
+                sunset: self.sunset(version).map(fmt_http_date),
+                changelog: self.changelogs.get(&version).cloned(),
+                capabilities: self
+                    .capabilities
+                    .iter()
+                    .filter(|capability| capability.supports(version))
+                    .map(|capability| capability.name().to_owned())
+                    .collect(),
+                migration_guides: self
+                    .migration_guides
+                    .iter()
+                    .filter(|guide| guide.from == version)
+                    .cloned()
+                    .collect(),
+            })
+            .collect();
+
+        VersionManifest {
+            versions,
+            default_version: self.external_version(self.current_default_version()),
+            metadata: self.manifest_metadata.clone(),
+        }
+    }
+
+    /// The current lifecycle status of the given version.
+    pub(crate) fn status(&self, version: u16) -> VersionStatus {
+        if self.is_retired(version) {
+            VersionStatus::Retired
+        } else if self
+            .deprecation(version)
+            .is_some_and(|deprecation| deprecation <= self.clock.now())
+        {
+            VersionStatus::Deprecated
+        } else {
+            VersionStatus::Active
+        }
+    }
+}
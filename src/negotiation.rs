@@ -0,0 +1,297 @@
+//! Negotiation of API versions independent of the tower service, e.g. for unit tests of the
+//! versioning configuration, CLIs or the debug endpoint, see
+//! [ApiVersionLayer::debug_endpoint](crate::ApiVersionLayer::debug_endpoint), or for handlers of
+//! applications which do not rewrite paths at all, see [NegotiatedVersion].
+
+use crate::{ApiVersionLayer, Config, ResolvedApiVersion, VersionSource};
+use axum::{
+    extract::{FromRef, FromRequestParts},
+    http::{HeaderMap, Method, Request, StatusCode, Uri, request::Parts},
+};
+use httpdate::fmt_http_date;
+use serde::Serialize;
+use std::{
+    fmt::{self, Debug, Formatter},
+    sync::Arc,
+};
+
+/// Input for [negotiate], i.e. the relevant parts of a request.
+#[derive(Debug, Clone, Copy)]
+pub struct NegotiationInput<'a> {
+    /// The request method.
+    pub method: &'a Method,
+
+    /// The request URI.
+    pub uri: &'a Uri,
+
+    /// The request headers.
+    pub headers: &'a HeaderMap,
+}
+
+/// Outcome of [negotiate].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum NegotiationReport {
+    /// The request is not versioned, e.g. because its path does not start with the base path.
+    Skipped,
+
+    /// The negotiation failed, i.e. the request is rejected.
+    Failed {
+        /// The status code of the error response.
+        status: u16,
+
+        /// The default message of the error response.
+        message: String,
+    },
+
+    /// The negotiation succeeded.
+    #[non_exhaustive]
+    Negotiated {
+        /// The resolved version.
+        version: u16,
+
+        /// The source of the resolved version.
+        source: VersionSource,
+
+        /// The URI passed to the inner service, i.e. the rewritten one, if rewritten.
+        uri: String,
+
+        /// Whether the resolved version is deprecated.
+        deprecated: bool,
+
+        /// The sunset of the resolved version as HTTP date, if any.
+        sunset: Option<String>,
+
+        /// The maximum request body size for the resolved version, if any.
+        max_body_size: Option<usize>,
+
+        /// The timeout in milliseconds for the resolved version, if any.
+        timeout_ms: Option<u128>,
+    },
+}
+
+/// Negotiate the version for the given input according to the given layer's configuration,
+/// exactly like the [ApiVersionService](crate::ApiVersionService) does, yet without calling the
+/// inner service. To decide exactly alike, the custom [Policy](crate::policy::Policy) objects and
+/// the [VersionHealth](crate::VersionHealth) are consulted, hence these should be free of side
+/// effects; the middleware's own side effects, e.g. auditing, pinning clients for a
+/// [Drain](crate::Drain) or tracking the deprecation budget, are skipped.
+///
+/// # Examples
+///
+/// ```
+/// # use api_version::{
+/// #     ApiVersionLayer, ApiVersions, VersionSource,
+/// #     negotiation::{NegotiationInput, NegotiationReport, negotiate},
+/// # };
+/// # use axum::http::{HeaderMap, Method, Uri};
+/// const API_VERSIONS: ApiVersions<2> = ApiVersions::new([0, 1]);
+///
+/// let layer = ApiVersionLayer::new("/api", API_VERSIONS);
+/// let input = NegotiationInput {
+///     method: &Method::GET,
+///     uri: &Uri::from_static("/api/test"),
+///     headers: &HeaderMap::new(),
+/// };
+/// let report = negotiate(&input, &layer);
+/// assert!(matches!(
+///     report,
+///     NegotiationReport::Negotiated { version: 1, source: VersionSource::Defaulted, .. }
+/// ));
+/// ```
+pub fn negotiate<const N: usize>(
+    input: &NegotiationInput<'_>,
+    layer: &ApiVersionLayer<N>,
+) -> NegotiationReport {
+    layer.config.report(input)
+}
+
+/// Shared negotiation according to a layer's configuration for [NegotiatedVersion], created via
+/// [ApiVersionLayer::negotiator]. Clones are cheap.
+#[derive(Clone)]
+pub struct Negotiator(Arc<dyn Report>);
+
+impl Debug for Negotiator {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Negotiator").finish_non_exhaustive()
+    }
+}
+
+/// Object-safe negotiation, erasing the number of API versions.
+trait Report: Send + Sync + 'static {
+    fn report(&self, input: &NegotiationInput<'_>) -> NegotiationReport;
+}
+
+impl<const N: usize> Report for Config<N> {
+    fn report(&self, input: &NegotiationInput<'_>) -> NegotiationReport {
+        Config::report(self, input)
+    }
+}
+
+impl<const N: usize> ApiVersionLayer<N> {
+    /// Simulate the negotiation for the given request, see [negotiate], e.g. for unit tests of the
+    /// application's versioning configuration without constructing routers. The body is ignored,
+    /// hence `()` does.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use api_version::{
+    /// #     ApiVersionLayer, ApiVersions, X_API_VERSION, negotiation::NegotiationReport,
+    /// # };
+    /// # use axum::http::Request;
+    /// const API_VERSIONS: ApiVersions<2> = ApiVersions::new([0, 1]);
+    ///
+    /// let layer = ApiVersionLayer::new("/api", API_VERSIONS);
+    /// let request = Request::get("/api/foo").header(&X_API_VERSION, "v3").body(()).unwrap();
+    /// assert!(matches!(
+    ///     layer.simulate(&request),
+    ///     NegotiationReport::Failed { status: 404, .. }
+    /// ));
+    /// ```
+    pub fn simulate<B>(&self, request: &Request<B>) -> NegotiationReport {
+        let input = NegotiationInput {
+            method: request.method(),
+            uri: request.uri(),
+            headers: request.headers(),
+        };
+        self.config.report(&input)
+    }
+
+    /// A [Negotiator] with this layer's configuration, to be provided via the router state for
+    /// the [NegotiatedVersion] extractor.
+    pub fn negotiator(&self) -> Negotiator {
+        Negotiator(Arc::new(self.config.clone()))
+    }
+}
+
+/// Extractor for the negotiated version and its source, usable without the
+/// [ApiVersionLayer], e.g. for applications which want version awareness
+/// in handlers, but no path rewriting at all: if the layer has already negotiated the version,
+/// its result is used, else the version is negotiated by the [Negotiator] from the router state
+/// like the layer would do. Requests which fail the negotiation are rejected like the layer
+/// would do, requests not subject to versioning, e.g. not under the base path, with
+/// `500 Internal Server Error`.
+///
+/// # Examples
+///
+/// ```
+/// # use api_version::{
+/// #     ApiVersionLayer, ApiVersions,
+/// #     negotiation::{NegotiatedVersion, Negotiator},
+/// # };
+/// # use axum::{Router, extract::FromRef, routing::get};
+/// const API_VERSIONS: ApiVersions<2> = ApiVersions::new([0, 1]);
+///
+/// #[derive(Clone)]
+/// struct AppState {
+///     negotiator: Negotiator,
+/// }
+///
+/// impl FromRef<AppState> for Negotiator {
+///     fn from_ref(state: &AppState) -> Self {
+///         state.negotiator.clone()
+///     }
+/// }
+///
+/// async fn users(NegotiatedVersion { version, .. }: NegotiatedVersion) -> String {
+///     format!("users for v{version}")
+/// }
+///
+/// let negotiator = ApiVersionLayer::new("/api", API_VERSIONS).negotiator();
+/// let app: Router = Router::new()
+///     .route("/api/users", get(users))
+///     .with_state(AppState { negotiator });
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct NegotiatedVersion {
+    /// The negotiated version.
+    pub version: u16,
+
+    /// The source of the negotiated version.
+    pub source: VersionSource,
+}
+
+impl<S> FromRequestParts<S> for NegotiatedVersion
+where
+    Negotiator: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = (StatusCode, String);
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        if let Some(&ResolvedApiVersion(version)) = parts.extensions.get()
+            && let Some(&source) = parts.extensions.get()
+        {
+            return Ok(Self { version, source });
+        }
+
+        let input = NegotiationInput {
+            method: &parts.method,
+            uri: &parts.uri,
+            headers: &parts.headers,
+        };
+        match Negotiator::from_ref(state).0.report(&input) {
+            NegotiationReport::Negotiated {
+                version, source, ..
+            } => Ok(Self { version, source }),
+
+            NegotiationReport::Failed { status, message } => Err((
+                StatusCode::from_u16(status).unwrap_or(StatusCode::BAD_REQUEST),
+                message,
+            )),
+
+            NegotiationReport::Skipped => Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "request is not subject to versioning".to_string(),
+            )),
+        }
+    }
+}
+
+impl<const N: usize> Config<N> {
+    /// See [negotiate].
+    pub(crate) fn report(&self, input: &NegotiationInput<'_>) -> NegotiationReport {
+        let uri = match self.without_dot_segments(input.uri) {
+            Ok(uri) => uri,
+            Err(kind) => {
+                return NegotiationReport::Failed {
+                    status: kind.status_code().as_u16(),
+                    message: kind.to_string(),
+                };
+            }
+        };
+        let uri = uri.as_ref().unwrap_or(input.uri);
+
+        let internal = self.has_internal_access_header(input.headers);
+        match self.decide(input.method, uri, input.headers, internal) {
+            Ok(None) => NegotiationReport::Skipped,
+
+            Err(kind) => NegotiationReport::Failed {
+                status: kind.status_code().as_u16(),
+                message: kind.to_string(),
+            },
+
+            Ok(Some(negotiated)) => {
+                let version = negotiated.version;
+                let uri = self
+                    .rewritten_uri(uri, &negotiated)
+                    .unwrap_or_else(|| uri.to_owned());
+                NegotiationReport::Negotiated {
+                    version,
+                    source: negotiated.source,
+                    uri: uri.to_string(),
+                    deprecated: self.deprecation(version).is_some(),
+                    sunset: self.sunset(version).map(fmt_http_date),
+                    max_body_size: self.max_body_sizes.get(&version).copied(),
+                    timeout_ms: self
+                        .timeouts
+                        .get(&version)
+                        .map(|timeout| timeout.as_millis()),
+                }
+            }
+        }
+    }
+}
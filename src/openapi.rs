@@ -0,0 +1,350 @@
+//! Detection of breaking changes between two OpenAPI documents (JSON) of the same API version,
+//! e.g. generated via utoipa for the routes of a version before and after a change, such that
+//! release pipelines can block accidental breaking changes within an existing version, see
+//! [breaking_changes]. Requires the `"dev-tools"` feature, which also provides the
+//! `api-version-openapi-diff` binary taking the paths of the old and the new document.
+
+use serde_json::{Map, Value};
+use std::{
+    collections::BTreeSet,
+    fmt::{self, Display, Formatter},
+};
+
+const METHODS: [&str; 8] = [
+    "get", "put", "post", "delete", "options", "head", "patch", "trace",
+];
+
+/// Breaking change between two OpenAPI documents, see [breaking_changes]. The [Display]
+/// implementation gives a human-readable description.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum BreakingChange {
+    /// The path has been removed.
+    RemovedPath { path: String },
+
+    /// The operation, i.e. the method for the path, has been removed.
+    RemovedOperation { path: String, method: String },
+
+    /// The parameter of the operation has been added as required or has become required.
+    NewRequiredParameter {
+        path: String,
+        method: String,
+        location: String,
+        name: String,
+    },
+
+    /// The request body of the operation has been added as required or has become required.
+    NewRequiredRequestBody { path: String, method: String },
+
+    /// The response with the status of the operation has been removed.
+    RemovedResponse {
+        path: String,
+        method: String,
+        status: String,
+    },
+
+    /// The schema in the components has been removed.
+    RemovedSchema { schema: String },
+
+    /// The property of the schema in the components has been removed.
+    RemovedProperty { schema: String, property: String },
+
+    /// The type of the property of the schema in the components has changed.
+    ChangedPropertyType {
+        schema: String,
+        property: String,
+        old: String,
+        new: String,
+    },
+
+    /// The property of the schema in the components has been added as required or has become
+    /// required.
+    NewRequiredProperty { schema: String, property: String },
+}
+
+impl Display for BreakingChange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            BreakingChange::RemovedPath { path } => write!(f, "removed path {path}"),
+
+            BreakingChange::RemovedOperation { path, method } => {
+                write!(f, "removed operation {} {path}", method.to_uppercase())
+            }
+
+            BreakingChange::NewRequiredParameter {
+                path,
+                method,
+                location,
+                name,
+            } => write!(
+                f,
+                "new required {location} parameter '{name}' for {} {path}",
+                method.to_uppercase()
+            ),
+
+            BreakingChange::NewRequiredRequestBody { path, method } => {
+                write!(
+                    f,
+                    "new required request body for {} {path}",
+                    method.to_uppercase()
+                )
+            }
+
+            BreakingChange::RemovedResponse {
+                path,
+                method,
+                status,
+            } => write!(
+                f,
+                "removed response {status} for {} {path}",
+                method.to_uppercase()
+            ),
+
+            BreakingChange::RemovedSchema { schema } => write!(f, "removed schema {schema}"),
+
+            BreakingChange::RemovedProperty { schema, property } => {
+                write!(f, "removed property '{property}' of schema {schema}")
+            }
+
+            BreakingChange::ChangedPropertyType {
+                schema,
+                property,
+                old,
+                new,
+            } => write!(
+                f,
+                "changed type of property '{property}' of schema {schema} from {old} to {new}"
+            ),
+
+            BreakingChange::NewRequiredProperty { schema, property } => {
+                write!(f, "new required property '{property}' of schema {schema}")
+            }
+        }
+    }
+}
+
+/// The breaking changes from the given old to the given new OpenAPI document (JSON) of the same
+/// API version, ordered by path and schema. As schemas are not tracked through their usages,
+/// changes to component schemas are reported conservatively, i.e. removed properties as well as
+/// new required ones, no matter whether the schema is used for requests or responses. Local
+/// references to parameters, i.e. `"#/components/parameters/{name}"`, are resolved.
+///
+/// # Examples
+///
+/// ```
+/// # use api_version::openapi::breaking_changes;
+/// # use serde_json::json;
+/// let old = json!({
+///     "paths": {
+///         "/users": { "get": {}, "post": {} }
+///     }
+/// });
+/// let new = json!({
+///     "paths": {
+///         "/users": { "get": {} }
+///     }
+/// });
+/// let changes = breaking_changes(&old, &new);
+/// assert_eq!(changes.len(), 1);
+/// assert_eq!(changes[0].to_string(), "removed operation POST /users");
+/// ```
+pub fn breaking_changes(old: &Value, new: &Value) -> Vec<BreakingChange> {
+    let mut changes = Vec::new();
+
+    let new_paths = object(new, "paths");
+    for (path, old_item) in sorted(object(old, "paths")) {
+        let Some(new_item) = new_paths.and_then(|paths| paths.get(path)) else {
+            changes.push(BreakingChange::RemovedPath {
+                path: path.to_owned(),
+            });
+            continue;
+        };
+
+        for method in METHODS {
+            let Some(old_operation) = old_item.get(method) else {
+                continue;
+            };
+            let Some(new_operation) = new_item.get(method) else {
+                changes.push(BreakingChange::RemovedOperation {
+                    path: path.to_owned(),
+                    method: method.to_owned(),
+                });
+                continue;
+            };
+            operation_changes(
+                old,
+                new,
+                path,
+                method,
+                (old_item, old_operation),
+                (new_item, new_operation),
+                &mut changes,
+            );
+        }
+    }
+
+    let schemas = |document| object(document, "components").and_then(|c| c.get("schemas"));
+    let new_schemas = schemas(new);
+    for (name, old_schema) in sorted(schemas(old)) {
+        match new_schemas.and_then(|schemas| schemas.get(name)) {
+            Some(new_schema) => schema_changes(name, old_schema, new_schema, &mut changes),
+            None => changes.push(BreakingChange::RemovedSchema {
+                schema: name.to_owned(),
+            }),
+        }
+    }
+
+    changes
+}
+
+fn operation_changes(
+    old: &Value,
+    new: &Value,
+    path: &str,
+    method: &str,
+    (old_item, old_operation): (&Value, &Value),
+    (new_item, new_operation): (&Value, &Value),
+    changes: &mut Vec<BreakingChange>,
+) {
+    let old_required = required_parameters(old, old_item, old_operation);
+    for (location, name) in required_parameters(new, new_item, new_operation) {
+        if !old_required.contains(&(location.clone(), name.clone())) {
+            changes.push(BreakingChange::NewRequiredParameter {
+                path: path.to_owned(),
+                method: method.to_owned(),
+                location,
+                name,
+            });
+        }
+    }
+
+    let is_body_required = |operation: &Value| {
+        operation
+            .pointer("/requestBody/required")
+            .and_then(Value::as_bool)
+            .unwrap_or_default()
+    };
+    if is_body_required(new_operation) && !is_body_required(old_operation) {
+        changes.push(BreakingChange::NewRequiredRequestBody {
+            path: path.to_owned(),
+            method: method.to_owned(),
+        });
+    }
+
+    let new_responses = new_operation.get("responses");
+    for (status, _) in sorted(old_operation.get("responses")) {
+        if new_responses
+            .and_then(|responses| responses.get(status))
+            .is_none()
+        {
+            changes.push(BreakingChange::RemovedResponse {
+                path: path.to_owned(),
+                method: method.to_owned(),
+                status: status.to_owned(),
+            });
+        }
+    }
+}
+
+/// The required parameters of the given operation of the given path item as pairs of location
+/// and name, resolving local references in the given document.
+fn required_parameters(
+    document: &Value,
+    item: &Value,
+    operation: &Value,
+) -> BTreeSet<(String, String)> {
+    [item, operation]
+        .into_iter()
+        .filter_map(|value| value.get("parameters").and_then(Value::as_array))
+        .flatten()
+        .filter_map(
+            |parameter| match parameter.get("$ref").and_then(Value::as_str) {
+                Some(reference) => reference
+                    .strip_prefix("#/components/parameters/")
+                    .and_then(|name| document.pointer(&format!("/components/parameters/{name}"))),
+                None => Some(parameter),
+            },
+        )
+        .filter(|parameter| {
+            parameter
+                .get("required")
+                .and_then(Value::as_bool)
+                .unwrap_or_default()
+        })
+        .filter_map(|parameter| {
+            let location = parameter.get("in")?.as_str()?;
+            let name = parameter.get("name")?.as_str()?;
+            Some((location.to_owned(), name.to_owned()))
+        })
+        .collect()
+}
+
+fn schema_changes(
+    schema: &str,
+    old_schema: &Value,
+    new_schema: &Value,
+    changes: &mut Vec<BreakingChange>,
+) {
+    let new_properties = new_schema.get("properties");
+    for (property, old_property) in sorted(old_schema.get("properties")) {
+        let Some(new_property) = new_properties.and_then(|properties| properties.get(property))
+        else {
+            changes.push(BreakingChange::RemovedProperty {
+                schema: schema.to_owned(),
+                property: property.to_owned(),
+            });
+            continue;
+        };
+
+        let old_type = old_property.get("type");
+        let new_type = new_property.get("type");
+        if let (Some(old_type), Some(new_type)) = (old_type, new_type)
+            && old_type != new_type
+        {
+            changes.push(BreakingChange::ChangedPropertyType {
+                schema: schema.to_owned(),
+                property: property.to_owned(),
+                old: old_type.to_string(),
+                new: new_type.to_string(),
+            });
+        }
+    }
+
+    let required = |schema: &Value| {
+        schema
+            .get("required")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+            .map(ToOwned::to_owned)
+            .collect::<BTreeSet<_>>()
+    };
+    let old_required = required(old_schema);
+    for property in required(new_schema) {
+        if !old_required.contains(&property) {
+            changes.push(BreakingChange::NewRequiredProperty {
+                schema: schema.to_owned(),
+                property,
+            });
+        }
+    }
+}
+
+/// The given field of the given value as object, if any.
+fn object<'a>(value: &'a Value, field: &str) -> Option<&'a Value> {
+    value.get(field).filter(|value| value.is_object())
+}
+
+/// The entries of the given object, if any, sorted by key.
+fn sorted(object: Option<&Value>) -> Vec<(&str, &Value)> {
+    let mut entries = object
+        .and_then(Value::as_object)
+        .map(Map::iter)
+        .into_iter()
+        .flatten()
+        .map(|(key, value)| (key.as_str(), value))
+        .collect::<Vec<_>>();
+    entries.sort_unstable_by_key(|(key, _)| *key);
+    entries
+}
@@ -0,0 +1,459 @@
+//! Composable decision pipeline of an [ApiVersionLayer]: the decision whether and for which
+//! version a request is versioned is made by an ordered chain of policies, starting with the
+//! built-in stages, see [PolicyStage], into which custom [Policy] objects can be inserted or
+//! which they can replace, see [ApiVersionLayer::policy_before].
+
+use crate::{
+    ApiVersionLayer, Config, RequestedVersion, RpcPolicy, VersionSource, VersioningError,
+    X_API_VERSION, XApiVersion, is_rpc, version,
+};
+use axum::{
+    extract::Request,
+    http::{HeaderMap, Method, Uri},
+    response::Response,
+};
+use axum_extra::headers::HeaderMapExt;
+use std::{
+    fmt::{self, Debug, Formatter},
+    sync::Arc,
+};
+use tracing::debug;
+
+/// Custom policy in the decision chain, see [ApiVersionLayer::policy_before]. This is
+/// implemented for suitable closures, which only take part in the decision.
+///
+/// # Examples
+///
+/// ```
+/// # use api_version::{
+/// #     ApiVersionLayer, ApiVersions, VersionSource,
+/// #     policy::{PolicyContext, PolicyOutcome, PolicyStage},
+/// # };
+/// const API_VERSIONS: ApiVersions<2> = ApiVersions::new([0, 1]);
+///
+/// // Take the version from the "api-version" query parameter, if any.
+/// let query_version = |context: &mut PolicyContext<'_>| {
+///     let version = context
+///         .uri()
+///         .query()
+///         .into_iter()
+///         .flat_map(|query| query.split('&'))
+///         .find_map(|pair| pair.strip_prefix("api-version="))
+///         .and_then(|version| version.parse().ok());
+///     if let Some(version) = version {
+///         context.set_version(version, VersionSource::Policy);
+///     }
+///     PolicyOutcome::Continue
+/// };
+///
+/// let layer = ApiVersionLayer::new("/api", API_VERSIONS)
+///     .policy_before(PolicyStage::Extract, query_version);
+/// ```
+pub trait Policy: Send + Sync + 'static {
+    /// Take part in the decision for the request of the given context, e.g. by setting the
+    /// version. Defaults to [PolicyOutcome::Continue].
+    fn decide(&self, context: &mut PolicyContext<'_>) -> PolicyOutcome {
+        let _ = context;
+        PolicyOutcome::Continue
+    }
+
+    /// Render the response for the given negotiation error for the given request, if this policy
+    /// takes care of error rendering. Defaults to `None`.
+    fn render(&self, error: VersioningError, request: &Request) -> Option<Response> {
+        let _ = (error, request);
+        None
+    }
+}
+
+impl<F> Policy for F
+where
+    F: Fn(&mut PolicyContext<'_>) -> PolicyOutcome + Send + Sync + 'static,
+{
+    fn decide(&self, context: &mut PolicyContext<'_>) -> PolicyOutcome {
+        self(context)
+    }
+}
+
+/// Built-in stages of the decision chain, in default order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PolicyStage {
+    /// Skip requests with skipped methods, see
+    /// [ApiVersionLayer::skip_method], and skipped RPC requests, see
+    /// [ApiVersionLayer::rpc_policy], and set the path for requests under the base path, see
+    /// [PolicyContext::path], or skip them.
+    Filter,
+
+    /// Unless already set, set the version from a valid version prefix, the `"x-api-version"`
+    /// header, the `"prefer"` header, see [ApiVersionLayer::prefer_header], or the vendor media
+    /// type, see [ApiVersionLayer::vendor_media_type], rejecting
+    /// ambiguous requests in strict mode, see [ApiVersionLayer::strict].
+    Extract,
+
+    /// Unless already set, set the version to the default version, see
+    /// [ApiVersionLayer::default_version].
+    Default,
+
+    /// Reject unknown, internal and retired versions.
+    Validate,
+
+    /// Render negotiation errors, see [ApiVersionLayer::error_message].
+    Render,
+}
+
+/// Outcome of a policy in the decision chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PolicyOutcome {
+    /// Continue with the next policy.
+    Continue,
+
+    /// Do not version the request at all.
+    Skip,
+
+    /// Reject the request with the given error.
+    Reject(VersioningError),
+}
+
+/// Request and state of the decision, passed along the decision chain. After the last policy,
+/// requests without path are skipped and requests without version are rejected with
+/// [VersioningError::Unversioned].
+#[derive(Debug)]
+pub struct PolicyContext<'a> {
+    method: &'a Method,
+    uri: &'a Uri,
+    headers: &'a HeaderMap,
+    internal: bool,
+    versions: &'a [u16],
+    path: Option<String>,
+    version: Option<(u16, VersionSource)>,
+}
+
+impl<'a> PolicyContext<'a> {
+    /// The method of the request.
+    pub fn method(&self) -> &'a Method {
+        self.method
+    }
+
+    /// The URI of the request, with dot segments removed, if configured.
+    pub fn uri(&self) -> &'a Uri {
+        self.uri
+    }
+
+    /// The headers of the request.
+    pub fn headers(&self) -> &'a HeaderMap {
+        self.headers
+    }
+
+    /// Whether the request has internal access, see [ApiVersionLayer::internal_version].
+    pub fn is_internal(&self) -> bool {
+        self.internal
+    }
+
+    /// The API versions.
+    pub fn versions(&self) -> &'a [u16] {
+        self.versions
+    }
+
+    /// The path relative to the base path, without version prefix once extracted from it, if the
+    /// request is subject to versioning, which is set by [PolicyStage::Filter].
+    pub fn path(&self) -> Option<&str> {
+        self.path.as_deref()
+    }
+
+    /// Set the path relative to the base path, see [PolicyContext::path].
+    pub fn set_path(&mut self, path: impl Into<String>) {
+        self.path = Some(path.into());
+    }
+
+    /// The version and its source, if already set.
+    pub fn version(&self) -> Option<(u16, VersionSource)> {
+        self.version
+    }
+
+    /// Set the version and its source.
+    pub fn set_version(&mut self, version: u16, source: VersionSource) {
+        self.version = Some((version, source));
+    }
+}
+
+/// Link of the decision chain.
+#[derive(Clone)]
+pub(crate) enum PolicyLink {
+    Builtin(PolicyStage),
+    Custom(Arc<dyn Policy>),
+}
+
+impl PolicyLink {
+    /// The default chain, i.e. all built-in stages.
+    pub(crate) fn defaults() -> Vec<Self> {
+        [
+            PolicyStage::Filter,
+            PolicyStage::Extract,
+            PolicyStage::Default,
+            PolicyStage::Validate,
+            PolicyStage::Render,
+        ]
+        .into_iter()
+        .map(PolicyLink::Builtin)
+        .collect()
+    }
+}
+
+impl Debug for PolicyLink {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            PolicyLink::Builtin(stage) => stage.fmt(f),
+            PolicyLink::Custom(_) => f.write_str("Custom"),
+        }
+    }
+}
+
+impl<const N: usize> ApiVersionLayer<N> {
+    /// Insert the given policy into the decision chain right before the given built-in stage,
+    /// e.g. to extract the version from a query parameter with precedence over the built-in
+    /// sources via [PolicyStage::Extract] or to render negotiation errors in the application's
+    /// error format via [PolicyStage::Render]. Can be called multiple times; policies inserted
+    /// before the same stage are applied in order of insertion.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the given stage has been replaced, see [ApiVersionLayer::replace_policy].
+    pub fn policy_before(mut self, stage: PolicyStage, policy: impl Policy) -> Self {
+        let index = self.config.policy_index(stage);
+        self.config
+            .policies
+            .insert(index, PolicyLink::Custom(Arc::new(policy)));
+        self
+    }
+
+    /// Insert the given policy into the decision chain right after the given built-in stage, e.g.
+    /// to reject requests for certain versions via [PolicyStage::Validate]. Can be called
+    /// multiple times; policies inserted after the same stage are applied in reverse order of
+    /// insertion.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the given stage has been replaced, see [ApiVersionLayer::replace_policy].
+    pub fn policy_after(mut self, stage: PolicyStage, policy: impl Policy) -> Self {
+        let index = self.config.policy_index(stage);
+        self.config
+            .policies
+            .insert(index + 1, PolicyLink::Custom(Arc::new(policy)));
+        self
+    }
+
+    /// Replace the given built-in stage of the decision chain with the given policy. Negotiation
+    /// errors not rendered by any policy are rendered like by [PolicyStage::Render].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the given stage has already been replaced.
+    pub fn replace_policy(mut self, stage: PolicyStage, policy: impl Policy) -> Self {
+        let index = self.config.policy_index(stage);
+        self.config.policies[index] = PolicyLink::Custom(Arc::new(policy));
+        self
+    }
+}
+
+impl<const N: usize> Config<N> {
+    /// The index of the given built-in stage in the decision chain.
+    fn policy_index(&self, stage: PolicyStage) -> usize {
+        self.policies
+            .iter()
+            .position(|link| matches!(link, PolicyLink::Builtin(s) if *s == stage))
+            .expect("policy stage must not have been replaced")
+    }
+
+    /// Apply the decision chain to a request with the given method, URI and headers.
+    pub(crate) fn apply_policies<'a>(
+        &'a self,
+        method: &'a Method,
+        uri: &'a Uri,
+        headers: &'a HeaderMap,
+        internal: bool,
+    ) -> Result<Option<(String, u16, VersionSource)>, VersioningError> {
+        let mut context = PolicyContext {
+            method,
+            uri,
+            headers,
+            internal,
+            versions: &self.versions[..],
+            path: None,
+            version: None,
+        };
+
+        for link in &self.policies {
+            let outcome = match link {
+                PolicyLink::Builtin(stage) => self.apply_stage(*stage, &mut context),
+                PolicyLink::Custom(policy) => policy.decide(&mut context),
+            };
+            match outcome {
+                PolicyOutcome::Continue => {}
+                PolicyOutcome::Skip => return Ok(None),
+                PolicyOutcome::Reject(error) => return Err(error),
+            }
+        }
+
+        let Some(path) = context.path else {
+            debug!(%uri, "not rewriting the path, because no path has been set");
+            return Ok(None);
+        };
+        let Some((version, source)) = context.version else {
+            debug!(%uri, "rejecting request, because no version has been set");
+            return Err(VersioningError::Unversioned);
+        };
+        Ok(Some((path, version, source)))
+    }
+
+    /// Render the response for the given negotiation error for the given request by the first
+    /// policy in the decision chain doing so, if any.
+    pub(crate) fn render_policies(
+        &self,
+        error: VersioningError,
+        request: &Request,
+    ) -> Option<Response> {
+        self.policies.iter().find_map(|link| match link {
+            PolicyLink::Builtin(PolicyStage::Render) => Some(self.render_error(error, request)),
+            PolicyLink::Builtin(_) => None,
+            PolicyLink::Custom(policy) => policy.render(error, request),
+        })
+    }
+
+    fn apply_stage(&self, stage: PolicyStage, context: &mut PolicyContext<'_>) -> PolicyOutcome {
+        match stage {
+            PolicyStage::Filter => self.filter(context),
+            PolicyStage::Extract => self.extract(context),
+            PolicyStage::Default => {
+                if context.version.is_none() {
+                    let version = self.default_version_for(context.headers);
+                    context.set_version(version, VersionSource::Defaulted);
+                }
+                PolicyOutcome::Continue
+            }
+            PolicyStage::Validate => self.validate(context),
+            PolicyStage::Render => PolicyOutcome::Continue,
+        }
+    }
+
+    /// See [PolicyStage::Filter].
+    fn filter(&self, context: &mut PolicyContext<'_>) -> PolicyOutcome {
+        let PolicyContext {
+            method,
+            uri,
+            headers,
+            ..
+        } = *context;
+
+        // Skip requests with a skipped method.
+        if self.skipped_methods.contains(method) {
+            debug!(%method, "not rewriting the path, because method is skipped");
+            return PolicyOutcome::Skip;
+        }
+
+        // Skip gRPC-Web and Connect requests if configured.
+        if self.rpc_policy == RpcPolicy::Skip && is_rpc(headers) {
+            debug!("not rewriting the path, because RPC request is skipped");
+            return PolicyOutcome::Skip;
+        }
+
+        // Strip base path prefix or skip. Without rewriting, the base path itself, e.g. a single
+        // GraphQL endpoint, is versioned, too.
+        if let Some(path) = uri.path().strip_prefix(self.base_path.as_str())
+            && (path.starts_with('/') || !self.rewrite_path && path.is_empty())
+        {
+            context.set_path(path);
+            PolicyOutcome::Continue
+        } else if self.strict && self.rewrite_path && uri.path() == self.base_path {
+            debug!(%uri, "rejecting request for the base path itself");
+            PolicyOutcome::Reject(VersioningError::Unversioned)
+        } else {
+            debug!(%uri, "not rewriting the path, because does not start with base path");
+            PolicyOutcome::Skip
+        }
+    }
+
+    /// See [PolicyStage::Extract].
+    fn extract(&self, context: &mut PolicyContext<'_>) -> PolicyOutcome {
+        if context.version.is_some() {
+            return PolicyOutcome::Continue;
+        }
+        let Some(path) = context.path.as_deref() else {
+            return PolicyOutcome::Continue;
+        };
+        let PolicyContext {
+            headers, internal, ..
+        } = *context;
+
+        // Use the version of a valid version prefix of the path.
+        if let (Some(version), path) = version::strip_version(path)
+            && self.versions.contains(&version)
+            && self.is_visible(version, internal)
+        {
+            let path = path.to_owned();
+            context.set_path(path);
+            context.set_version(version, VersionSource::PathPrefix);
+            return PolicyOutcome::Continue;
+        }
+
+        if self.strict
+            && let Some(version) = path.split('/').nth(1).and_then(version::parse)
+            && !(self.versions.contains(&version) && self.is_visible(version, internal))
+        {
+            return PolicyOutcome::Reject(VersioningError::UnknownVersion(version));
+        }
+
+        // Use the version of the header or the vendor media type.
+        if headers
+            .get_all(&X_API_VERSION)
+            .iter()
+            .take(self.max_version_header_values + 1)
+            .count()
+            > self.max_version_header_values
+        {
+            debug!("too many version header values");
+            return PolicyOutcome::Reject(VersioningError::InvalidVersionHeader);
+        }
+        let header = headers.typed_try_get::<XApiVersion>();
+        if self.strict && header.is_err() {
+            return PolicyOutcome::Reject(VersioningError::InvalidVersionHeader);
+        }
+        let version = header
+            .ok()
+            .flatten()
+            .map(|XApiVersion(requested)| match requested {
+                RequestedVersion::Version(version) => (version, VersionSource::Header),
+                RequestedVersion::Latest => (self.latest_version(internal), VersionSource::Header),
+            })
+            .or_else(|| {
+                self.preferred_version(headers)
+                    .map(|version| (version, VersionSource::Preference))
+            })
+            .or_else(|| {
+                self.media_type_version(headers, internal)
+                    .map(|version| (version, VersionSource::MediaType))
+            });
+        if let Some((version, source)) = version {
+            context.set_version(version, source);
+        }
+
+        PolicyOutcome::Continue
+    }
+
+    /// See [PolicyStage::Validate].
+    fn validate(&self, context: &mut PolicyContext<'_>) -> PolicyOutcome {
+        let Some((version, _)) = context.version else {
+            return PolicyOutcome::Continue;
+        };
+
+        if !self.versions.contains(&version) || !self.is_visible(version, context.internal) {
+            return PolicyOutcome::Reject(VersioningError::UnknownVersion(version));
+        }
+        if self.is_retired(version) && !self.is_draining(version, context.headers) {
+            debug!(?version, "version is retired");
+            return PolicyOutcome::Reject(VersioningError::RetiredVersion(version));
+        }
+
+        PolicyOutcome::Continue
+    }
+}
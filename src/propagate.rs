@@ -0,0 +1,155 @@
+//! Client-side propagation of the resolved API version to upstream calls, such that the version
+//! context flows through service-to-service chains.
+
+use crate::{ResolvedApiVersion, X_API_VERSION, version_header_value};
+use axum::http::{HeaderMap, HeaderName, HeaderValue, Request};
+use std::task::{Context, Poll};
+use tower::{Layer, Service};
+
+/// Tower layer for HTTP clients setting the `"x-api-version"` header on outgoing requests which
+/// carry a [ResolvedApiVersion] extension, e.g. copied from the incoming request, or, with the
+/// `"tokio"` feature, are sent while handling a versioned request, see
+/// [current_version](crate::current_version). Requests which already have the header are not
+/// changed.
+///
+/// # Examples
+///
+/// ```
+/// # use api_version::{ResolvedApiVersion, X_API_VERSION, propagate::PropagateVersionLayer};
+/// # use axum::{body::Body, http::{Request, Response}};
+/// # use std::convert::Infallible;
+/// # use tower::{Layer, ServiceExt, service_fn};
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let upstream = service_fn(|request: Request<Body>| async move {
+///     assert_eq!(request.headers()[&X_API_VERSION], "v1");
+///     Ok::<_, Infallible>(Response::new(Body::empty()))
+/// });
+/// let client = PropagateVersionLayer::new().layer(upstream);
+///
+/// let mut request = Request::new(Body::empty());
+/// request.extensions_mut().insert(ResolvedApiVersion(1));
+/// client.oneshot(request).await.unwrap();
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct PropagateVersionLayer {
+    header: HeaderName,
+    baggage: bool,
+}
+
+impl PropagateVersionLayer {
+    /// Create a new propagation layer using the `"x-api-version"` header.
+    pub fn new() -> Self {
+        Self {
+            header: X_API_VERSION.clone(),
+            baggage: false,
+        }
+    }
+
+    /// Use the given header instead of `"x-api-version"`, e.g. if the upstream service expects a
+    /// different one.
+    pub fn header(mut self, header: HeaderName) -> Self {
+        self.header = header;
+        self
+    }
+
+    /// Also propagate the version as W3C Baggage member, see [set_baggage]; disabled by default.
+    pub fn baggage(mut self, baggage: bool) -> Self {
+        self.baggage = baggage;
+        self
+    }
+}
+
+impl Default for PropagateVersionLayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S> Layer<S> for PropagateVersionLayer {
+    type Service = PropagateVersion<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        PropagateVersion {
+            inner,
+            header: self.header.clone(),
+            baggage: self.baggage,
+        }
+    }
+}
+
+/// See [PropagateVersionLayer].
+#[derive(Debug, Clone)]
+pub struct PropagateVersion<S> {
+    inner: S,
+    header: HeaderName,
+    baggage: bool,
+}
+
+impl<S, B> Service<Request<B>> for PropagateVersion<S>
+where
+    S: Service<Request<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request<B>) -> Self::Future {
+        let version = request
+            .extensions()
+            .get::<ResolvedApiVersion>()
+            .map(|ResolvedApiVersion(version)| *version);
+        #[cfg(feature = "tokio")]
+        let version = version.or_else(crate::current_version);
+
+        if let Some(version) = version {
+            if !request.headers().contains_key(&self.header) {
+                request
+                    .headers_mut()
+                    .insert(self.header.clone(), version_header_value(version));
+            }
+            if self.baggage {
+                set_baggage(request.headers_mut(), version);
+            }
+        }
+
+        self.inner.call(request)
+    }
+}
+
+/// Key of the W3C Baggage member carrying the version, e.g. `"baggage: api.version=2"`.
+pub const BAGGAGE_KEY: &str = "api.version";
+
+/// Set the W3C Baggage member for the given version, i.e. `"api.version={version}"`, in the given
+/// headers, replacing an existing member with the same key and keeping all other members, such
+/// that downstream services and collectors see the version without custom headers.
+pub fn set_baggage(headers: &mut HeaderMap, version: u16) {
+    let mut members = headers
+        .get_all(&BAGGAGE)
+        .iter()
+        .filter_map(|baggage| baggage.to_str().ok())
+        .flat_map(|baggage| baggage.split(','))
+        .map(str::trim)
+        .filter(|member| {
+            !member.is_empty()
+                && member
+                    .split(['=', ';'])
+                    .next()
+                    .is_none_or(|key| key.trim() != BAGGAGE_KEY)
+        })
+        .collect::<Vec<_>>();
+    let member = format!("{BAGGAGE_KEY}={version}");
+    members.push(&member);
+
+    let baggage = HeaderValue::from_str(&members.join(","))
+        .expect("baggage members are a valid header value");
+    headers.insert(BAGGAGE.clone(), baggage);
+}
+
+/// Header name for the W3C Baggage HTTP header.
+static BAGGAGE: HeaderName = HeaderName::from_static("baggage");
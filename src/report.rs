@@ -0,0 +1,192 @@
+//! Summary of the effective versioning policy, intended to be logged once at startup, see
+//! [ApiVersionLayer::report](crate::ApiVersionLayer::report).
+
+use crate::{Config, DotSegmentPolicy, RpcPolicy, X_API_VERSION, manifest::VersionStatus};
+use httpdate::fmt_http_date;
+use serde::Serialize;
+use std::fmt::{self, Display, Formatter};
+
+/// Summary of the effective versioning policy of an [ApiVersionLayer](crate::ApiVersionLayer),
+/// taking the live state into account. The [Display] implementation gives a compact
+/// human-readable form, e.g. for logging at startup.
+///
+/// # Examples
+///
+/// ```
+/// # use api_version::{ApiVersionLayer, ApiVersions};
+/// # use std::time::UNIX_EPOCH;
+/// const API_VERSIONS: ApiVersions<2> = ApiVersions::new([0, 1]);
+///
+/// let layer = ApiVersionLayer::new("/api", API_VERSIONS).deprecation(0, UNIX_EPOCH);
+/// assert_eq!(
+///     layer.report().to_string(),
+///     "API versioning policy for /api\n\
+///      \x20 versions: v0 (deprecated), v1 (active)\n\
+///      \x20 default version: v1\n\
+///      \x20 sources: path prefix, header x-api-version, default\n\
+///      \x20 filters: dot segments normalized"
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[non_exhaustive]
+pub struct PolicyReport {
+    /// The base path, "/" if none.
+    pub base_path: String,
+
+    /// The versions with their lifecycle states.
+    pub versions: Vec<VersionReport>,
+
+    /// The current default version.
+    pub default_version: u16,
+
+    /// The policies affecting the default version, e.g. a scheduled change.
+    pub default_policies: Vec<String>,
+
+    /// The sources versions are extracted from, in order of precedence.
+    pub sources: Vec<String>,
+
+    /// The filters, i.e. settings restricting or skipping versioning.
+    pub filters: Vec<String>,
+}
+
+/// Lifecycle state of a version in a [PolicyReport].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[non_exhaustive]
+pub struct VersionReport {
+    /// The version.
+    pub version: u16,
+
+    /// The lifecycle status.
+    pub status: VersionStatus,
+
+    /// Whether the version is internal.
+    pub internal: bool,
+
+    /// The sunset as HTTP-date, if any.
+    pub sunset: Option<String>,
+}
+
+impl Display for PolicyReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let versions = self
+            .versions
+            .iter()
+            .map(|version| {
+                let mut details = vec![
+                    match version.status {
+                        VersionStatus::Active => "active",
+                        VersionStatus::Deprecated => "deprecated",
+                        VersionStatus::Retired => "retired",
+                    }
+                    .to_string(),
+                ];
+                if version.internal {
+                    details.push("internal".to_string());
+                }
+                if let Some(sunset) = &version.sunset {
+                    details.push(format!("sunset {sunset}"));
+                }
+                format!("v{} ({})", version.version, details.join(", "))
+            })
+            .collect::<Vec<_>>();
+
+        writeln!(f, "API versioning policy for {}", self.base_path)?;
+        writeln!(f, "  versions: {}", versions.join(", "))?;
+        write!(f, "  default version: v{}", self.default_version)?;
+        if !self.default_policies.is_empty() {
+            write!(f, " ({})", self.default_policies.join(", "))?;
+        }
+        writeln!(f)?;
+        writeln!(f, "  sources: {}", self.sources.join(", "))?;
+        write!(f, "  filters: ")?;
+        if self.filters.is_empty() {
+            write!(f, "none")
+        } else {
+            write!(f, "{}", self.filters.join(", "))
+        }
+    }
+}
+
+impl<const N: usize> Config<N> {
+    /// See [ApiVersionLayer::report](crate::ApiVersionLayer::report).
+    pub(crate) fn policy_report(&self) -> PolicyReport {
+        let versions = self
+            .versions
+            .iter()
+            .map(|&version| VersionReport {
+                version,
+                status: self.status(version),
+                internal: self.is_internal(version),
+                sunset: self.sunset(version).map(fmt_http_date),
+            })
+            .collect();
+
+        let mut default_policies = self
+            .default_schedule
+            .iter()
+            .map(|&(at, version)| format!("v{version} as of {}", fmt_http_date(at)))
+            .collect::<Vec<_>>();
+        if let Some(rollout) = self.rollout() {
+            default_policies.push(format!(
+                "rollout of v{} from {} to {}",
+                rollout.version,
+                fmt_http_date(rollout.start),
+                fmt_http_date(rollout.end)
+            ));
+        }
+        if let Some(experiment) = &self.default_experiment {
+            default_policies.push(format!("experiment {}", experiment.id));
+        }
+        if self.version_health.is_some() {
+            default_policies.push("health fallback".to_string());
+        }
+
+        let mut sources = vec!["path prefix".to_string(), format!("header {X_API_VERSION}")];
+        if self.prefer_header {
+            sources.push("header prefer".to_string());
+        }
+        if let Some(vendor) = &self.vendor {
+            sources.push(format!("media type application/vnd.{vendor}.v{{N}}+json"));
+        }
+        sources.push("default".to_string());
+
+        let mut filters = Vec::new();
+        if self.strict {
+            filters.push("strict".to_string());
+        }
+        if !self.rewrite_path {
+            filters.push("no path rewriting".to_string());
+        }
+        if !self.skipped_methods.is_empty() {
+            let methods = self
+                .skipped_methods
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>();
+            filters.push(format!("skipped methods {}", methods.join(" ")));
+        }
+        match self.rpc_policy {
+            RpcPolicy::Version => {}
+            RpcPolicy::Skip => filters.push("RPC requests skipped".to_string()),
+            RpcPolicy::MetadataOnly => filters.push("RPC requests not rewritten".to_string()),
+        }
+        match self.dot_segment_policy {
+            DotSegmentPolicy::Pass => {}
+            DotSegmentPolicy::Normalize => filters.push("dot segments normalized".to_string()),
+            DotSegmentPolicy::Reject => filters.push("dot segments rejected".to_string()),
+        }
+
+        PolicyReport {
+            base_path: if self.base_path.is_empty() {
+                "/".to_string()
+            } else {
+                self.base_path.clone()
+            },
+            versions,
+            default_version: self.current_default_version(),
+            default_policies,
+            sources,
+            filters,
+        }
+    }
+}
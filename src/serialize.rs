@@ -0,0 +1,125 @@
+//! Serializers for the responses of the discovery and debug endpoints, negotiated via the
+//! `"accept"` request header, e.g. YAML for ops tooling, see [Serializer] and
+//! [ApiVersionLayer::serializer](crate::ApiVersionLayer::serializer).
+
+use crate::{error_response, wire};
+use axum::{
+    Json,
+    http::{
+        HeaderMap, HeaderValue, StatusCode,
+        header::{ACCEPT, CONTENT_TYPE},
+    },
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use std::{fmt::Display, sync::Arc};
+use tracing::debug;
+
+/// Serializer for a media type other than JSON, which is always supported, see
+/// [ApiVersionLayer::serializer](crate::ApiVersionLayer::serializer). This is implemented for
+/// suitable closures.
+///
+/// # Examples
+///
+/// ```
+/// # use api_version::{ApiVersionLayer, ApiVersions};
+/// const API_VERSIONS: ApiVersions<2> = ApiVersions::new([0, 1]);
+///
+/// let layer = ApiVersionLayer::new("/api", API_VERSIONS)
+///     .discovery_endpoint(true)
+///     .serializer("text/plain", |value: &serde_json::Value| {
+///         serde_json::to_string_pretty(value) // e.g. serde_yaml::to_string for "application/yaml"
+///     });
+/// ```
+pub trait Serializer: Send + Sync + 'static {
+    /// Serialize the given value.
+    fn serialize(&self, value: &serde_json::Value) -> Result<String, String>;
+}
+
+impl<F, E> Serializer for F
+where
+    F: Fn(&serde_json::Value) -> Result<String, E> + Send + Sync + 'static,
+    E: Display,
+{
+    fn serialize(&self, value: &serde_json::Value) -> Result<String, String> {
+        self(value).map_err(|error| error.to_string())
+    }
+}
+
+/// Respond with the given value serialized according to the first media range of the
+/// `"accept"` header in the given headers supported by the given serializers or JSON, which is
+/// also the fallback.
+pub(crate) fn respond(
+    serializers: &[(HeaderValue, Arc<dyn Serializer>)],
+    headers: &HeaderMap,
+    value: impl Serialize,
+) -> Response {
+    let serializer = headers
+        .get_all(ACCEPT)
+        .iter()
+        .filter_map(|accept| accept.to_str().ok())
+        .flat_map(|accept| accept.split(','))
+        .filter_map(|media_range| {
+            let media_range = media_range.split(';').next()?.trim();
+            if ["*/*", "application/*", "application/json"]
+                .iter()
+                .any(|json| media_range.eq_ignore_ascii_case(json))
+            {
+                return Some(None);
+            }
+            serializers
+                .iter()
+                .find(|(media_type, _)| matches(media_range, media_type))
+                .map(Some)
+        })
+        .next()
+        .flatten();
+
+    let value = match serde_json::to_value(value) {
+        Ok(value) => value,
+        Err(error) => return serialization_error(error),
+    };
+    match serializer {
+        None => Json(value).into_response(),
+
+        Some((media_type, serializer)) => match serializer.serialize(&value) {
+            Ok(body) => ([(CONTENT_TYPE, media_type.clone())], body).into_response(),
+            Err(error) => serialization_error(error),
+        },
+    }
+}
+
+/// Check whether the first media range of the `"accept"` header in the given headers matches the
+/// given media type, e.g. `"text/html"` for browsers.
+pub(crate) fn prefers(headers: &HeaderMap, media_type: &str) -> bool {
+    headers
+        .get_all(ACCEPT)
+        .iter()
+        .filter_map(|accept| accept.to_str().ok())
+        .flat_map(|accept| accept.split(','))
+        .filter_map(|media_range| media_range.split(';').next())
+        .next()
+        .is_some_and(|media_range| media_range.trim().eq_ignore_ascii_case(media_type))
+}
+
+/// Check whether the given media range, e.g. `"application/*"`, matches the given media type.
+fn matches(media_range: &str, media_type: &HeaderValue) -> bool {
+    let Ok(media_type) = media_type.to_str() else {
+        return false;
+    };
+    match media_range.strip_suffix("/*") {
+        Some(range_type) => media_type
+            .split_once('/')
+            .is_some_and(|(type_, _)| type_.eq_ignore_ascii_case(range_type)),
+        None => media_type.eq_ignore_ascii_case(media_range),
+    }
+}
+
+fn serialization_error(error: impl Display) -> Response {
+    debug!(%error, "cannot serialize response");
+    error_response(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        wire::SERIALIZATION_FAILED,
+        format!("cannot serialize response: {error}"),
+    )
+}
@@ -0,0 +1,405 @@
+//! Live versioning state shared by multiple instances, see
+//! [ApiVersionLayer::state_store](crate::ApiVersionLayer::state_store).
+
+use crate::Config;
+#[cfg(feature = "watch-config")]
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::{Arc, PoisonError, RwLock},
+    time::SystemTime,
+};
+
+/// Storage for the live [VersionState], e.g. in memory, see [InMemoryVersionStateStore], or in a
+/// database shared by multiple instances. The state is loaded several times for every versioned
+/// request, e.g. for the default version and for the deprecation and sunset of the negotiated one,
+/// hence loading must be cheap: implementations should hand out a shared snapshot and those backed
+/// by remote storage should cache it and refresh it in the background.
+pub trait VersionStateStore: Send + Sync + 'static {
+    /// Load the current state, if any, as a shared snapshot.
+    fn load(&self) -> Option<Arc<VersionState>>;
+
+    /// Save the given state, replacing the current one.
+    fn save(&self, state: VersionState);
+}
+
+/// Live versioning state overriding the static configuration of the
+/// [ApiVersionLayer](crate::ApiVersionLayer): deprecations and sunsets are merged with the
+/// configured ones, the default version and the rollout replace the configured ones, if set.
+/// Versions which are not one of the API versions are ignored.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VersionState {
+    /// The default version, see
+    /// [ApiVersionLayer::default_version](crate::ApiVersionLayer::default_version).
+    pub default_version: Option<u16>,
+
+    /// The deprecations by version, see
+    /// [ApiVersionLayer::deprecation](crate::ApiVersionLayer::deprecation).
+    pub deprecations: BTreeMap<u16, SystemTime>,
+
+    /// The sunsets by version, see [ApiVersionLayer::sunset](crate::ApiVersionLayer::sunset).
+    pub sunsets: BTreeMap<u16, SystemTime>,
+
+    /// The rollout of a new default version, see
+    /// [ApiVersionLayer::ramp_default_version](crate::ApiVersionLayer::ramp_default_version).
+    pub rollout: Option<Rollout>,
+
+    /// The internal versions released to all clients, i.e. no longer internal, see
+    /// [ApiVersionLayer::internal_version](crate::ApiVersionLayer::internal_version).
+    pub released: BTreeSet<u16>,
+}
+
+/// Rollout of a new default version, see [VersionState::rollout].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rollout {
+    /// The version rolled out.
+    pub version: u16,
+
+    /// The start of the rollout.
+    pub start: SystemTime,
+
+    /// The end of the rollout.
+    pub end: SystemTime,
+}
+
+/// Change of the live versioning state, see [InMemoryVersionStateStore::subscribe].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum VersionEvent {
+    /// The given internal version has been added for all clients, see [VersionState::released].
+    Added {
+        /// The added version.
+        version: u16,
+    },
+
+    /// The given version has been deprecated at the given time.
+    Deprecated {
+        /// The deprecated version.
+        version: u16,
+
+        /// The deprecation.
+        at: SystemTime,
+    },
+
+    /// The given version has been scheduled to be retired at the given time.
+    SunsetScheduled {
+        /// The version to be retired.
+        version: u16,
+
+        /// The sunset.
+        at: SystemTime,
+    },
+
+    /// The default version has been changed to the given one or reset to the configured one.
+    DefaultChanged {
+        /// The new default version, if any.
+        version: Option<u16>,
+    },
+
+    /// The rollout of a new default version has been changed to the given one, if any.
+    RolloutChanged {
+        /// The new rollout, if any.
+        rollout: Option<Rollout>,
+    },
+}
+
+/// In-memory [VersionStateStore], e.g. for a single instance updating its state at runtime or for
+/// tests. Clones share the same state.
+#[derive(Debug, Clone)]
+#[cfg_attr(not(feature = "tokio"), derive(Default))]
+pub struct InMemoryVersionStateStore {
+    state: Arc<RwLock<Option<Arc<VersionState>>>>,
+    #[cfg(feature = "tokio")]
+    events: tokio::sync::broadcast::Sender<VersionEvent>,
+}
+
+impl InMemoryVersionStateStore {
+    /// Create an empty in-memory store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Subscribe to the [VersionEvent]s emitted when saving a state which differs from the
+    /// current one, e.g. to invalidate caches or notify clients. Slow subscribers lag behind and
+    /// miss the oldest events, see [tokio::sync::broadcast].
+    #[cfg(feature = "tokio")]
+    pub fn subscribe(&self) -> tokio::sync::broadcast::Receiver<VersionEvent> {
+        self.events.subscribe()
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Default for InMemoryVersionStateStore {
+    fn default() -> Self {
+        Self {
+            state: Arc::default(),
+            events: tokio::sync::broadcast::Sender::new(64),
+        }
+    }
+}
+
+impl VersionStateStore for InMemoryVersionStateStore {
+    fn load(&self) -> Option<Arc<VersionState>> {
+        self.state
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    fn save(&self, state: VersionState) {
+        let mut current = self.state.write().unwrap_or_else(PoisonError::into_inner);
+        #[cfg(feature = "tokio")]
+        for event in events(current.as_deref(), &state) {
+            // Sending only fails without subscribers.
+            let _ = self.events.send(event);
+        }
+        *current = Some(Arc::new(state));
+    }
+}
+
+/// The events for the change from the given current state, if any, to the given new one.
+#[cfg(feature = "tokio")]
+fn events(current: Option<&VersionState>, new: &VersionState) -> Vec<VersionEvent> {
+    let empty = VersionState::default();
+    let current = current.unwrap_or(&empty);
+    let mut events = vec![];
+
+    for &version in new.released.difference(&current.released) {
+        events.push(VersionEvent::Added { version });
+    }
+
+    for (&version, &at) in &new.deprecations {
+        if current.deprecations.get(&version) != Some(&at) {
+            events.push(VersionEvent::Deprecated { version, at });
+        }
+    }
+
+    for (&version, &at) in &new.sunsets {
+        if current.sunsets.get(&version) != Some(&at) {
+            events.push(VersionEvent::SunsetScheduled { version, at });
+        }
+    }
+
+    if current.default_version != new.default_version {
+        events.push(VersionEvent::DefaultChanged {
+            version: new.default_version,
+        });
+    }
+
+    if current.rollout != new.rollout {
+        events.push(VersionEvent::RolloutChanged {
+            rollout: new.rollout,
+        });
+    }
+
+    events
+}
+
+/// Format of the file watched by
+/// [ApiVersionLayer::watch_config](crate::ApiVersionLayer::watch_config), a JSON representation
+/// of [VersionState] with times as HTTP-dates, e.g.:
+///
+/// ```json
+/// {
+///   "default_version": 1,
+///   "deprecations": { "0": "Sun, 01 Jun 2025 00:00:00 GMT" },
+///   "sunsets": { "0": "Wed, 01 Oct 2025 00:00:00 GMT" }
+/// }
+/// ```
+#[cfg(feature = "watch-config")]
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VersionStateConfig {
+    /// See [VersionState::default_version].
+    pub default_version: Option<u16>,
+
+    /// See [VersionState::deprecations].
+    pub deprecations: BTreeMap<u16, String>,
+
+    /// See [VersionState::sunsets].
+    pub sunsets: BTreeMap<u16, String>,
+
+    /// See [VersionState::rollout].
+    pub rollout: Option<RolloutConfig>,
+
+    /// See [VersionState::released].
+    pub released: BTreeSet<u16>,
+}
+
+/// See [Rollout], with times as HTTP-dates.
+#[cfg(feature = "watch-config")]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RolloutConfig {
+    /// See [Rollout::version].
+    pub version: u16,
+
+    /// See [Rollout::start].
+    pub start: String,
+
+    /// See [Rollout::end].
+    pub end: String,
+}
+
+#[cfg(feature = "watch-config")]
+impl TryFrom<VersionStateConfig> for VersionState {
+    type Error = httpdate::Error;
+
+    fn try_from(config: VersionStateConfig) -> Result<Self, Self::Error> {
+        let parse_all = |times: BTreeMap<u16, String>| {
+            times
+                .into_iter()
+                .map(|(version, time)| httpdate::parse_http_date(&time).map(|time| (version, time)))
+                .collect::<Result<BTreeMap<_, _>, _>>()
+        };
+        let rollout = config
+            .rollout
+            .map(|rollout| {
+                Ok::<_, httpdate::Error>(Rollout {
+                    version: rollout.version,
+                    start: httpdate::parse_http_date(&rollout.start)?,
+                    end: httpdate::parse_http_date(&rollout.end)?,
+                })
+            })
+            .transpose()?;
+
+        Ok(VersionState {
+            default_version: config.default_version,
+            deprecations: parse_all(config.deprecations)?,
+            sunsets: parse_all(config.sunsets)?,
+            rollout,
+            released: config.released,
+        })
+    }
+}
+
+/// Load the file at the given path into the given store and reload it whenever its content
+/// changes, until the store is dropped. Changes are detected by watching the parent directory of
+/// the file, such that replacing the file, e.g. by editors or Kubernetes config maps, is detected,
+/// too.
+#[cfg(feature = "watch-config")]
+pub(crate) fn watch(path: std::path::PathBuf, store: InMemoryVersionStateStore) {
+    use notify::{RecursiveMode, Watcher};
+    use tracing::{debug, warn};
+
+    fn load(path: &std::path::Path, content: &[u8]) -> Option<VersionState> {
+        let state = serde_json::from_slice::<VersionStateConfig>(content)
+            .map_err(|error| error.to_string())
+            .and_then(|config| VersionState::try_from(config).map_err(|error| error.to_string()));
+        match state {
+            Ok(state) => {
+                debug!(path = %path.display(), "loaded versioning state");
+                Some(state)
+            }
+
+            Err(error) => {
+                warn!(path = %path.display(), error, "cannot load versioning state");
+                None
+            }
+        }
+    }
+
+    // Load synchronously, such that the state is applied before the first request.
+    let mut last_content = std::fs::read(&path).ok();
+    match &last_content {
+        Some(content) => {
+            if let Some(state) = load(&path, content) {
+                store.save(state);
+            }
+        }
+
+        None => warn!(path = %path.display(), "cannot read versioning state file"),
+    }
+
+    let (changes_tx, mut changes_rx) = tokio::sync::mpsc::unbounded_channel();
+    let watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        if event.is_ok_and(|event| !event.kind.is_access()) {
+            let _ = changes_tx.send(());
+        }
+    })
+    .and_then(|mut watcher| {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => std::path::Path::new("."),
+        };
+        watcher.watch(dir, RecursiveMode::NonRecursive)?;
+        Ok(watcher)
+    });
+    let watcher = match watcher {
+        Ok(watcher) => watcher,
+        Err(error) => {
+            warn!(path = %path.display(), %error, "cannot watch versioning state file");
+            return;
+        }
+    };
+
+    let state = Arc::downgrade(&store.state);
+    let events = store.events.clone();
+    tokio::spawn(async move {
+        // Keep watching as long as this task runs.
+        let _watcher = watcher;
+
+        while changes_rx.recv().await.is_some() {
+            let Some(state) = state.upgrade() else {
+                break;
+            };
+            let store = InMemoryVersionStateStore {
+                state,
+                events: events.clone(),
+            };
+
+            let content = tokio::fs::read(&path).await.ok();
+            if content.is_some() && content != last_content {
+                if let Some(state) = content.as_deref().and_then(|content| load(&path, content)) {
+                    store.save(state);
+                }
+                last_content = content;
+            }
+        }
+    });
+}
+
+impl<const N: usize> Config<N> {
+    /// The deprecation of the given version, if any, taking the live state into account.
+    pub(crate) fn deprecation(&self, version: u16) -> Option<SystemTime> {
+        self.stored_state()
+            .and_then(|state| state.deprecations.get(&version).copied())
+            .or_else(|| self.deprecations.get(&version).copied())
+    }
+
+    /// The sunset of the given version, if any, taking the live state into account.
+    pub(crate) fn sunset(&self, version: u16) -> Option<SystemTime> {
+        self.stored_state()
+            .and_then(|state| state.sunsets.get(&version).copied())
+            .or_else(|| self.sunsets.get(&version).copied())
+    }
+
+    /// The configured default version, taking the live state into account.
+    pub(crate) fn configured_default_version(&self) -> u16 {
+        self.stored_state()
+            .and_then(|state| state.default_version)
+            .filter(|version| self.versions.contains(version))
+            .unwrap_or(self.default_version)
+    }
+
+    /// The rollout of a new default version, if any, taking the live state into account.
+    pub(crate) fn rollout(&self) -> Option<Rollout> {
+        self.stored_state()
+            .and_then(|state| state.rollout)
+            .filter(|rollout| self.versions.contains(&rollout.version))
+            .or(self.default_ramp)
+    }
+
+    /// Check whether the given version is internal, i.e. flagged as internal and not released,
+    /// taking the live state into account.
+    pub(crate) fn is_internal(&self, version: u16) -> bool {
+        self.internal_versions.contains(&version)
+            && !self
+                .stored_state()
+                .is_some_and(|state| state.released.contains(&version))
+    }
+
+    fn stored_state(&self) -> Option<Arc<VersionState>> {
+        self.state_store.as_ref().and_then(|store| store.load())
+    }
+}
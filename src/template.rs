@@ -0,0 +1,128 @@
+//! Rewrite templates governing where the version segment is inserted into rewritten paths, see
+//! [ApiVersionLayer::rewrite_template](crate::ApiVersionLayer::rewrite_template).
+
+use std::fmt::Write;
+
+/// Parsed and validated rewrite template.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct RewriteTemplate {
+    template: String,
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    Base,
+    Version,
+    Path,
+    Resource,
+    Rest,
+}
+
+impl RewriteTemplate {
+    /// Parse and validate the given template.
+    pub(crate) fn parse(template: &str) -> Result<Self, String> {
+        let mut parts = Vec::new();
+        let mut rest = template;
+        while !rest.is_empty() {
+            match rest.find('{') {
+                Some(0) => {
+                    let end = rest
+                        .find('}')
+                        .ok_or_else(|| "unclosed placeholder".to_string())?;
+                    let part = match &rest[1..end] {
+                        "base" => Part::Base,
+                        "version" => Part::Version,
+                        "path" => Part::Path,
+                        "resource" => Part::Resource,
+                        "rest" => Part::Rest,
+                        name => return Err(format!("unknown placeholder '{{{name}}}'")),
+                    };
+                    parts.push(part);
+                    rest = &rest[end + 1..];
+                }
+
+                index => {
+                    let (literal, tail) = rest.split_at(index.unwrap_or(rest.len()));
+                    if let Some(c) = literal
+                        .chars()
+                        .find(|&c| !c.is_ascii_graphic() || matches!(c, '}' | '?' | '#'))
+                    {
+                        return Err(format!("invalid character '{c}'"));
+                    }
+                    parts.push(Part::Literal(literal.to_owned()));
+                    rest = tail;
+                }
+            }
+        }
+
+        let count = |part: Part| parts.iter().filter(|p| **p == part).count();
+        if count(Part::Version) != 1 {
+            return Err("'{version}' must occur exactly once".to_string());
+        }
+        match (count(Part::Path), count(Part::Resource), count(Part::Rest)) {
+            (1, 0, 0) | (0, 1, 1) => {}
+            _ => {
+                return Err(
+                    "either '{path}' or '{resource}' and '{rest}' must occur exactly once"
+                        .to_string(),
+                );
+            }
+        }
+        if count(Part::Base) > 1 {
+            return Err("'{base}' must occur at most once".to_string());
+        }
+        match parts.as_slice() {
+            [Part::Literal(literal), ..] | [Part::Base, Part::Literal(literal), ..]
+                if literal.starts_with('/') => {}
+            _ => return Err("must start with '/' or '{base}/'".to_string()),
+        }
+
+        Ok(Self {
+            template: template.to_owned(),
+            parts,
+        })
+    }
+
+    /// The template as given.
+    pub(crate) fn as_str(&self) -> &str {
+        &self.template
+    }
+
+    /// Expand this template for the given base path, version and path, i.e. the request path
+    /// without base path and version prefix. An empty resource, e.g. for the path `"/"`, does not
+    /// leave an empty segment, i.e. one of its adjacent slashes is dropped.
+    pub(crate) fn expand(&self, base: &str, version: u16, path: &str) -> String {
+        let (resource, rest) = match path.strip_prefix('/') {
+            Some(path) => path.split_at(path.find('/').unwrap_or(path.len())),
+            None => ("", path),
+        };
+
+        let mut expanded = String::with_capacity(self.template.len() + base.len() + path.len());
+        let mut skip_slash = false;
+        for part in &self.parts {
+            match part {
+                Part::Literal(literal) if skip_slash => {
+                    expanded.push_str(literal.strip_prefix('/').unwrap_or(literal));
+                }
+                Part::Literal(literal) => expanded.push_str(literal),
+                Part::Base => expanded.push_str(base),
+                Part::Version => write!(expanded, "{version}").expect("can write to string"),
+                Part::Path => expanded.push_str(path),
+                Part::Resource if resource.is_empty() => {
+                    if expanded.ends_with('/') {
+                        expanded.pop();
+                    } else {
+                        skip_slash = true;
+                    }
+                    continue;
+                }
+                Part::Resource => expanded.push_str(resource),
+                Part::Rest => expanded.push_str(rest),
+            }
+            skip_slash = false;
+        }
+        expanded
+    }
+}
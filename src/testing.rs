@@ -0,0 +1,113 @@
+//! Helpers for testing the middleware behavior independent of axum routing, see
+//! [MockVersionedService].
+
+use crate::{ResolvedApiVersion, VersionSource};
+use axum::{body::Body, extract::Request, http::Uri, response::Response};
+use futures::future::{Ready, ready};
+use std::{
+    convert::Infallible,
+    sync::{Arc, Mutex, PoisonError},
+    task::{Context, Poll},
+};
+use tower::Service;
+
+/// Inner service recording the requests it receives and responding with `200 OK`, such that tests
+/// can assert exactly which URI, version and version source the middleware passed on. Clones
+/// share the recorded requests.
+///
+/// # Examples
+///
+/// ```
+/// # use api_version::{ApiVersionLayer, ApiVersions, testing::MockVersionedService};
+/// # use axum::{body::Body, http::Request};
+/// # use tower::{Layer, ServiceExt};
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// const API_VERSIONS: ApiVersions<2> = ApiVersions::new([0, 1]);
+///
+/// let inner = MockVersionedService::new();
+/// let app = ApiVersionLayer::new("/api", API_VERSIONS).layer(inner.clone());
+///
+/// let request = Request::builder().uri("/api/test?x=1").body(Body::empty()).unwrap();
+/// app.oneshot(request).await.unwrap();
+/// inner.assert_received(["/api/v1/test?x=1"]);
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct MockVersionedService {
+    received: Arc<Mutex<Vec<ReceivedRequest>>>,
+}
+
+/// Request received by a [MockVersionedService].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReceivedRequest {
+    /// The URI.
+    pub uri: Uri,
+
+    /// The resolved version, if any.
+    pub version: Option<u16>,
+
+    /// The source of the resolved version, if any.
+    pub source: Option<VersionSource>,
+}
+
+impl MockVersionedService {
+    /// Create a mock service without any received requests.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The requests received so far.
+    pub fn received(&self) -> Vec<ReceivedRequest> {
+        self.received
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Assert that exactly the given URIs have been received so far, in this order.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the received URIs differ from the given ones.
+    #[track_caller]
+    pub fn assert_received<'a>(&self, uris: impl IntoIterator<Item = &'a str>) {
+        let received = self
+            .received()
+            .into_iter()
+            .map(|request| request.uri.to_string())
+            .collect::<Vec<_>>();
+        let expected = uris.into_iter().map(ToOwned::to_owned).collect::<Vec<_>>();
+        assert_eq!(
+            received, expected,
+            "received URIs differ from expected ones"
+        );
+    }
+}
+
+impl Service<Request> for MockVersionedService {
+    type Response = Response;
+    type Error = Infallible;
+    type Future = Ready<Result<Response, Infallible>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        let received = ReceivedRequest {
+            uri: request.uri().to_owned(),
+            version: request
+                .extensions()
+                .get::<ResolvedApiVersion>()
+                .map(|version| version.0),
+            source: request.extensions().get::<VersionSource>().copied(),
+        };
+        self.received
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(received);
+
+        ready(Ok(Response::new(Body::empty())))
+    }
+}
@@ -0,0 +1,260 @@
+//! Declarative transformations of JSON bodies per version, e.g. removing a field added in a later
+//! version from the responses for an earlier one, see
+//! [ApiVersionLayer::response_transform](crate::ApiVersionLayer::response_transform), or filling
+//! a default for a field added in a later version into the requests for an earlier one, see
+//! [ApiVersionLayer::request_transform](crate::ApiVersionLayer::request_transform).
+
+use crate::{error_response, wire};
+use axum::{
+    body::{Body, to_bytes},
+    extract::Request,
+    http::{
+        HeaderMap, StatusCode,
+        header::{CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE},
+    },
+    response::{IntoResponse, Response},
+};
+use http_body_util::LengthLimitError;
+use serde_json::Value;
+use std::error::Error;
+use tracing::{debug, warn};
+
+/// Transformation of the top-level fields of a JSON object or of each object of a JSON array.
+/// Operations are applied in the order they have been added.
+///
+/// # Examples
+///
+/// ```
+/// # use api_version::transform::JsonTransform;
+/// # use serde_json::json;
+/// let transform = JsonTransform::new().remove("email").rename("full_name", "name");
+///
+/// let mut value = json!({ "full_name": "Jane", "email": "jane@example.com" });
+/// transform.apply(&mut value);
+/// assert_eq!(value, json!({ "name": "Jane" }));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct JsonTransform {
+    operations: Vec<Operation>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Operation {
+    Remove(String),
+    Rename(String, String),
+    Fill(String, Value),
+}
+
+impl JsonTransform {
+    /// Create an empty JSON transformation.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Remove the given field.
+    pub fn remove(mut self, field: impl Into<String>) -> Self {
+        self.operations.push(Operation::Remove(field.into()));
+        self
+    }
+
+    /// Rename the given field, if present.
+    pub fn rename(mut self, from: impl Into<String>, to: impl Into<String>) -> Self {
+        self.operations
+            .push(Operation::Rename(from.into(), to.into()));
+        self
+    }
+
+    /// Insert the given value for the given field, if absent, e.g. a default for a field which
+    /// is required by the newest version yet unknown to earlier ones.
+    pub fn fill(mut self, field: impl Into<String>, value: Value) -> Self {
+        self.operations.push(Operation::Fill(field.into(), value));
+        self
+    }
+
+    /// Apply this transformation to the given JSON value; values other than objects or arrays of
+    /// objects are not changed.
+    pub fn apply(&self, value: &mut Value) {
+        match value {
+            Value::Object(object) => {
+                for operation in &self.operations {
+                    match operation {
+                        Operation::Remove(field) => {
+                            object.remove(field);
+                        }
+
+                        Operation::Rename(from, to) => {
+                            if let Some(value) = object.remove(from) {
+                                object.insert(to.to_owned(), value);
+                            }
+                        }
+
+                        Operation::Fill(field, value) => {
+                            object
+                                .entry(field.to_owned())
+                                .or_insert_with(|| value.to_owned());
+                        }
+                    }
+                }
+            }
+
+            Value::Array(values) => values
+                .iter_mut()
+                .filter(|value| value.is_object())
+                .for_each(|value| self.apply(value)),
+
+            _ => {}
+        }
+    }
+}
+
+/// Apply the given transformation to the given response, if it has a JSON body.
+pub(crate) async fn transform_response(transform: &JsonTransform, response: Response) -> Response {
+    if !is_json(response.headers()) {
+        debug!("not transforming response, because not JSON");
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let bytes = match to_bytes(body, usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(error) => {
+            warn!(%error, "cannot read response body");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let body = match serde_json::from_slice::<Value>(&bytes) {
+        Ok(mut value) => {
+            transform.apply(&mut value);
+            parts.headers.remove(CONTENT_LENGTH);
+            Body::from(serde_json::to_vec(&value).expect("JSON value can be serialized"))
+        }
+
+        Err(error) => {
+            debug!(%error, "not transforming response, because invalid JSON");
+            Body::from(bytes)
+        }
+    };
+
+    Response::from_parts(parts, body)
+}
+
+/// Apply the given transformation to the given request for the given version, if it has a JSON
+/// body, buffering at most the given number of bytes and, if the given flag is set, also bodies
+/// without content length.
+pub(crate) async fn transform_request(
+    transform: &JsonTransform,
+    request: Request,
+    version: u16,
+    limit: usize,
+    transform_streaming_requests: bool,
+) -> Result<Request, Response> {
+    if !is_json(request.headers()) {
+        debug!("not transforming request, because not JSON");
+        return Ok(request);
+    }
+
+    // Refuse early instead of buffering.
+    let content_length = request
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|content_length| content_length.to_str().ok())
+        .and_then(|content_length| content_length.parse::<u64>().ok());
+    match content_length {
+        Some(content_length) if content_length > limit as u64 => {
+            debug!(
+                ?version,
+                content_length, "request body too large for transformation"
+            );
+            return Err(error_response(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                wire::BODY_TOO_LARGE,
+                format!("request body too large for version '{version}'"),
+            ));
+        }
+
+        None if !transform_streaming_requests => {
+            debug!(
+                ?version,
+                "not buffering streaming request for transformation"
+            );
+            return Err(error_response(
+                StatusCode::LENGTH_REQUIRED,
+                wire::LENGTH_REQUIRED,
+                format!("content length required for version '{version}'"),
+            ));
+        }
+
+        _ => {}
+    }
+
+    let (mut parts, body) = request.into_parts();
+    let bytes = match to_bytes(body, limit).await {
+        Ok(bytes) => bytes,
+
+        Err(error) if is_length_limit_error(&error) => {
+            debug!(?version, "request body too large for transformation");
+            return Err(error_response(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                wire::BODY_TOO_LARGE,
+                format!("request body too large for version '{version}'"),
+            ));
+        }
+
+        Err(error) => {
+            debug!(%error, "cannot read request body");
+            return Err((StatusCode::BAD_REQUEST, "cannot read request body").into_response());
+        }
+    };
+
+    let body = match serde_json::from_slice::<Value>(&bytes) {
+        Ok(mut value) => {
+            transform.apply(&mut value);
+            parts.headers.remove(CONTENT_LENGTH);
+            Body::from(serde_json::to_vec(&value).expect("JSON value can be serialized"))
+        }
+
+        // Let the handler reject invalid JSON.
+        Err(error) => {
+            debug!(%error, "not transforming request, because invalid JSON");
+            Body::from(bytes)
+        }
+    };
+
+    Ok(Request::from_parts(parts, body))
+}
+
+/// Check whether the given error is caused by exceeding a body size limit.
+/// Check whether the given headers have a `"content-encoding"` other than `"identity"`.
+pub(crate) fn is_encoded(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(CONTENT_ENCODING)
+        .iter()
+        .filter_map(|encoding| encoding.to_str().ok())
+        .flat_map(|encoding| encoding.split(','))
+        .any(|encoding| !encoding.trim().eq_ignore_ascii_case("identity"))
+}
+
+fn is_length_limit_error(error: &(dyn Error + 'static)) -> bool {
+    let mut error = Some(error);
+    while let Some(e) = error {
+        if e.is::<LengthLimitError>() {
+            return true;
+        }
+        error = e.source();
+    }
+    false
+}
+
+/// Check whether the given headers have a JSON content type, e.g. `"application/json"` or
+/// `"application/problem+json"`.
+fn is_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .and_then(|content_type| content_type.split(';').next())
+        .is_some_and(|mime| {
+            let mime = mime.trim();
+            mime == "application/json" || mime.ends_with("+json")
+        })
+}
@@ -1,14 +1,64 @@
-use api_version::{ApiVersionLayer, ApiVersions, X_API_VERSION};
+#[cfg(feature = "json-transform")]
+use api_version::transform::JsonTransform;
+use api_version::{
+    ApiVersionLayer, ApiVersionState, ApiVersions, BUCKETS, CircuitBreaker, DEPRECATION,
+    DotSegmentPolicy, Drain, InternalAccess, NegotiationStrategy, NoVersioning, OriginalRequestUri,
+    RedirectPolicy, ResolvedApiVersion, RpcPolicy, SUNSET, VersionScopedState, VersionSource,
+    VersioningError, X_API_DEFAULT_VERSION_CHANGING, X_API_SUNSET_IN, X_API_SUPPORTED_VERSIONS,
+    X_API_VERSION, X_API_VERSION_ERROR, X_API_VERSION_EXPERIMENT, bucket,
+    capability::{Capabilities, Capability},
+    cors::CorsPolicy,
+    manifest::VersionManifest,
+    middleware_fn, middleware_with_state,
+    negotiation::NegotiatedVersion,
+    policy::{Policy, PolicyContext, PolicyOutcome, PolicyStage},
+    propagate::PropagateVersionLayer,
+    state::{InMemoryVersionStateStore, VersionState, VersionStateStore},
+    testing::MockVersionedService,
+    versioned_paths,
+};
+#[cfg(feature = "tokio")]
+use api_version::{
+    ConcurrencyLimits, DeprecationBudget, RateLimitDecision,
+    audit::{NegotiationOutcome, NegotiationRecord},
+    current_version,
+    state::VersionEvent,
+};
+#[cfg(feature = "json-transform")]
+use axum::http::header::CONTENT_ENCODING;
+#[cfg(feature = "tokio")]
+use axum::http::header::CONTENT_LENGTH;
 use axum::{
-    Router,
-    body::Body,
-    http::{Request, StatusCode},
-    response::{IntoResponse, Response},
-    routing::get,
+    Extension, Json, Router,
+    body::{Body, Bytes},
+    extract::FromRef,
+    http::{
+        HeaderMap, HeaderName, HeaderValue, Method, Request, StatusCode,
+        header::{
+            ACCEPT, ACCEPT_LANGUAGE, ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS,
+            ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_EXPOSE_HEADERS, ACCESS_CONTROL_MAX_AGE,
+            ACCESS_CONTROL_REQUEST_METHOD, ALLOW, CACHE_CONTROL, CONTENT_TYPE, ETAG, IF_NONE_MATCH,
+            LINK, LOCATION, ORIGIN, VARY,
+        },
+    },
+    middleware::{from_fn, from_fn_with_state},
+    response::{
+        IntoResponse, Redirect, Response,
+        sse::{Event, Sse},
+    },
+    routing::{get, options, post},
 };
-use futures::{TryStreamExt, future::ok};
-use std::iter::Extend;
-use tower::{Layer, Service};
+use futures::{TryStreamExt, channel::mpsc, future::ok, stream};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    convert::Infallible,
+    iter::Extend,
+    sync::{Arc, Mutex},
+    time::{Duration, UNIX_EPOCH},
+};
+use tokio_test::{assert_pending, assert_ready_ok};
+use tower::{Layer, Service, ServiceExt};
+use tower_test::mock;
 
 const API_VERSIONS: ApiVersions<2> = ApiVersions::new([0, 1]);
 
@@ -68,6 +118,7 @@ async fn test() {
         .unwrap();
     let response = app.call(request).await.unwrap();
     assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(response.headers()[&X_API_SUPPORTED_VERSIONS], "v0, v1");
 
     // Valid version prefix (existing version).
     let request = Request::builder()
@@ -96,16 +147,3679 @@ async fn test() {
     assert_eq!(text(response).await, "api-doc");
 }
 
-async fn ready() -> impl IntoResponse {
-    "ready"
+#[tokio::test]
+async fn test_nested() {
+    let api = Router::new()
+        .route("/api/v0/test", get(ok_0))
+        .route("/api/v1/test", get(ok_1));
+    let api = ApiVersionLayer::nested("/public", "/public/api", API_VERSIONS).layer(api);
+
+    let mut app = Router::new()
+        .route("/ready", get(ready))
+        .nest_service("/public", api);
+
+    // No version should return the highest version.
+    let request = Request::builder()
+        .uri("/public/api/test")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(text(response).await, "1");
+
+    // Existing version.
+    let request = Request::builder()
+        .uri("/public/api/test")
+        .header(&X_API_VERSION, "v0")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(text(response).await, "0");
+
+    // Valid version prefix (existing version).
+    let request = Request::builder()
+        .uri("/public/api/v0/test")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(text(response).await, "0");
 }
 
-async fn ok_0() -> impl IntoResponse {
-    "0"
+#[tokio::test]
+async fn test_without_base_path() {
+    let app = Router::new()
+        .route("/v0/test", get(ok_0))
+        .route("/v1/", get(ok_1))
+        .route("/v1/test", get(ok_1));
+    let mut app = ApiVersionLayer::without_base_path(API_VERSIONS).layer(app);
+
+    // Every path is versioned, including the root.
+    for (uri, expected) in [("/", "1"), ("/test", "1"), ("/v0/test", "0")] {
+        let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+        let response = app.call(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(text(response).await, expected);
+    }
+
+    let request = Request::builder()
+        .uri("/test")
+        .header(&X_API_VERSION, "v0")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(text(response).await, "0");
+
+    // A base path of "/" means no base path, also when nested.
+    let app = Router::new().route("/v0/test", get(ok_0));
+    let api = ApiVersionLayer::nested("/public", "/public/", API_VERSIONS).layer(app);
+    let mut app = Router::new().nest_service("/public", api);
+    let request = Request::builder()
+        .uri("/public/test")
+        .header(&X_API_VERSION, "v0")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(text(response).await, "0");
 }
 
-async fn ok_1() -> impl IntoResponse {
-    "1"
+#[tokio::test]
+async fn test_subtree() {
+    let app = Router::new()
+        .route("/api/v0/test", get(ok_0))
+        .route("/api/v1/test", get(ok_1))
+        .route("/api/billing/v1/test", get(ok_1))
+        .route("/api/billing/v2/test", get(ok_2));
+    let mut app = ApiVersionLayer::new("/api", API_VERSIONS)
+        .subtree(ApiVersionLayer::new("/api/billing", ApiVersions::new([1, 2])).default_version(1))
+        .layer(app);
+
+    // Each subtree has its own versions and default.
+    for (uri, version, expected) in [
+        ("/api/test", None, "1"),
+        ("/api/test", Some("v0"), "0"),
+        ("/api/billing/test", None, "1"),
+        ("/api/billing/test", Some("v2"), "2"),
+        ("/api/billing/v2/test", None, "2"),
+    ] {
+        let mut request = Request::builder().uri(uri);
+        if let Some(version) = version {
+            request = request.header(&X_API_VERSION, version);
+        }
+        let response = app
+            .call(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(text(response).await, expected);
+    }
+
+    // No cross-talk between subtrees.
+    let request = Request::builder()
+        .uri("/api/billing/test")
+        .header(&X_API_VERSION, "v0")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(response.headers()[&X_API_SUPPORTED_VERSIONS], "v1, v2");
+
+    let request = Request::builder()
+        .uri("/api/test")
+        .header(&X_API_VERSION, "v2")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(response.headers()[&X_API_SUPPORTED_VERSIONS], "v0, v1");
+
+    // Other paths are passed through.
+    let request = Request::builder()
+        .uri("/apis/test")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_multi_segment_base_path() {
+    let app = Router::new()
+        .route("/public/api/v0/test", get(ok_0))
+        .route("/public/api/v1/test", get(ok_1));
+
+    let mut app = ApiVersionLayer::new("/public/api", API_VERSIONS).layer(app);
+
+    // No version should return the highest version.
+    let request = Request::builder()
+        .uri("/public/api/test")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(text(response).await, "1");
+
+    // Existing version.
+    let request = Request::builder()
+        .uri("/public/api/test")
+        .header(&X_API_VERSION, "v0")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(text(response).await, "0");
+}
+
+#[tokio::test]
+async fn test_strip_base_path() {
+    let app = Router::new()
+        .route("/v0/test", get(ok_0))
+        .route("/v1/test", get(ok_1));
+
+    let mut app = ApiVersionLayer::new("/api", API_VERSIONS)
+        .strip_base_path(true)
+        .layer(app);
+
+    // No version should return the highest version.
+    let request = Request::builder()
+        .uri("/api/test")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(text(response).await, "1");
+
+    // Existing version.
+    let request = Request::builder()
+        .uri("/api/test")
+        .header(&X_API_VERSION, "v0")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(text(response).await, "0");
+
+    // Valid version prefix (existing version).
+    let request = Request::builder()
+        .uri("/api/v0/test")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(text(response).await, "0");
+}
+
+#[tokio::test]
+async fn test_header_only() {
+    let app = Router::new()
+        .route("/api/test", get(resolved))
+        .route("/api/graphql", get(resolved));
+
+    let mut app = ApiVersionLayer::new("/api", API_VERSIONS)
+        .rewrite_path(false)
+        .echo_version(true)
+        .layer(app);
+
+    // No version should resolve the highest version.
+    let request = Request::builder()
+        .uri("/api/test")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[&X_API_VERSION], "v1");
+    assert_eq!(text(response).await, "1");
+
+    // Existing version for the base path itself.
+    let request = Request::builder()
+        .uri("/api/graphql")
+        .header(&X_API_VERSION, "v0")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[&X_API_VERSION], "v0");
+    assert_eq!(text(response).await, "0");
+
+    // Non-existing version.
+    let request = Request::builder()
+        .uri("/api/test")
+        .header(&X_API_VERSION, "v2")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_graphql() {
+    let app = Router::new().route("/api/graphql", post(graphql));
+
+    let mut app = ApiVersionLayer::new("/api/graphql", API_VERSIONS)
+        .rewrite_path(false)
+        .version_request_header(HeaderName::from_static("x-graphql-api-version"))
+        .layer(app);
+
+    // No version should resolve the highest version.
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri("/api/graphql")
+        .body(Body::from("{ test }"))
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(text(response).await, "v1: { test }");
+
+    // Existing version.
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri("/api/graphql")
+        .header(&X_API_VERSION, "v0")
+        .body(Body::from("{ test }"))
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(text(response).await, "v0: { test }");
+}
+
+#[tokio::test]
+async fn test_version_request_header() {
+    let app = Router::new()
+        .route("/api/v0/test", get(version_header))
+        .route("/api/v1/test", get(version_header));
+
+    let mut app = ApiVersionLayer::new("/api", API_VERSIONS)
+        .version_request_header(X_API_VERSION.clone())
+        .layer(app);
+
+    for (uri, version, expected) in [
+        ("/api/test", None, "v1"),
+        ("/api/v0/test", None, "v0"),
+        ("/api/v0/test", Some("v1"), "v0"),
+        ("/api/test", Some("latest"), "v1"),
+        ("/api/test", Some("invalid"), "v1"),
+    ] {
+        let mut request = Request::builder().uri(uri);
+        if let Some(version) = version {
+            request = request.header(&X_API_VERSION, version);
+        }
+        let response = app
+            .call(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(text(response).await, expected);
+    }
+}
+
+#[tokio::test]
+async fn test_streaming() {
+    let (tx, rx) = mpsc::unbounded::<Result<Bytes, Infallible>>();
+    let rx = Arc::new(Mutex::new(Some(rx)));
+    let app = Router::new()
+        .route(
+            "/api/v1/stream",
+            get(move || {
+                let rx = rx.lock().unwrap().take().unwrap();
+                async move { Body::from_stream(rx) }
+            }),
+        )
+        .route("/api/v1/sse", get(sse));
+
+    let mut app = ApiVersionLayer::new("/api", API_VERSIONS)
+        .echo_version(true)
+        .layer(app);
+
+    // Response headers are added before the body starts streaming.
+    let request = Request::builder()
+        .uri("/api/stream")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[&X_API_VERSION], "v1");
+    tx.unbounded_send(Ok(Bytes::from("1"))).unwrap();
+    drop(tx);
+    assert_eq!(text(response).await, "1");
+
+    // Server-sent events.
+    let request = Request::builder()
+        .uri("/api/sse")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[&X_API_VERSION], "v1");
+    assert_eq!(text(response).await, "data: 1\n\n");
+}
+
+#[tokio::test]
+async fn test_skip_streaming_responses() {
+    let app = Router::new()
+        .route("/api/v1/test", get(ok_1))
+        .route("/api/v1/sse", get(sse));
+
+    let mut app = ApiVersionLayer::new("/api", API_VERSIONS)
+        .echo_version(true)
+        .skip_streaming_responses(true)
+        .layer(app);
+
+    // Non-streaming responses get response headers.
+    let request = Request::builder()
+        .uri("/api/test")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[&X_API_VERSION], "v1");
+
+    // Streaming responses do not get response headers.
+    let request = Request::builder()
+        .uri("/api/sse")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(!response.headers().contains_key(&X_API_VERSION));
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn test_max_body_size_and_timeout() {
+    let app = Router::new()
+        .route("/api/v0/test", post(echo))
+        .route("/api/v1/test", post(echo))
+        .route("/api/v0/slow", get(slow))
+        .route("/api/v1/slow", get(slow));
+
+    let mut app = ApiVersionLayer::new("/api", API_VERSIONS)
+        .max_body_size(0, 4)
+        .timeout(0, Duration::from_millis(10))
+        .layer(app);
+
+    // Body within limit.
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri("/api/test")
+        .header(&X_API_VERSION, "v0")
+        .body(Body::from("1234"))
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(text(response).await, "1234");
+
+    // Body exceeding limit with content-length.
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri("/api/test")
+        .header(&X_API_VERSION, "v0")
+        .header(CONTENT_LENGTH, 5)
+        .body(Body::from("12345"))
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    // Body exceeding limit without content-length.
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri("/api/test")
+        .header(&X_API_VERSION, "v0")
+        .body(Body::from("12345"))
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    // No limit for other versions.
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri("/api/test")
+        .body(Body::from("12345"))
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Timeout.
+    let request = Request::builder()
+        .uri("/api/slow")
+        .header(&X_API_VERSION, "v0")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+    // No timeout for other versions.
+    let request = Request::builder()
+        .uri("/api/slow")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn test_rate_limit_policy() {
+    let app = Router::new()
+        .route("/api/v0/test", get(ok_0))
+        .route("/api/v1/test", get(ok_1));
+
+    let mut app = ApiVersionLayer::new("/api", API_VERSIONS)
+        .client_key_header(HeaderName::from_static("x-client-id"))
+        .rate_limit_policy(
+            |client_key: Option<&str>, version| match (client_key, version) {
+                (Some("greedy"), 0) => RateLimitDecision::Deny,
+                (_, 0) => RateLimitDecision::Slowdown(Duration::from_millis(1)),
+                _ => RateLimitDecision::Allow,
+            },
+        )
+        .layer(app);
+
+    // Denied.
+    let request = Request::builder()
+        .uri("/api/test")
+        .header(&X_API_VERSION, "v0")
+        .header("x-client-id", "greedy")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+    // Slowed down.
+    let request = Request::builder()
+        .uri("/api/test")
+        .header(&X_API_VERSION, "v0")
+        .header("x-client-id", "modest")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(text(response).await, "0");
+
+    // Allowed.
+    let request = Request::builder()
+        .uri("/api/test")
+        .header("x-client-id", "greedy")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(text(response).await, "1");
+}
+
+#[tokio::test]
+async fn test_caching_hints() {
+    let app = Router::new()
+        .route("/api/v0/test", get(etag))
+        .route("/api/v1/test", get(etag));
+
+    let mut app = ApiVersionLayer::new("/api", API_VERSIONS)
+        .cache_control(0, HeaderValue::from_static("no-store"))
+        .version_etags(true)
+        .layer(app);
+
+    let request = Request::builder()
+        .uri("/api/test")
+        .header(&X_API_VERSION, "v0")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[CACHE_CONTROL], "no-store");
+    assert_eq!(response.headers()[ETAG], r#""v0-abc""#);
+
+    let request = Request::builder()
+        .uri("/api/test")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[CACHE_CONTROL], "max-age=60");
+    assert_eq!(response.headers()[ETAG], r#""v1-abc""#);
+
+    let request = Request::builder()
+        .uri("/api/test")
+        .header(IF_NONE_MATCH, r#""v1-abc""#)
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+    let request = Request::builder()
+        .uri("/api/test")
+        .header(IF_NONE_MATCH, r#""v0-abc""#)
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_redirect_policy() {
+    let app = Router::new()
+        .route("/api/v0/old", get(redirect))
+        .route("/api/v1/old", get(redirect));
+
+    let mut app = ApiVersionLayer::new("/api", API_VERSIONS)
+        .redirect_policy(RedirectPolicy::Strip)
+        .layer(app);
+
+    let request = Request::builder()
+        .uri("/api/old")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+    assert_eq!(response.headers()[LOCATION], "/api/new?foo=bar");
+
+    let app = Router::new()
+        .route("/api/v0/old", get(redirect))
+        .route("/api/v1/old", get(redirect));
+
+    let mut app = ApiVersionLayer::new("/api", API_VERSIONS)
+        .redirect_policy(RedirectPolicy::Map(|version| format!("version-{version}")))
+        .layer(app);
+
+    let request = Request::builder()
+        .uri("/api/old")
+        .header(&X_API_VERSION, "v0")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+    assert_eq!(response.headers()[LOCATION], "/api/version-0/new?foo=bar");
+
+    for map in [
+        (|version| format!("release {version}")) as fn(u16) -> String,
+        |version| format!("release/{version}?"),
+        |_| String::new(),
+    ] {
+        let app = Router::new()
+            .route("/api/v0/old", get(redirect))
+            .route("/api/v1/old", get(redirect));
+
+        let mut app = ApiVersionLayer::new("/api", API_VERSIONS)
+            .redirect_policy(RedirectPolicy::Map(map))
+            .layer(app);
+
+        let request = Request::builder()
+            .uri("/api/old")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.call(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(response.headers()[LOCATION], "/api/v1/new?foo=bar");
+    }
+
+    let app = Router::new()
+        .route("/api/v0/old", get(redirect))
+        .route("/api/v1/old", get(redirect));
+
+    let mut app = ApiVersionLayer::new("/api", API_VERSIONS)
+        .redirect_policy(RedirectPolicy::Strip)
+        .forwarded_prefix(true)
+        .layer(app);
+
+    let request = Request::builder()
+        .uri("/api/old")
+        .header("x-forwarded-prefix", "/service-a/")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+    assert_eq!(response.headers()[LOCATION], "/service-a/api/new?foo=bar");
+
+    for forwarded_prefix in ["/a b", "/a?b", "service-a"] {
+        let request = Request::builder()
+            .uri("/api/old")
+            .header("x-forwarded-prefix", forwarded_prefix)
+            .body(Body::empty())
+            .unwrap();
+        let response = app.call(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(response.headers()[LOCATION], "/api/new?foo=bar");
+    }
+}
+
+#[tokio::test]
+async fn test_mock_versioned_service() {
+    let inner = MockVersionedService::new();
+    let mut app = ApiVersionLayer::new("/api", API_VERSIONS).layer(inner.clone());
+
+    for (uri, header) in [
+        ("/api/test?foo=bar", None),
+        ("/api/v0/test", None),
+        ("/api/v7/test", None),
+        ("/api/test", Some("v0")),
+        ("/api/", None),
+        ("/apix/test", None),
+        ("/test", None),
+    ] {
+        let mut request = Request::builder().uri(uri);
+        if let Some(header) = header {
+            request = request.header(&X_API_VERSION, header);
+        }
+        app.call(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+    }
+
+    inner.assert_received([
+        "/api/v1/test?foo=bar",
+        "/api/v0/test",
+        "/api/v1/v7/test",
+        "/api/v0/test",
+        "/api/v1/",
+        "/apix/test",
+        "/test",
+    ]);
+    let sources = inner
+        .received()
+        .into_iter()
+        .map(|request| request.source)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        sources,
+        [
+            Some(VersionSource::Defaulted),
+            Some(VersionSource::PathPrefix),
+            Some(VersionSource::Defaulted),
+            Some(VersionSource::Header),
+            Some(VersionSource::Defaulted),
+            None,
+            None,
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_tower_mock() {
+    let (inner, mut handle) = mock::pair::<Request<Body>, Response>();
+    let mut app = ApiVersionLayer::new("/api", API_VERSIONS).layer(inner);
+
+    for (uri, header, expected_uri, expected_version) in [
+        ("/api/test?foo=bar", None, "/api/v1/test?foo=bar", Some(1)),
+        ("/api/test", Some("v0"), "/api/v0/test", Some(0)),
+        ("/api/v0/test", None, "/api/v0/test", Some(0)),
+        ("/ready", None, "/ready", None),
+    ] {
+        let mut request = Request::builder().uri(uri);
+        if let Some(header) = header {
+            request = request.header(&X_API_VERSION, header);
+        }
+        let request = request.body(Body::empty()).unwrap();
+        let response = tokio::spawn(app.ready().await.unwrap().call(request));
+
+        let (request, send_response) = handle.next_request().await.unwrap();
+        assert_eq!(request.uri(), expected_uri);
+        assert_eq!(
+            request
+                .extensions()
+                .get::<ResolvedApiVersion>()
+                .map(|version| version.0),
+            expected_version
+        );
+        send_response.send_response(Response::new(Body::from("ok")));
+
+        let response = response.await.unwrap().unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(text(response).await, "ok");
+    }
+
+    // Failed negotiations do not call the inner service.
+    let request = Request::builder()
+        .uri("/api/test")
+        .header(&X_API_VERSION, "v2")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.ready().await.unwrap().call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    drop(app);
+    assert!(handle.next_request().await.is_none());
+}
+
+#[tokio::test]
+async fn test_tower_mock_backpressure() {
+    let (inner, mut handle) = mock::pair::<Request<Body>, Response>();
+    let mut app = mock::Spawn::new(ApiVersionLayer::new("/api", API_VERSIONS).layer(inner));
+
+    // The readiness of the inner service is passed on.
+    handle.allow(0);
+    assert_pending!(app.poll_ready());
+    handle.allow(1);
+    assert_ready_ok!(app.poll_ready());
+
+    // Errors of the inner service are passed on.
+    let request = Request::builder()
+        .uri("/api/test")
+        .body(Body::empty())
+        .unwrap();
+    let response = tokio::spawn(app.call(request));
+    let (_, send_response) = handle.next_request().await.unwrap();
+    send_response.send_error("boom");
+    let error = response.await.unwrap().unwrap_err();
+    assert_eq!(error.to_string(), "boom");
+}
+
+#[tokio::test]
+async fn test_rpc_policy() {
+    let app = Router::new()
+        .route("/api/pkg.Service/Method", post(resolved))
+        .route("/api/v1/pkg.Service/Method", post(ok_1));
+
+    let mut app = ApiVersionLayer::new("/api", API_VERSIONS)
+        .rpc_policy(RpcPolicy::MetadataOnly)
+        .layer(app);
+
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri("/api/pkg.Service/Method")
+        .header(CONTENT_TYPE, "application/grpc-web+proto")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(text(response).await, "1");
+
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri("/api/pkg.Service/Method")
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(text(response).await, "1");
+
+    let app = Router::new().route("/api/pkg.Service/Method", post(ok_0));
+
+    let mut app = ApiVersionLayer::new("/api", API_VERSIONS)
+        .rpc_policy(RpcPolicy::Skip)
+        .layer(app);
+
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri("/api/pkg.Service/Method")
+        .header("connect-protocol-version", "1")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(text(response).await, "0");
+}
+
+#[tokio::test]
+async fn test_versioned_paths() {
+    versioned_paths! {
+        base_path: "/api",
+        versions: [0, 1],
+        paths: { TEST = "/test" },
+    }
+
+    let app = Router::new()
+        .route(TEST.v(0), get(ok_0))
+        .route(TEST.v(1), get(ok_1));
+
+    let mut app = ApiVersionLayer::new("/api", API_VERSIONS).layer(app);
+
+    let request = Request::builder()
+        .uri(TEST.v(0))
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(text(response).await, "0");
+}
+
+#[tokio::test]
+async fn test_fallback() {
+    let app = Router::new()
+        .route("/api/v0/test", get(ok_0))
+        .route("/api/v1/test", get(ok_1))
+        .fallback(not_found);
+
+    let mut app = ApiVersionLayer::new("/api", API_VERSIONS).layer(app);
+
+    let request = Request::builder()
+        .uri("/api/unknown")
+        .header(&X_API_VERSION, "v0")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(
+        text(response).await,
+        "/api/unknown not found in v0 (header)"
+    );
+}
+
+#[tokio::test]
+async fn test_drain() {
+    let app = Router::new()
+        .route("/api/v0/test", get(ok_0))
+        .route("/api/v1/test", get(ok_1));
+
+    let sunset = UNIX_EPOCH + Duration::from_secs(1_800_000_000);
+    let now = Arc::new(Mutex::new(sunset - Duration::from_secs(1)));
+    let drain = Drain::new(Duration::from_secs(60)).capacity(2);
+    let mut app = ApiVersionLayer::new("/api", API_VERSIONS)
+        .client_key_header(HeaderName::from_static("x-client-id"))
+        .sunset(0, sunset)
+        .drain(drain.clone())
+        .clock({
+            let now = now.clone();
+            move || *now.lock().unwrap()
+        })
+        .layer(app);
+
+    // Pin client a to v0 before the sunset.
+    let request = Request::builder()
+        .uri("/api/v0/test")
+        .header("x-client-id", "a")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(drain.pinned_clients(0), 1);
+
+    // Pin clients up to the capacity only.
+    for client_key in ["a", "c", "d"] {
+        let request = Request::builder()
+            .uri("/api/v0/test")
+            .header("x-client-id", client_key)
+            .body(Body::empty())
+            .unwrap();
+        let response = app.call(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+    assert_eq!(drain.pinned_clients(0), 2);
+
+    // Within the drain window only pinned clients are served.
+    *now.lock().unwrap() = sunset;
+    for (client_key, status) in [
+        ("a", StatusCode::OK),
+        ("b", StatusCode::GONE),
+        ("c", StatusCode::OK),
+        ("d", StatusCode::GONE),
+    ] {
+        let request = Request::builder()
+            .uri("/api/v0/test")
+            .header("x-client-id", client_key)
+            .body(Body::empty())
+            .unwrap();
+        let response = app.call(request).await.unwrap();
+        assert_eq!(response.status(), status);
+    }
+    assert_eq!(drain.drained_requests(0), 2);
+
+    // After the drain window all clients get 410 and pinned clients are dropped.
+    *now.lock().unwrap() = sunset + Duration::from_secs(60);
+    let request = Request::builder()
+        .uri("/api/v0/test")
+        .header("x-client-id", "a")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::GONE);
+    assert_eq!(drain.pinned_clients(0), 0);
+}
+
+#[tokio::test]
+async fn test_state_store() {
+    let app = Router::new()
+        .route("/api/v0/test", get(ok_0))
+        .route("/api/v1/test", get(ok_1));
+
+    let store = InMemoryVersionStateStore::new();
+    let mut app = ApiVersionLayer::new("/api", API_VERSIONS)
+        .state_store(store.clone())
+        .layer(app);
+
+    let request = Request::builder()
+        .uri("/api/test")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(text(response).await, "1");
+
+    let sunset = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    store.save(VersionState {
+        default_version: Some(0),
+        sunsets: BTreeMap::from([(1, sunset)]),
+        ..Default::default()
+    });
+
+    let request = Request::builder()
+        .uri("/api/test")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(text(response).await, "0");
+
+    let request = Request::builder()
+        .uri("/api/v1/test")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::GONE);
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn test_version_events() {
+    let store = InMemoryVersionStateStore::new();
+    let mut events = store.subscribe();
+
+    let sunset = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    store.save(VersionState {
+        default_version: Some(0),
+        sunsets: BTreeMap::from([(1, sunset)]),
+        ..Default::default()
+    });
+    assert_eq!(
+        events.recv().await.unwrap(),
+        VersionEvent::SunsetScheduled {
+            version: 1,
+            at: sunset
+        }
+    );
+    assert_eq!(
+        events.recv().await.unwrap(),
+        VersionEvent::DefaultChanged { version: Some(0) }
+    );
+
+    store.save(VersionState {
+        sunsets: BTreeMap::from([(1, sunset)]),
+        ..Default::default()
+    });
+    assert_eq!(
+        events.recv().await.unwrap(),
+        VersionEvent::DefaultChanged { version: None }
+    );
+
+    store.save(VersionState {
+        sunsets: BTreeMap::from([(1, sunset)]),
+        released: BTreeSet::from([2]),
+        ..Default::default()
+    });
+    assert_eq!(
+        events.recv().await.unwrap(),
+        VersionEvent::Added { version: 2 }
+    );
+    assert!(events.try_recv().is_err());
+}
+
+#[cfg(feature = "watch-config")]
+#[tokio::test]
+async fn test_watch_config() {
+    let app = Router::new()
+        .route("/api/v0/test", get(ok_0))
+        .route("/api/v1/test", get(ok_1));
+
+    let path = std::env::temp_dir().join(format!("api-version-{}.json", std::process::id()));
+    std::fs::write(&path, r#"{ "default_version": 0 }"#).unwrap();
+    let mut app = ApiVersionLayer::new("/api", API_VERSIONS)
+        .watch_config(&path)
+        .layer(app);
+
+    let request = Request::builder()
+        .uri("/api/test")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(text(response).await, "0");
+
+    std::fs::write(
+        &path,
+        r#"{ "sunsets": { "1": "Tue, 14 Nov 2023 22:13:20 GMT" } }"#,
+    )
+    .unwrap();
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let request = Request::builder()
+        .uri("/api/v1/test")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::GONE);
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn test_deprecation_and_sunset() {
+    let app = Router::new()
+        .route("/api/v0/test", get(ok_0))
+        .route("/api/v1/test", get(ok_1));
+
+    let deprecation = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    let sunset = UNIX_EPOCH + Duration::from_secs(1_800_000_000);
+    let now = Arc::new(Mutex::new(sunset - Duration::from_secs(1)));
+    let layer = ApiVersionLayer::new("/api", API_VERSIONS)
+        .deprecation(0, deprecation)
+        .sunset(0, sunset)
+        .clock({
+            let now = now.clone();
+            move || *now.lock().unwrap()
+        });
+    let mut app = layer.layer(app);
+
+    // Deprecated version before sunset.
+    let request = Request::builder()
+        .uri("/api/test")
+        .header(&X_API_VERSION, "v0")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[&DEPRECATION], "@1700000000");
+    assert_eq!(response.headers()[&SUNSET], "Fri, 15 Jan 2027 08:00:00 GMT");
+    assert!(!response.headers().contains_key(&X_API_SUNSET_IN));
+
+    // Not deprecated version.
+    let request = Request::builder()
+        .uri("/api/test")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(!response.headers().contains_key(&DEPRECATION));
+    assert!(!response.headers().contains_key(&SUNSET));
+
+    // Retired version after sunset.
+    *now.lock().unwrap() = sunset;
+    let request = Request::builder()
+        .uri("/api/test")
+        .header(&X_API_VERSION, "v0")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::GONE);
+
+    // Retired version prefix after sunset.
+    let request = Request::builder()
+        .uri("/api/v0/test")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::GONE);
+    assert_eq!(response.headers()[&X_API_SUPPORTED_VERSIONS], "v1");
+}
+
+#[tokio::test]
+async fn test_sunset_countdown() {
+    let app = Router::new()
+        .route("/api/v0/test", get(ok_0))
+        .route("/api/v1/test", get(ok_1));
+
+    let sunset = UNIX_EPOCH + Duration::from_secs(1_800_000_000);
+    let mut app = ApiVersionLayer::new("/api", API_VERSIONS)
+        .sunset(0, sunset)
+        .sunset_countdown(true)
+        .clock(move || sunset - Duration::from_millis(86_400_500))
+        .layer(app);
+
+    let request = Request::builder()
+        .uri("/api/v0/test")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.headers()[&X_API_SUNSET_IN], "86400");
+
+    let request = Request::builder()
+        .uri("/api/v1/test")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert!(!response.headers().contains_key(&X_API_SUNSET_IN));
+}
+
+#[tokio::test]
+async fn test_default_version_advisory() {
+    let app = Router::new()
+        .route("/api/v0/test", get(ok_0))
+        .route("/api/v1/test", get(ok_1));
+
+    let at = UNIX_EPOCH + Duration::from_secs(1_759_276_800);
+    let now = Arc::new(Mutex::new(at - Duration::from_secs(1)));
+    let mut app = ApiVersionLayer::new("/api", API_VERSIONS)
+        .default_version(0)
+        .default_version_advisory(1, at)
+        .clock({
+            let now = now.clone();
+            move || *now.lock().unwrap()
+        })
+        .layer(app);
+
+    // No version should return the default version with advisory.
+    let request = Request::builder()
+        .uri("/api/test")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()[&X_API_DEFAULT_VERSION_CHANGING],
+        "v1 on Wed, 01 Oct 2025 00:00:00 GMT"
+    );
+    assert_eq!(text(response).await, "0");
+
+    // Explicit version should not get advisory.
+    let request = Request::builder()
+        .uri("/api/test")
+        .header(&X_API_VERSION, "v0")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(
+        !response
+            .headers()
+            .contains_key(&X_API_DEFAULT_VERSION_CHANGING)
+    );
+
+    // No advisory after the announced time.
+    *now.lock().unwrap() = at;
+    let request = Request::builder()
+        .uri("/api/test")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(
+        !response
+            .headers()
+            .contains_key(&X_API_DEFAULT_VERSION_CHANGING)
+    );
+}
+
+#[tokio::test]
+async fn test_schedule_default_version() {
+    let app = Router::new()
+        .route("/api/v0/test", get(ok_0))
+        .route("/api/v1/test", get(ok_1));
+
+    let now = Arc::new(Mutex::new(UNIX_EPOCH));
+    let clock = {
+        let now = now.clone();
+        move || *now.lock().unwrap()
+    };
+    let mut app = ApiVersionLayer::new("/api", API_VERSIONS)
+        .default_version(0)
+        .schedule_default_version(1, UNIX_EPOCH + Duration::from_secs(1_759_276_800))
+        .clock(clock)
+        .layer(app);
+
+    let request = Request::builder()
+        .uri("/api/test")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(
+        response.headers()[&X_API_DEFAULT_VERSION_CHANGING],
+        "v1 on Wed, 01 Oct 2025 00:00:00 GMT"
+    );
+    assert_eq!(text(response).await, "0");
+
+    *now.lock().unwrap() = UNIX_EPOCH + Duration::from_secs(1_759_276_800);
+    let request = Request::builder()
+        .uri("/api/test")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert!(
+        !response
+            .headers()
+            .contains_key(&X_API_DEFAULT_VERSION_CHANGING)
+    );
+    assert_eq!(text(response).await, "1");
+}
+
+#[tokio::test]
+async fn test_ramp_default_version() {
+    let app = Router::new()
+        .route("/api/v0/test", get(ok_0))
+        .route("/api/v1/test", get(ok_1));
+
+    let now = Arc::new(Mutex::new(UNIX_EPOCH));
+    let clock = {
+        let now = now.clone();
+        move || *now.lock().unwrap()
+    };
+    let mut app = ApiVersionLayer::new("/api", API_VERSIONS)
+        .default_version(0)
+        .client_key_header(HeaderName::from_static("x-client-id"))
+        .ramp_default_version(
+            1,
+            UNIX_EPOCH + Duration::from_secs(100),
+            UNIX_EPOCH + Duration::from_secs(200),
+        )
+        .clock(clock)
+        .layer(app);
+
+    async fn versions<S>(app: &mut S) -> usize
+    where
+        S: Service<Request<Body>, Response = Response, Error = Infallible>,
+    {
+        let mut count = 0;
+        for client in 0..100 {
+            let request = Request::builder()
+                .uri("/api/test")
+                .header("x-client-id", format!("client-{client}"))
+                .body(Body::empty())
+                .unwrap();
+            let response = app.call(request).await.unwrap();
+            if text(response).await == "1" {
+                count += 1;
+            }
+        }
+        count
+    }
+
+    assert_eq!(versions(&mut app).await, 0);
+
+    *now.lock().unwrap() = UNIX_EPOCH + Duration::from_secs(150);
+    let halfway = versions(&mut app).await;
+    assert!(halfway > 20 && halfway < 80);
+    assert_eq!(versions(&mut app).await, halfway);
+
+    *now.lock().unwrap() = UNIX_EPOCH + Duration::from_secs(200);
+    assert_eq!(versions(&mut app).await, 100);
+}
+
+#[tokio::test]
+async fn test_default_experiment() {
+    let app = Router::new()
+        .route("/api/v0/test", get(ok_0))
+        .route("/api/v1/test", get(ok_1));
+
+    let mut app = ApiVersionLayer::new("/api", API_VERSIONS)
+        .client_key_header(HeaderName::from_static("x-client-id"))
+        .default_experiment("checkout", (0, 1), (1, 1))
+        .layer(app);
+
+    let mut versions = [0; 2];
+    for n in 0..100 {
+        let client_key = format!("client-{n}");
+        let version = bucket(&client_key, "checkout") >= BUCKETS / 2;
+        let request = Request::builder()
+            .uri("/api/test")
+            .header("x-client-id", &client_key)
+            .body(Body::empty())
+            .unwrap();
+        let response = app.call(request).await.unwrap();
+        let expected = if version {
+            "checkout=v1"
+        } else {
+            "checkout=v0"
+        };
+        assert_eq!(response.headers()[&X_API_VERSION_EXPERIMENT], expected);
+        let body = text(response).await;
+        assert_eq!(body, if version { "1" } else { "0" });
+        versions[version as usize] += 1;
+    }
+    assert!(versions[0] > 0 && versions[1] > 0);
+
+    let request = Request::builder()
+        .uri("/api/test")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert!(!response.headers().contains_key(&X_API_VERSION_EXPERIMENT));
+    assert_eq!(text(response).await, "1");
+
+    let request = Request::builder()
+        .uri("/api/v0/test")
+        .header("x-client-id", "client-0")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert!(!response.headers().contains_key(&X_API_VERSION_EXPERIMENT));
+}
+
+#[tokio::test]
+async fn test_version_health() {
+    let app = Router::new()
+        .route("/api/v0/test", get(ok_0))
+        .route("/api/v1/test", get(ok_1));
+
+    let circuit_breaker = CircuitBreaker::new(0.05);
+    let mut app = ApiVersionLayer::new("/api", API_VERSIONS)
+        .version_health(circuit_breaker.clone())
+        .layer(app);
+
+    let request = Request::builder()
+        .uri("/api/test")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(text(response).await, "1");
+
+    circuit_breaker.report_error_rate(1, 0.2);
+    let request = Request::builder()
+        .uri("/api/test")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(text(response).await, "0");
+
+    let request = Request::builder()
+        .uri("/api/test")
+        .header(&X_API_VERSION, "v1")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(text(response).await, "1");
+
+    circuit_breaker.report_error_rate(1, 0.01);
+    let request = Request::builder()
+        .uri("/api/test")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(text(response).await, "1");
+}
+
+#[tokio::test]
+async fn test_internal_version() {
+    const API_VERSIONS: ApiVersions<3> = ApiVersions::new([0, 1, 2]);
+
+    let app = Router::new()
+        .route("/api/v0/test", get(ok_0))
+        .route("/api/v1/test", get(ok_1))
+        .route("/api/v2/test", get(ok_2));
+
+    let app = ApiVersionLayer::new("/api", API_VERSIONS)
+        .internal_version(2)
+        .discovery_endpoint(true)
+        .layer(app);
+    let mut app = tower::service_fn(move |mut request: Request<Body>| {
+        if request.headers().contains_key("x-staff") {
+            request.extensions_mut().insert(InternalAccess);
+        }
+        app.clone().call(request)
+    });
+
+    let request = Request::builder()
+        .uri("/api/test")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(text(response).await, "1");
+
+    let request = Request::builder()
+        .uri("/api/test")
+        .header(&X_API_VERSION, "v2")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(response.headers()[&X_API_SUPPORTED_VERSIONS], "v0, v1");
+
+    let request = Request::builder()
+        .uri("/api/v2/test")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let request = Request::builder()
+        .uri("/api/test")
+        .header(&X_API_VERSION, "v2")
+        .header("x-staff", "true")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(text(response).await, "2");
+
+    let request = Request::builder()
+        .uri("/api/_versions")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    let manifest = serde_json::from_str::<serde_json::Value>(&text(response).await).unwrap();
+    assert_eq!(manifest["versions"].as_array().unwrap().len(), 2);
+    assert_eq!(manifest["default_version"], 1);
+}
+
+#[tokio::test]
+async fn test_release_internal_version() {
+    const API_VERSIONS: ApiVersions<3> = ApiVersions::new([0, 1, 2]);
+
+    let app = Router::new()
+        .route("/api/v0/test", get(ok_0))
+        .route("/api/v1/test", get(ok_1))
+        .route("/api/v2/test", get(ok_2));
+
+    let store = InMemoryVersionStateStore::new();
+    let mut app = ApiVersionLayer::new("/api", API_VERSIONS)
+        .internal_version(2)
+        .state_store(store.clone())
+        .layer(app);
+
+    let request = Request::builder()
+        .uri("/api/v2/test")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    store.save(VersionState {
+        released: BTreeSet::from([2]),
+        ..Default::default()
+    });
+
+    let request = Request::builder()
+        .uri("/api/v2/test")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(text(response).await, "2");
+
+    // Released versions are used as default.
+    let request = Request::builder()
+        .uri("/api/test")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(text(response).await, "2");
+}
+
+#[tokio::test]
+async fn test_error_message() {
+    let app = Router::new()
+        .route("/api/v0/test", get(ok_0))
+        .route("/api/v1/test", get(ok_1));
+
+    let mut app = ApiVersionLayer::new("/api", API_VERSIONS)
+        .error_message(|kind, request| {
+            let language = request
+                .headers()
+                .get(ACCEPT_LANGUAGE)
+                .and_then(|language| language.to_str().ok());
+            match (kind, language) {
+                (VersioningError::UnknownVersion(version), Some("de")) => {
+                    format!("unbekannte Version '{version}'")
+                }
+                (kind, _) => kind.to_string(),
+            }
+        })
+        .layer(app);
+
+    let request = Request::builder()
+        .uri("/api/test")
+        .header(&X_API_VERSION, "v2")
+        .header(ACCEPT_LANGUAGE, "de")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(text(response).await, "unbekannte Version '2'");
+
+    let request = Request::builder()
+        .uri("/api/test")
+        .header(&X_API_VERSION, "v2")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(text(response).await, "unknown version '2'");
+}
+
+#[tokio::test]
+async fn test_skip_method() {
+    let app = Router::new()
+        .route("/api/v1/test", get(ok_1))
+        .route("/api/test", options(ready));
+
+    let mut app = ApiVersionLayer::new("/api", API_VERSIONS)
+        .skip_method(Method::OPTIONS)
+        .layer(app);
+
+    let request = Request::builder()
+        .method(Method::OPTIONS)
+        .uri("/api/test")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(text(response).await, "ready");
+
+    let request = Request::builder()
+        .uri("/api/test")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(text(response).await, "1");
+}
+
+#[tokio::test]
+async fn test_strict() {
+    let app = Router::new()
+        .route("/api/v1/test", get(ok_1))
+        .route("/api", get(ready));
+
+    let mut app = ApiVersionLayer::new("/api", API_VERSIONS)
+        .strict(true)
+        .layer(app);
+
+    let request = Request::builder()
+        .uri("/api/test")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(text(response).await, "1");
+
+    let request = Request::builder()
+        .uri("/api/test")
+        .header(&X_API_VERSION, "1")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let request = Request::builder()
+        .uri("/api/v7/test")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(text(response).await, "unknown version '7'");
+
+    let request = Request::builder().uri("/api").body(Body::empty()).unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(text(response).await, "unversioned path");
+}
+
+#[tokio::test]
+async fn test_max_version_header_values() {
+    let app = Router::new().route("/api/v0/test", get(ok_0));
+
+    let mut app = ApiVersionLayer::new("/api", API_VERSIONS)
+        .max_version_header_values(2)
+        .layer(app);
+
+    let request = Request::builder()
+        .uri("/api/test")
+        .header(&X_API_VERSION, "v0")
+        .header(&X_API_VERSION, "v0")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(text(response).await, "0");
+
+    let request = Request::builder()
+        .uri("/api/test")
+        .header(&X_API_VERSION, "v0")
+        .header(&X_API_VERSION, "v0")
+        .header(&X_API_VERSION, "v0")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(text(response).await, "invalid version header");
+
+    let mut request = Request::builder().uri("/api/test");
+    for _ in 0..1_000 {
+        request = request.header(&X_API_VERSION, "v0");
+    }
+    let response = app
+        .call(request.body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_dot_segment_policy() {
+    let app = Router::new()
+        .route("/api/v0/secret", get(ok_0))
+        .route("/api/v1/test", get(ok_1));
+
+    // By default, dot segments are removed before versioning, hence the internal version cannot
+    // be reached via path traversal.
+    let mut normalizing_app = ApiVersionLayer::new("/api", API_VERSIONS)
+        .internal_version(0)
+        .strict(true)
+        .layer(app.clone());
+
+    for uri in [
+        "/api/v1/../v0/secret",
+        "/api/v1/%2e%2E/v0/secret",
+        "/api/../api/v0/secret",
+        "/other/../api/v0/secret",
+    ] {
+        let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+        let response = normalizing_app.call(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND, "{uri}");
+        assert_eq!(text(response).await, "unknown version '0'", "{uri}");
+    }
+
+    let request = Request::builder()
+        .uri("/api/./v0/../test?x=1")
+        .body(Body::empty())
+        .unwrap();
+    let response = normalizing_app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(text(response).await, "1");
+
+    let mut rejecting_app = ApiVersionLayer::new("/api", API_VERSIONS)
+        .internal_version(0)
+        .dot_segment_policy(DotSegmentPolicy::Reject)
+        .layer(app.clone());
+
+    for uri in [
+        "/api/v1/../v0/secret",
+        "/api/./v1/test",
+        "/other/../api/v1/test",
+    ] {
+        let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+        let response = rejecting_app.call(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{uri}");
+        assert_eq!(response.headers()[&X_API_VERSION_ERROR], "dot-segments");
+    }
+
+    // Requests neither before nor after removing dot segments under the base path are not
+    // affected.
+    let request = Request::builder()
+        .uri("/other/../test")
+        .body(Body::empty())
+        .unwrap();
+    let response = rejecting_app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert!(!response.headers().contains_key(&X_API_VERSION_ERROR));
+
+    let mut passing_app = ApiVersionLayer::new("/api", API_VERSIONS)
+        .dot_segment_policy(DotSegmentPolicy::Pass)
+        .layer(app);
+
+    let request = Request::builder()
+        .uri("/api/v1/../v0/secret")
+        .body(Body::empty())
+        .unwrap();
+    let response = passing_app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn test_audit_sink() {
+    let app = Router::new()
+        .route("/api/v0/test", get(ok_0))
+        .route("/api/v1/test", get(ok_1));
+
+    let (records, mut received) = tokio::sync::mpsc::unbounded_channel();
+    let mut app = ApiVersionLayer::new("/api", API_VERSIONS)
+        .client_key_header(HeaderName::from_static("x-client-id"))
+        .audit_sink(move |record: NegotiationRecord| {
+            let records = records.clone();
+            async move {
+                records.send(record).unwrap();
+            }
+        })
+        .layer(app);
+
+    for (uri, client_key) in [
+        ("/api/test", Some("client-1")),
+        ("/api/v0/test", None),
+        ("/api/test", Some("client-2")),
+        ("/other", None),
+    ] {
+        let mut request = Request::builder().uri(uri);
+        if let Some(client_key) = client_key {
+            request = request.header("x-client-id", client_key);
+        }
+        if client_key == Some("client-2") {
+            request = request.header(&X_API_VERSION, "v7");
+        }
+        app.call(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+    }
+
+    let record = received.recv().await.unwrap();
+    assert_eq!(record.method, Method::GET);
+    assert_eq!(record.uri, "/api/test");
+    assert_eq!(record.client_key.as_deref(), Some("client-1"));
+    assert_eq!(
+        record.outcome,
+        NegotiationOutcome::Negotiated {
+            version: 1,
+            source: VersionSource::Defaulted
+        }
+    );
+
+    let record = received.recv().await.unwrap();
+    assert_eq!(record.uri, "/api/v0/test");
+    assert_eq!(record.client_key, None);
+    assert_eq!(
+        record.outcome,
+        NegotiationOutcome::Negotiated {
+            version: 0,
+            source: VersionSource::PathPrefix
+        }
+    );
+
+    let record = received.recv().await.unwrap();
+    assert_eq!(record.client_key.as_deref(), Some("client-2"));
+    assert_eq!(
+        record.outcome,
+        NegotiationOutcome::Failed(VersioningError::UnknownVersion(7))
+    );
+
+    let record = received.recv().await.unwrap();
+    assert_eq!(record.uri, "/other");
+    assert_eq!(record.outcome, NegotiationOutcome::Skipped);
+}
+
+#[tokio::test]
+async fn test_cors_policy() {
+    const API_VERSIONS: ApiVersions<3> = ApiVersions::new([0, 1, 2]);
+
+    let app = Router::new()
+        .route("/api/v0/test", get(ok_0))
+        .route("/api/v1/test", get(ok_1))
+        .route("/api/v2/test", get(ok_2));
+
+    let mut app = ApiVersionLayer::new("/api", API_VERSIONS)
+        .cors_policy(0, CorsPolicy::any_origin())
+        .cors_policy(
+            2,
+            CorsPolicy::allowed_origins([HeaderValue::from_static("https://app.example.com")])
+                .allowed_methods([Method::GET, Method::PUT])
+                .max_age(Duration::from_secs(600)),
+        )
+        .layer(app);
+
+    let request = Request::builder()
+        .uri("/api/v0/test")
+        .header(ORIGIN, "https://evil.example.com")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+    assert_eq!(
+        response.headers()[ACCESS_CONTROL_EXPOSE_HEADERS],
+        "x-api-version, deprecation, sunset"
+    );
+
+    let request = Request::builder()
+        .method(Method::OPTIONS)
+        .uri("/api/test")
+        .header(ORIGIN, "https://app.example.com")
+        .header(ACCESS_CONTROL_REQUEST_METHOD, "PUT")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert_eq!(
+        response.headers()[ACCESS_CONTROL_ALLOW_ORIGIN],
+        "https://app.example.com"
+    );
+    assert_eq!(response.headers()[ACCESS_CONTROL_ALLOW_METHODS], "GET, PUT");
+    assert_eq!(
+        response.headers()[ACCESS_CONTROL_ALLOW_HEADERS],
+        "x-api-version"
+    );
+    assert_eq!(response.headers()[ACCESS_CONTROL_MAX_AGE], "600");
+
+    let request = Request::builder()
+        .method(Method::OPTIONS)
+        .uri("/api/test")
+        .header(ORIGIN, "https://evil.example.com")
+        .header(ACCESS_CONTROL_REQUEST_METHOD, "PUT")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert!(!response.headers().contains_key(ACCESS_CONTROL_ALLOW_ORIGIN));
+
+    let request = Request::builder()
+        .uri("/api/test")
+        .header(ORIGIN, "https://evil.example.com")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(!response.headers().contains_key(ACCESS_CONTROL_ALLOW_ORIGIN));
+    assert_eq!(response.headers()[VARY], "origin");
+
+    let request = Request::builder()
+        .uri("/api/v1/test")
+        .header(ORIGIN, "https://app.example.com")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(!response.headers().contains_key(ACCESS_CONTROL_ALLOW_ORIGIN));
+}
+
+#[tokio::test]
+async fn test_latest_version() {
+    const API_VERSIONS: ApiVersions<4> = ApiVersions::new([0, 1, 2, 3]);
+
+    let app = Router::new()
+        .route("/api/v0/test", get(ok_0))
+        .route("/api/v1/test", get(ok_1))
+        .route("/api/v2/test", get(ok_2));
+
+    let mut app = ApiVersionLayer::new("/api", API_VERSIONS)
+        .default_version(0)
+        .internal_version(3)
+        .layer(app);
+
+    let request = Request::builder()
+        .uri("/api/test")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(text(response).await, "0");
+
+    for latest in ["*", "latest"] {
+        let request = Request::builder()
+            .uri("/api/test")
+            .header(&X_API_VERSION, latest)
+            .body(Body::empty())
+            .unwrap();
+        let response = app.call(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(text(response).await, "2");
+    }
+}
+
+#[tokio::test]
+async fn test_negotiated_version() {
+    let layer = ApiVersionLayer::new("/api", API_VERSIONS);
+    let app = Router::new()
+        .route("/api/test", get(negotiated_version))
+        .route("/other", get(negotiated_version));
+
+    // Without the layer, the version is negotiated by the extractor.
+    let mut unversioned_app = app.clone().with_state(layer.negotiator());
+
+    let request = Request::builder()
+        .uri("/api/test")
+        .header(&X_API_VERSION, "v0")
+        .body(Body::empty())
+        .unwrap();
+    let response = unversioned_app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(text(response).await, "0 Header");
+
+    let request = Request::builder()
+        .uri("/api/test")
+        .body(Body::empty())
+        .unwrap();
+    let response = unversioned_app.call(request).await.unwrap();
+    assert_eq!(text(response).await, "1 Defaulted");
+
+    let request = Request::builder()
+        .uri("/api/test")
+        .header(&X_API_VERSION, "v7")
+        .body(Body::empty())
+        .unwrap();
+    let response = unversioned_app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(text(response).await, "unknown version '7'");
+
+    let request = Request::builder()
+        .uri("/other")
+        .body(Body::empty())
+        .unwrap();
+    let response = unversioned_app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+    // With the layer, its result is used.
+    let app = Router::new()
+        .route("/api/v0/test", get(negotiated_version))
+        .with_state(layer.negotiator());
+    let mut versioned_app = layer.layer(app);
+
+    let request = Request::builder()
+        .uri("/api/v0/test")
+        .body(Body::empty())
+        .unwrap();
+    let response = versioned_app.call(request).await.unwrap();
+    assert_eq!(text(response).await, "0 PathPrefix");
+}
+
+#[tokio::test]
+async fn test_middleware_with_state() {
+    #[derive(Clone)]
+    struct AppState {
+        versioning: ApiVersionState<2>,
+    }
+
+    impl FromRef<AppState> for ApiVersionState<2> {
+        fn from_ref(state: &AppState) -> Self {
+            state.versioning.clone()
+        }
+    }
+
+    let state = AppState {
+        versioning: ApiVersionLayer::new("/api", API_VERSIONS)
+            .default_version(0)
+            .into(),
+    };
+    let app = Router::new()
+        .route("/api/v0/test", get(ok_0))
+        .route("/api/v1/test", get(ok_1))
+        .with_state(state.clone());
+
+    let mut app = from_fn_with_state(state.clone(), middleware_with_state::<2>).layer(app);
+
+    let request = Request::builder()
+        .uri("/api/test")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(text(response).await, "0");
+
+    let request = Request::builder()
+        .uri("/api/test")
+        .header(&X_API_VERSION, "v1")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(text(response).await, "1");
+
+    let app = Router::new().route("/api/v0/test", get(ok_0));
+    let mut app = state.versioning.layer(app);
+
+    let request = Request::builder()
+        .uri("/api/test")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(text(response).await, "0");
+}
+
+#[tokio::test]
+async fn test_path_alias() {
+    let app = Router::new()
+        .route("/api/v0/users/{id}", get(ok_0))
+        .route("/api/v1/accounts/{id}", get(ok_1))
+        .route("/api/v1/usersettings", get(ok_1));
+    let mut app = ApiVersionLayer::new("/api", API_VERSIONS)
+        .path_alias(1, "/users", "/accounts")
+        .layer(app);
+
+    for (uri, version, expected) in [
+        ("/api/users/42", "v0", StatusCode::OK),
+        ("/api/users/42", "v1", StatusCode::OK),
+        ("/api/v1/users/42", "v1", StatusCode::OK),
+        ("/api/accounts/42", "v1", StatusCode::OK),
+        ("/api/accounts/42", "v0", StatusCode::NOT_FOUND),
+        ("/api/usersettings", "v1", StatusCode::OK),
+    ] {
+        let request = Request::builder()
+            .uri(uri)
+            .header(&X_API_VERSION, version)
+            .body(Body::empty())
+            .unwrap();
+        let response = app.call(request).await.unwrap();
+        assert_eq!(response.status(), expected, "{uri} {version}");
+    }
+}
+
+#[tokio::test]
+async fn test_rewrite_template() {
+    let app = Router::new()
+        .route("/api/users/v0", get(ok_0))
+        .route("/api/users/v1/{id}", get(ok_1));
+
+    let mut app = ApiVersionLayer::new("/api", API_VERSIONS)
+        .rewrite_template("{base}/{resource}/v{version}{rest}")
+        .layer(app);
+
+    let request = Request::builder()
+        .uri("/api/users/42?x=1")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(text(response).await, "1");
+
+    let request = Request::builder()
+        .uri("/api/v0/users")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(text(response).await, "0");
+}
+
+#[tokio::test]
+async fn test_local() {
+    // The inner service's future holds an Rc across an await point, hence is not Send.
+    let inner = tower::service_fn(|request: Request<Body>| async move {
+        let path = std::rc::Rc::new(request.uri().path().to_owned());
+        tokio::task::yield_now().await;
+        Ok::<_, Infallible>(path.to_string().into_response())
+    });
+    let mut app = ApiVersionLayer::new("/api", API_VERSIONS)
+        .local()
+        .layer(inner);
+
+    let request = Request::builder()
+        .uri("/api/test")
+        .header(&X_API_VERSION, "v0")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(text(response).await, "/api/v0/test");
+
+    let request = Request::builder()
+        .uri("/api/test")
+        .header(&X_API_VERSION, "v2")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(response.headers()[&X_API_VERSION_ERROR], "unknown-version");
+}
+
+#[tokio::test]
+async fn test_installed_twice() {
+    let layer = ApiVersionLayer::new("/api", API_VERSIONS)
+        .rewrite_template("{base}/{resource}/v{version}{rest}");
+    let app = Router::new().route("/api/users/v1", get(ok_1));
+    let mut app = layer.layer(layer.layer(app));
+
+    let request = Request::builder()
+        .uri("/api/users")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(text(response).await, "1");
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn test_deprecation_budget() {
+    let app = Router::new()
+        .route("/api/v0/test", get(ok_0))
+        .route("/api/v1/test", get(ok_1));
+
+    let start = UNIX_EPOCH + Duration::from_secs(1_800_000_000);
+    let now = Arc::new(Mutex::new(start));
+    let (alerts, mut received) = tokio::sync::mpsc::unbounded_channel();
+    let budget = DeprecationBudget::new(0.25, Duration::from_secs(60), move |alert| {
+        let alerts = alerts.clone();
+        async move {
+            alerts.send(alert).unwrap();
+        }
+    });
+    let mut app = ApiVersionLayer::new("/api", API_VERSIONS)
+        .deprecation(0, UNIX_EPOCH)
+        .deprecation_budget(budget)
+        .clock({
+            let now = now.clone();
+            move || *now.lock().unwrap()
+        })
+        .layer(app);
+
+    // First window within budget, second one exceeding it, third one to evaluate the second.
+    for (offset, uris) in [
+        (0, ["/api/v0/test", "/api/test", "/api/test", "/api/test"]),
+        (60, ["/api/v0/test", "/api/v0/test", "/api/test", "/other"]),
+        (120, ["/api/test", "/api/test", "/api/test", "/api/test"]),
+    ] {
+        *now.lock().unwrap() = start + Duration::from_secs(offset);
+        for uri in uris {
+            let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+            app.call(request).await.unwrap();
+        }
+    }
+
+    let alert = received.recv().await.unwrap();
+    assert_eq!(alert.start, start + Duration::from_secs(60));
+    assert_eq!(alert.requests, 3);
+    assert_eq!(alert.deprecated_requests, BTreeMap::from([(0, 2)]));
+    assert!(received.try_recv().is_err());
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn test_concurrency_limits() {
+    let gate = Arc::new(tokio::sync::Semaphore::new(0));
+    let app = Router::new()
+        .route(
+            "/api/v0/test",
+            get({
+                let gate = gate.clone();
+                move || async move {
+                    gate.acquire().await.unwrap().forget();
+                    "0"
+                }
+            }),
+        )
+        .route("/api/v1/test", get(ok_1));
+
+    let limits = ConcurrencyLimits::new([(0, 1)]);
+    let app = ApiVersionLayer::new("/api", API_VERSIONS)
+        .concurrency_limits(limits.clone())
+        .layer(app);
+
+    let request = || {
+        Request::builder()
+            .uri("/api/v0/test")
+            .body(Body::empty())
+            .unwrap()
+    };
+    let first = tokio::spawn(app.clone().call(request()));
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let second = tokio::spawn(app.clone().call(request()));
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(limits.in_flight(0), 1);
+
+    // The other version is not affected.
+    let request = Request::builder()
+        .uri("/api/v1/test")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().call(request).await.unwrap();
+    assert_eq!(text(response).await, "1");
+    assert_eq!(limits.in_flight(1), 0);
+
+    gate.add_permits(1);
+    let response = first.await.unwrap().unwrap();
+    assert_eq!(text(response).await, "0");
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(limits.in_flight(0), 1);
+    assert!(!second.is_finished());
+
+    gate.add_permits(1);
+    let response = second.await.unwrap().unwrap();
+    assert_eq!(text(response).await, "0");
+    assert_eq!(limits.in_flight(0), 0);
+}
+
+#[tokio::test]
+async fn test_no_versioning() {
+    let app = Router::new()
+        .route("/api/v1/test", get(ok_1))
+        .route("/api/webhooks", get(ready));
+
+    let app = ApiVersionLayer::new("/api", API_VERSIONS).layer(app);
+    let mut app = tower::service_fn(move |mut request: Request<Body>| {
+        if request.uri().path() == "/api/webhooks" {
+            request.extensions_mut().insert(NoVersioning);
+        }
+        app.clone().call(request)
+    });
+
+    let request = Request::builder()
+        .uri("/api/webhooks")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(text(response).await, "ready");
+
+    let request = Request::builder()
+        .uri("/api/test")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(text(response).await, "1");
+}
+
+#[cfg(feature = "chaos")]
+#[tokio::test]
+async fn test_chaos() {
+    use api_version::chaos::{Chaos, Fault};
+
+    let app = Router::new()
+        .route("/api/v0/test", get(ok_0))
+        .route("/api/v1/test", get(ok_1));
+
+    let chaos = Chaos::new(Fault::Error(VersioningError::RetiredVersion(1)), 0.5)
+        .filter(|request| request.headers().contains_key("x-chaos"));
+    let mut app = ApiVersionLayer::new("/api", API_VERSIONS)
+        .chaos(chaos)
+        .layer(app);
+
+    let mut statuses = vec![];
+    for _ in 0..4 {
+        let request = Request::builder()
+            .uri("/api/test")
+            .header("x-chaos", "true")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.call(request).await.unwrap();
+        statuses.push(response.status());
+    }
+    assert_eq!(
+        statuses,
+        [
+            StatusCode::OK,
+            StatusCode::GONE,
+            StatusCode::OK,
+            StatusCode::GONE
+        ]
+    );
+
+    let request = Request::builder()
+        .uri("/api/test")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let app = Router::new()
+        .route("/api/v0/test", get(ok_0))
+        .route("/api/v1/test", get(ok_1));
+    let mut app = ApiVersionLayer::new("/api", API_VERSIONS)
+        .chaos(Chaos::new(Fault::ForceVersion(0), 1.0))
+        .layer(app);
+
+    let request = Request::builder()
+        .uri("/api/test")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(text(response).await, "0");
+}
+
+#[tokio::test]
+async fn test_version_source() {
+    let app = Router::new()
+        .route("/api/v0/test", get(source))
+        .route("/api/v1/test", get(source));
+
+    let mut app = ApiVersionLayer::new("/api", API_VERSIONS).layer(app);
+
+    let request = Request::builder()
+        .uri("/api/test")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(text(response).await, "Defaulted");
+
+    let request = Request::builder()
+        .uri("/api/test")
+        .header(&X_API_VERSION, "v0")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(text(response).await, "Header");
+
+    let request = Request::builder()
+        .uri("/api/v0/test")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(text(response).await, "PathPrefix");
+}
+
+#[tokio::test]
+async fn test_middleware_fn() {
+    let app = Router::new()
+        .route("/api/v0/test", get(ok_0))
+        .route("/api/v1/test", get(ok_1));
+
+    let mut app = from_fn(middleware_fn(
+        ApiVersionLayer::new("/api", API_VERSIONS).echo_version(true),
+    ))
+    .layer(app);
+
+    let request = Request::builder()
+        .uri("/api/test")
+        .header(&X_API_VERSION, "v0")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[&X_API_VERSION], "v0");
+    assert_eq!(text(response).await, "0");
+
+    let request = Request::builder()
+        .uri("/api/test")
+        .header(&X_API_VERSION, "v2")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_dispatch() {
+    let v0 = Router::new().route("/api/test", get(ok_0));
+    let v1 = Router::new().route("/api/test", get(ok_1));
+    let app = Router::new().route("/ready", get(ready));
+
+    let mut app = ApiVersionLayer::new("/api", API_VERSIONS)
+        .dispatch([(0, v0), (1, v1)])
+        .layer(app);
+
+    // Not versioned.
+    let request = Request::builder()
+        .uri("/ready")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(text(response).await, "ready");
+
+    // No version should dispatch to the highest version.
+    let request = Request::builder()
+        .uri("/api/test")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(text(response).await, "1");
+
+    // Existing version.
+    let request = Request::builder()
+        .uri("/api/test")
+        .header(&X_API_VERSION, "v0")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(text(response).await, "0");
+
+    // Valid version prefix (existing version).
+    let request = Request::builder()
+        .uri("/api/v0/test")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(text(response).await, "0");
+
+    // Non-existing version.
+    let request = Request::builder()
+        .uri("/api/test")
+        .header(&X_API_VERSION, "v2")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_dispatch_endpoints() {
+    let v0 = Router::new().route("/api/test", get(ok_0));
+    let v1 = Router::new().route("/api/test", get(ok_1));
+    let app = Router::new().route("/ready", get(ready));
+
+    let mut app = ApiVersionLayer::new("/api", API_VERSIONS)
+        .discovery_endpoint(true)
+        .debug_endpoint(true)
+        .dispatch([(0, v0), (1, v1)])
+        .layer(app);
+
+    // Discovery endpoint.
+    let request = Request::builder()
+        .uri("/api/_versions")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let manifest = serde_json::from_str::<serde_json::Value>(&text(response).await).unwrap();
+    assert_eq!(manifest["default_version"], 1);
+    assert_eq!(manifest["versions"].as_array().map(Vec::len), Some(2));
+
+    // Debug endpoint.
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri("/api/_version-debug")
+        .body(Body::from(
+            r#"{ "path": "/api/test", "headers": { "x-api-version": "v0" } }"#,
+        ))
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let report = serde_json::from_str::<serde_json::Value>(&text(response).await).unwrap();
+    assert_eq!(report["outcome"], "negotiated");
+    assert_eq!(report["version"], 0);
+
+    // Versioned requests are still dispatched.
+    let request = Request::builder()
+        .uri("/api/v0/test")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(text(response).await, "0");
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn test_error_responses() {
+    let app = Router::new()
+        .route("/api/v0/test", get(ok_0))
+        .route("/api/v1/test", get(ok_1))
+        .route("/api/v1/slow", get(slow));
+
+    let mut app = ApiVersionLayer::new("/api", API_VERSIONS)
+        .echo_version(true)
+        .timeout(1, Duration::from_millis(10))
+        .map_response(|version, response| {
+            response
+                .headers_mut()
+                .insert("x-test", HeaderValue::from(version));
+        })
+        .layer(app);
+
+    // Error from the inner service.
+    let request = Request::builder()
+        .uri("/api/unknown")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(response.headers()[&X_API_VERSION], "v1");
+    assert_eq!(response.headers()[VARY], "x-api-version");
+    assert_eq!(response.headers()["x-test"], "1");
+
+    // Error from the middleware after negotiation.
+    let request = Request::builder()
+        .uri("/api/slow")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(response.headers()[&X_API_VERSION_ERROR], "timeout");
+    assert_eq!(response.headers()[&X_API_VERSION], "v1");
+    assert_eq!(response.headers()[VARY], "x-api-version");
+    assert_eq!(response.headers()["x-test"], "1");
+
+    // Error from negotiation.
+    let request = Request::builder()
+        .uri("/api/test")
+        .header(&X_API_VERSION, "v2")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(response.headers()[&X_API_VERSION_ERROR], "unknown-version");
+    assert_eq!(
+        response.extensions().get::<VersioningError>(),
+        Some(&VersioningError::UnknownVersion(2))
+    );
+}
+
+#[tokio::test]
+async fn test_version_extension() {
+    #[derive(Clone)]
+    struct SignedVersion(String);
+
+    async fn canonical_string(request: Request<Body>) -> String {
+        let SignedVersion(version) = request.extensions().get().cloned().unwrap();
+        let OriginalRequestUri(uri) = request.extensions().get().cloned().unwrap();
+        let method = request.method().clone();
+        let body = axum::body::to_bytes(request.into_body(), 1_024)
+            .await
+            .unwrap();
+        format!(
+            "{method}\n{uri}\n{version}\n{}",
+            String::from_utf8_lossy(&body)
+        )
+    }
+
+    let app = Router::new().route("/api/v1/orders", post(canonical_string));
+    let mut app = ApiVersionLayer::new("/api", API_VERSIONS)
+        .version_extension(|version, extensions| {
+            extensions.insert(SignedVersion(format!("v{version}")));
+        })
+        .layer(app);
+
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri("/api/orders")
+        .header(&X_API_VERSION, "v1")
+        .body(Body::from("{\"id\":42}"))
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(text(response).await, "POST\n/api/orders\nv1\n{\"id\":42}");
+}
+
+#[tokio::test]
+async fn test_prefer_header() {
+    let app = Router::new()
+        .route("/api/v0/test", get(ok_0))
+        .route("/api/v1/test", get(ok_1));
+    let mut app = ApiVersionLayer::new("/api", API_VERSIONS)
+        .prefer_header(true)
+        .layer(app);
+
+    for (prefer, expected) in [
+        ("respond-async, api-version=0; strict", "0"),
+        ("API-Version=\"v0\"", "0"),
+        ("api-version=latest", "1"),
+        ("return=minimal", "1"),
+    ] {
+        let request = Request::builder()
+            .uri("/api/test")
+            .header("prefer", prefer)
+            .body(Body::empty())
+            .unwrap();
+        let response = app.call(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let preference_applied = response.headers().get("preference-applied").cloned();
+        assert_eq!(text(response).await, expected);
+        if expected == "0" {
+            assert_eq!(preference_applied.unwrap(), "api-version=0");
+        } else {
+            assert!(preference_applied.is_none());
+        }
+    }
+
+    // The "x-api-version" header takes precedence.
+    let request = Request::builder()
+        .uri("/api/test")
+        .header("prefer", "api-version=0")
+        .header(&X_API_VERSION, "v1")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert!(!response.headers().contains_key("preference-applied"));
+    assert_eq!(text(response).await, "1");
+}
+
+#[tokio::test]
+async fn test_vendor_media_type() {
+    let app = Router::new()
+        .route("/api/v0/user", get(user))
+        .route("/api/v1/user", get(user));
+
+    let mut app = ApiVersionLayer::new("/api", API_VERSIONS)
+        .vendor_media_type("myapp")
+        .layer(app);
+
+    let request = Request::builder()
+        .uri("/api/user")
+        .header(ACCEPT, "text/html, application/vnd.myapp.v0+json; q=0.9")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()[CONTENT_TYPE],
+        "application/vnd.myapp.v0+json"
+    );
+    assert_eq!(response.headers()[VARY], "accept");
+
+    let request = Request::builder()
+        .uri("/api/user")
+        .header(ACCEPT, "application/vnd.myapp.v2+json")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let request = Request::builder()
+        .uri("/api/user")
+        .header(ACCEPT, "application/json")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[CONTENT_TYPE], "application/json");
+}
+
+#[tokio::test]
+async fn test_negotiation_strategy() {
+    const API_VERSIONS: ApiVersions<3> = ApiVersions::new([0, 1, 2]);
+
+    let app = Router::new()
+        .route("/api/v0/test", get(ok_0))
+        .route("/api/v1/test", get(ok_1))
+        .route("/api/v2/test", get(ok_2));
+
+    let mut app = ApiVersionLayer::new("/api", API_VERSIONS)
+        .vendor_media_type("myapp")
+        .negotiation_strategy(NegotiationStrategy::PreferLowestCompatible)
+        .sunset(0, UNIX_EPOCH)
+        .layer(app);
+
+    let request = Request::builder()
+        .uri("/api/test")
+        .header(
+            ACCEPT,
+            "application/vnd.myapp.v2+json, application/vnd.myapp.v7+json",
+        )
+        .header(
+            ACCEPT,
+            "application/vnd.myapp.v0+json, application/vnd.myapp.v1+json",
+        )
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(text(response).await, "1");
+
+    let request = Request::builder()
+        .uri("/api/test")
+        .header(
+            ACCEPT,
+            "application/vnd.myapp.v7+json, application/vnd.myapp.v0+json",
+        )
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(text(response).await, "unknown version '7'");
+}
+
+#[tokio::test]
+async fn test_version_state() {
+    let app = Router::new()
+        .route("/api/v0/test", get(schema))
+        .route("/api/v1/test", get(schema));
+
+    let mut app = ApiVersionLayer::new("/api", API_VERSIONS)
+        .version_state([(0, "legacy")])
+        .layer(app);
+
+    let request = Request::builder()
+        .uri("/api/v0/test")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(text(response).await, "legacy");
+
+    let request = Request::builder()
+        .uri("/api/test")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+}
+
+#[cfg(feature = "json-transform")]
+#[tokio::test]
+async fn test_response_transform() {
+    let app = Router::new()
+        .route("/api/v0/user", get(user))
+        .route("/api/v1/user", get(user));
+
+    let mut app = ApiVersionLayer::new("/api", API_VERSIONS)
+        .response_transform(
+            0,
+            JsonTransform::new()
+                .remove("email")
+                .rename("name", "full_name"),
+        )
+        .layer(app);
+
+    let request = Request::builder()
+        .uri("/api/v0/user")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let user = serde_json::from_str::<serde_json::Value>(&text(response).await).unwrap();
+    assert_eq!(user, serde_json::json!({ "full_name": "Jane" }));
+
+    let request = Request::builder()
+        .uri("/api/v1/user")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    let user = serde_json::from_str::<serde_json::Value>(&text(response).await).unwrap();
+    assert_eq!(
+        user,
+        serde_json::json!({ "name": "Jane", "email": "jane@example.com" })
+    );
+}
+
+#[cfg(feature = "json-transform")]
+#[tokio::test]
+async fn test_response_transform_encoded() {
+    let app = Router::new()
+        .route("/api/v0/user", get(gzip_user))
+        .route("/api/v1/user", get(gzip_user));
+
+    let mut app = ApiVersionLayer::new("/api", API_VERSIONS)
+        .response_transform(0, JsonTransform::new().remove("email"))
+        .layer(app);
+
+    let request = Request::builder()
+        .uri("/api/v0/user")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(response.headers()[&X_API_VERSION_ERROR], "encoded-response");
+
+    // Versions without transformation are not affected.
+    let request = Request::builder()
+        .uri("/api/v1/user")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[cfg(feature = "json-transform")]
+#[tokio::test]
+async fn test_request_transform() {
+    let app = Router::new()
+        .route("/api/v0/user", post(echo_json))
+        .route("/api/v1/user", post(echo_json));
+
+    let mut app = ApiVersionLayer::new("/api", API_VERSIONS)
+        .max_body_size(0, 64)
+        .request_transform(
+            0,
+            JsonTransform::new()
+                .rename("full_name", "name")
+                .fill("newsletter", serde_json::json!(false)),
+        )
+        .layer(app);
+
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri("/api/v0/user")
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(r#"{ "full_name": "Jane" }"#))
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let user = serde_json::from_str::<serde_json::Value>(&text(response).await).unwrap();
+    assert_eq!(
+        user,
+        serde_json::json!({ "name": "Jane", "newsletter": false })
+    );
+
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri("/api/v0/user")
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(format!(
+            r#"{{ "full_name": "{}" }}"#,
+            "x".repeat(64)
+        )))
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri("/api/v1/user")
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(r#"{ "full_name": "Jane" }"#))
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    let user = serde_json::from_str::<serde_json::Value>(&text(response).await).unwrap();
+    assert_eq!(user, serde_json::json!({ "full_name": "Jane" }));
+}
+
+#[cfg(feature = "json-transform")]
+#[tokio::test]
+async fn test_no_request_buffering() {
+    let app = Router::new()
+        .route("/api/v0/upload", post(first_chunk))
+        .route("/api/v1/upload", post(first_chunk));
+
+    let mut app = ApiVersionLayer::new("/api", API_VERSIONS)
+        .request_transform(0, JsonTransform::new().remove("x"))
+        .transform_streaming_requests(false)
+        .layer(app);
+
+    // Neither rewriting nor transformations buffer streaming bodies which are not JSON, i.e. the
+    // handler gets the first chunk while the body has not yet ended.
+    for (version, content_type) in [
+        ("v1", "application/octet-stream"),
+        ("v0", "multipart/form-data; boundary=x"),
+    ] {
+        let (sender, receiver) = mpsc::unbounded::<Result<Bytes, Infallible>>();
+        sender.unbounded_send(Ok(Bytes::from("first"))).unwrap();
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("/api/upload")
+            .header(&X_API_VERSION, version)
+            .header(CONTENT_TYPE, content_type)
+            .body(Body::from_stream(receiver))
+            .unwrap();
+        let response = tokio::time::timeout(Duration::from_secs(1), app.call(request))
+            .await
+            .expect("request body is not buffered")
+            .unwrap();
+        assert_eq!(text(response).await, "first");
+        drop(sender);
+    }
+
+    // Streaming JSON bodies are refused instead of buffered for transformations.
+    let (sender, receiver) = mpsc::unbounded::<Result<Bytes, Infallible>>();
+    sender.unbounded_send(Ok(Bytes::from("{"))).unwrap();
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri("/api/upload")
+        .header(&X_API_VERSION, "v0")
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from_stream(receiver))
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::LENGTH_REQUIRED);
+    assert_eq!(response.headers()[&X_API_VERSION_ERROR], "length-required");
+    drop(sender);
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn test_current_version() {
+    let app = Router::new()
+        .route("/api/v0/test", get(current))
+        .route("/api/v1/test", get(current));
+
+    let mut app = ApiVersionLayer::new("/api", API_VERSIONS).layer(app);
+
+    let request = Request::builder()
+        .uri("/api/test")
+        .header(&X_API_VERSION, "v0")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(text(response).await, "0");
+
+    assert_eq!(current_version(), None);
+}
+
+#[tokio::test]
+async fn test_discovery_endpoint() {
+    let app = Router::new()
+        .route("/api/v0/test", get(ok_0))
+        .route("/api/v1/test", get(ok_1));
+
+    let mut app = ApiVersionLayer::new("/api", API_VERSIONS)
+        .deprecation(0, UNIX_EPOCH)
+        .sunset(0, UNIX_EPOCH + Duration::from_secs(4_102_444_800))
+        .changelog(1, "https://example.com/changelog/v1")
+        .migration_guide(0, 1, "https://example.com/migrations/v0-v1")
+        .capabilities(Capabilities::new([
+            Capability::new("cursor-pagination", 1),
+            Capability::new("legacy-ids", 0).until(1),
+        ]))
+        .manifest_metadata("service", serde_json::json!("test"))
+        .discovery_endpoint(true)
+        .html_index(VersionManifest::to_html)
+        .layer(app);
+
+    let request = Request::builder()
+        .uri("/api/_versions")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let manifest = serde_json::from_str::<serde_json::Value>(&text(response).await).unwrap();
+    assert_eq!(
+        manifest,
+        serde_json::json!({
+            "versions": [
+                {
+                    "version": 0,
+                    "status": "deprecated",
+                    "deprecation": "Thu, 01 Jan 1970 00:00:00 GMT",
+                    "sunset": "Fri, 01 Jan 2100 00:00:00 GMT",
+                    "capabilities": ["legacy-ids"],
+                    "migration_guides": [
+                        { "from": 0, "to": 1, "url": "https://example.com/migrations/v0-v1" }
+                    ]
+                },
+                {
+                    "version": 1,
+                    "status": "active",
+                    "changelog": "https://example.com/changelog/v1",
+                    "capabilities": ["cursor-pagination"]
+                }
+            ],
+            "default_version": 1,
+            "service": "test"
+        })
+    );
+
+    let request = Request::builder()
+        .uri("/api/_versions")
+        .header(ACCEPT, "text/html,application/xhtml+xml,*/*;q=0.8")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[CONTENT_TYPE], "text/html; charset=utf-8");
+    let html = text(response).await;
+    assert!(html.contains(
+        "<tr><td>v0</td><td>deprecated</td><td>Fri, 01 Jan 2100 00:00:00 GMT</td>\
+         <td><a href=\"https://example.com/migrations/v0-v1\">migration to v1</a></td></tr>"
+    ));
+    assert!(html.contains(
+        "<tr><td>v1 (default)</td><td>active</td><td></td>\
+         <td><a href=\"https://example.com/changelog/v1\">changelog</a></td></tr>"
+    ));
+
+    let request = Request::builder()
+        .uri("/api/v0/test")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(
+        response.headers()[LINK],
+        r#"<https://example.com/migrations/v0-v1>; rel="deprecation""#
+    );
+
+    let request = Request::builder()
+        .method(Method::OPTIONS)
+        .uri("/api")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert_eq!(response.headers()[ALLOW], "OPTIONS");
+    assert_eq!(response.headers()[&X_API_SUPPORTED_VERSIONS], "v0, v1");
+}
+
+#[tokio::test]
+async fn test_serializer() {
+    let mut app = ApiVersionLayer::new("/api", API_VERSIONS)
+        .discovery_endpoint(true)
+        .serializer("text/plain", versions_text)
+        .layer(Router::new());
+
+    for (accept, content_type, body) in [
+        (None, "application/json", None),
+        (
+            Some("text/*, application/json"),
+            "text/plain",
+            Some("versions: 2"),
+        ),
+        (Some("text/html, */*;q=0.8"), "application/json", None),
+        (Some("application/yaml"), "application/json", None),
+    ] {
+        let mut request = Request::builder().uri("/api/_versions");
+        if let Some(accept) = accept {
+            request = request.header(ACCEPT, accept);
+        }
+        let response = app
+            .call(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_TYPE], content_type);
+        if let Some(body) = body {
+            assert_eq!(text(response).await, body);
+        }
+    }
+
+    // Serialization errors.
+    let mut app = ApiVersionLayer::new("/api", API_VERSIONS)
+        .debug_endpoint(true)
+        .serializer("text/plain", versions_text)
+        .layer(Router::new());
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri("/api/_version-debug")
+        .header(ACCEPT, "text/plain")
+        .body(Body::from(
+            r#"{ "method": "GET", "path": "/api/test", "headers": {} }"#,
+        ))
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(
+        response.headers()[&X_API_VERSION_ERROR],
+        "serialization-failed"
+    );
+}
+
+#[tokio::test]
+async fn test_report() {
+    const API_VERSIONS: ApiVersions<3> = ApiVersions::new([0, 1, 2]);
+
+    let layer = ApiVersionLayer::new("/api", API_VERSIONS)
+        .default_version(1)
+        .schedule_default_version(2, UNIX_EPOCH + Duration::from_secs(4_102_444_800))
+        .internal_version(2)
+        .sunset(0, UNIX_EPOCH)
+        .vendor_media_type("myapp")
+        .strict(true)
+        .skip_method(Method::OPTIONS)
+        .dot_segment_policy(DotSegmentPolicy::Reject);
+
+    let report = layer.report();
+    assert_eq!(report.default_version, 1);
+    assert_eq!(
+        report.to_string(),
+        "API versioning policy for /api\n  \
+         versions: v0 (retired, sunset Thu, 01 Jan 1970 00:00:00 GMT), v1 (active), \
+         v2 (active, internal)\n  \
+         default version: v1 (v2 as of Fri, 01 Jan 2100 00:00:00 GMT)\n  \
+         sources: path prefix, header x-api-version, \
+         media type application/vnd.myapp.v{N}+json, default\n  \
+         filters: strict, skipped methods OPTIONS, dot segments rejected"
+    );
+
+    let report = serde_json::to_value(&report).unwrap();
+    assert_eq!(
+        report["versions"][2],
+        serde_json::json!({ "version": 2, "status": "active", "internal": true, "sunset": null })
+    );
+}
+
+#[tokio::test]
+async fn test_propagate_version() {
+    let mut client =
+        PropagateVersionLayer::new().layer(tower::service_fn(|request: Request<Body>| {
+            let version = request
+                .headers()
+                .get(&X_API_VERSION)
+                .map(|v| v.to_str().unwrap().to_owned())
+                .unwrap_or_default();
+            ok::<_, Infallible>(version)
+        }));
+
+    let mut request = Request::builder()
+        .uri("http://upstream/test")
+        .body(Body::empty())
+        .unwrap();
+    request.extensions_mut().insert(ResolvedApiVersion(0));
+    let version = client.call(request).await.unwrap();
+    assert_eq!(version, "v0");
+
+    let mut request = Request::builder()
+        .uri("http://upstream/test")
+        .header(&X_API_VERSION, "v1")
+        .body(Body::empty())
+        .unwrap();
+    request.extensions_mut().insert(ResolvedApiVersion(0));
+    let version = client.call(request).await.unwrap();
+    assert_eq!(version, "v1");
+
+    let request = Request::builder()
+        .uri("http://upstream/test")
+        .body(Body::empty())
+        .unwrap();
+    let version = client.call(request).await.unwrap();
+    assert_eq!(version, "");
+}
+
+#[cfg(feature = "dev-tools")]
+#[test]
+fn test_openapi_breaking_changes() {
+    use api_version::openapi::{BreakingChange, breaking_changes};
+
+    let old = serde_json::json!({
+        "paths": {
+            "/users": {
+                "get": {
+                    "parameters": [
+                        { "name": "limit", "in": "query" },
+                        { "$ref": "#/components/parameters/tenant" }
+                    ],
+                    "responses": { "200": {}, "404": {} }
+                },
+                "post": { "requestBody": {} }
+            },
+            "/orders": { "get": {} }
+        },
+        "components": {
+            "parameters": {
+                "tenant": { "name": "tenant", "in": "header", "required": true }
+            },
+            "schemas": {
+                "User": {
+                    "properties": {
+                        "id": { "type": "integer" },
+                        "name": { "type": "string" },
+                        "email": { "type": "string" }
+                    },
+                    "required": ["id"]
+                },
+                "Order": {}
+            }
+        }
+    });
+    assert!(breaking_changes(&old, &old).is_empty());
+
+    let new = serde_json::json!({
+        "paths": {
+            "/users": {
+                "parameters": [{ "name": "region", "in": "query", "required": true }],
+                "get": {
+                    "parameters": [
+                        { "name": "limit", "in": "query", "required": true },
+                        { "$ref": "#/components/parameters/tenant" }
+                    ],
+                    "responses": { "200": {} }
+                },
+                "post": { "requestBody": { "required": true } }
+            },
+            "/accounts": { "get": {} }
+        },
+        "components": {
+            "parameters": {
+                "tenant": { "name": "tenant", "in": "header", "required": true }
+            },
+            "schemas": {
+                "User": {
+                    "properties": {
+                        "id": { "type": "string" },
+                        "name": { "type": "string" },
+                        "phone": { "type": "string" }
+                    },
+                    "required": ["id", "name"]
+                }
+            }
+        }
+    });
+    let changes = breaking_changes(&old, &new)
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        changes,
+        [
+            "removed path /orders",
+            "new required query parameter 'limit' for GET /users",
+            "new required query parameter 'region' for GET /users",
+            "removed response 404 for GET /users",
+            "new required query parameter 'region' for POST /users",
+            "new required request body for POST /users",
+            "removed schema Order",
+            "removed property 'email' of schema User",
+            "changed type of property 'id' of schema User from \"integer\" to \"string\"",
+            "new required property 'name' of schema User",
+        ]
+    );
+    assert_eq!(
+        breaking_changes(
+            &serde_json::json!({ "paths": { "/a": {} } }),
+            &serde_json::json!({})
+        ),
+        [BreakingChange::RemovedPath {
+            path: "/a".to_string()
+        }]
+    );
+}
+
+#[cfg(feature = "client")]
+#[tokio::test]
+async fn test_versioned_client() {
+    use api_version::client::{DowngradePolicy, DowngradedVersion, VersionedClientLayer};
+    use tower::{
+        ServiceBuilder, ServiceExt,
+        limit::ConcurrencyLimitLayer,
+        retry::{RetryLayer, budget::TpsBudget},
+    };
+
+    let app = Router::new()
+        .route("/api/v0/test", get(ok_0))
+        .route("/api/v1/test", get(ok_1));
+    let app = ApiVersionLayer::new("/api", API_VERSIONS)
+        .deprecation(0, UNIX_EPOCH)
+        .layer(app);
+    let body_server = tower::service_fn({
+        let app = app.clone();
+        move |request: Request<Body>| app.clone().oneshot(request)
+    });
+    let server = tower::service_fn(move |request: Request<String>| {
+        app.clone().oneshot(request.map(Body::from))
+    });
+
+    // Without downgrading, request bodies need not be cloneable.
+    let mut client = VersionedClientLayer::new(1).layer(body_server);
+    let request = Request::get("/api/test").body(Body::empty()).unwrap();
+    let response = client.call(request).await.unwrap();
+    assert_eq!(text(response).await, "1");
+
+    // Downgrading.
+    let mut client = VersionedClientLayer::new(2)
+        .downgrade_to(0)
+        .layer(server.clone());
+    let request = Request::get("/api/test").body(String::new()).unwrap();
+    let response = client.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.extensions().get::<DowngradedVersion>(),
+        Some(&DowngradedVersion(1))
+    );
+    assert_eq!(text(response).await, "1");
+
+    let mut client = VersionedClientLayer::new(2).layer(server.clone());
+    let request = Request::get("/api/test").body(String::new()).unwrap();
+    let response = client.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    // Downgrading via tower::retry with an exhausted budget.
+    let budget = Arc::new(TpsBudget::new(Duration::from_secs(1), 0, 0.0));
+    let mut client = ServiceBuilder::new()
+        .layer(VersionedClientLayer::new(2))
+        .layer(RetryLayer::new(DowngradePolicy::new(0).budget(budget)))
+        .service(server.clone());
+    let request = Request::get("/api/test").body(String::new()).unwrap();
+    let response = client.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let mut client = ServiceBuilder::new()
+        .layer(VersionedClientLayer::new(2))
+        .layer(RetryLayer::new(DowngradePolicy::new(0)))
+        .service(server.clone());
+    let request = Request::get("/api/test").body(String::new()).unwrap();
+    let response = client.call(request).await.unwrap();
+    assert_eq!(text(response).await, "1");
+
+    // Inner services which must be polled ready, with and without downgrading.
+    let mut client = VersionedClientLayer::new(2)
+        .downgrade_to(0)
+        .layer(ConcurrencyLimitLayer::new(1).layer(server.clone()));
+    for (version, expected) in [(Some("v0"), "0"), (None, "1"), (None, "1")] {
+        let mut request = Request::get("/api/test");
+        if let Some(version) = version {
+            request = request.header(&X_API_VERSION, version);
+        }
+        let response = client
+            .ready()
+            .await
+            .unwrap()
+            .call(request.body(String::new()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(text(response).await, expected);
+    }
+
+    // Deprecation notices.
+    let notices = Arc::new(Mutex::new(vec![]));
+    let mut client = VersionedClientLayer::new(0)
+        .on_deprecation({
+            let notices = notices.clone();
+            move |notice| notices.lock().unwrap().push(notice)
+        })
+        .layer(server);
+    for version in [None, Some("v1")] {
+        let mut request = Request::get("/api/test");
+        if let Some(version) = version {
+            request = request.header(&X_API_VERSION, version);
+        }
+        let response = client.call(request.body(String::new()).unwrap()).await;
+        assert_eq!(response.unwrap().status(), StatusCode::OK);
+    }
+    let notices = notices.lock().unwrap();
+    assert_eq!(notices.len(), 1);
+    assert_eq!(notices[0].version, 0);
+    assert_eq!(notices[0].deprecation, Some(UNIX_EPOCH));
+}
+
+#[cfg(feature = "client")]
+#[tokio::test]
+async fn test_versioned_client_middleware() {
+    use api_version::client::{DowngradedVersion, VersionedClientLayer};
+    use reqwest_middleware::{ClientBuilder, reqwest::Client};
+
+    let app = Router::new()
+        .route("/api/v0/test", get(ok_0))
+        .route("/api/v1/test", get(ok_1));
+    let app = ApiVersionLayer::new("/api", API_VERSIONS)
+        .deprecation(0, UNIX_EPOCH)
+        .layer(app);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/api/test", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, Router::new().fallback_service(app)).await });
+
+    // Downgrading.
+    let client = ClientBuilder::new(Client::new())
+        .with(VersionedClientLayer::new(2).downgrade_to(0))
+        .build();
+    let response = client.get(&url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.extensions().get::<DowngradedVersion>(),
+        Some(&DowngradedVersion(1))
+    );
+    assert_eq!(response.text().await.unwrap(), "1");
+
+    let client = ClientBuilder::new(Client::new())
+        .with(VersionedClientLayer::new(2))
+        .build();
+    let response = client.get(&url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    // Deprecation notices.
+    let notices = Arc::new(Mutex::new(vec![]));
+    let client = ClientBuilder::new(Client::new())
+        .with(VersionedClientLayer::new(0).on_deprecation({
+            let notices = notices.clone();
+            move |notice| notices.lock().unwrap().push(notice)
+        }))
+        .build();
+    for version in [None, Some("v1")] {
+        let mut request = client.get(&url);
+        if let Some(version) = version {
+            request = request.header(&X_API_VERSION, version);
+        }
+        let response = request.send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+    let notices = notices.lock().unwrap();
+    assert_eq!(notices.len(), 1);
+    assert_eq!(notices[0].version, 0);
+    assert_eq!(notices[0].deprecation, Some(UNIX_EPOCH));
+}
+
+#[tokio::test]
+async fn test_version_baggage() {
+    let app = Router::new().route("/api/v1/test", get(baggage));
+    let mut app = ApiVersionLayer::new("/api", API_VERSIONS)
+        .version_baggage(true)
+        .layer(app);
+
+    for (baggage, expected) in [
+        (None, "api.version=1"),
+        (
+            Some("userId=alice, api.version=0;ttl=1,serverNode=DF%2028"),
+            "userId=alice,serverNode=DF%2028,api.version=1",
+        ),
+    ] {
+        let mut request = Request::builder().uri("/api/test");
+        if let Some(baggage) = baggage {
+            request = request.header("baggage", baggage);
+        }
+        let response = app
+            .call(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(text(response).await, expected);
+    }
+
+    let mut client = PropagateVersionLayer::new()
+        .baggage(true)
+        .layer(tower::service_fn(|request: Request<Body>| {
+            ok::<_, Infallible>(request.headers()["baggage"].to_owned())
+        }));
+    let mut request = Request::builder()
+        .uri("http://upstream/test")
+        .header("baggage", "userId=alice")
+        .body(Body::empty())
+        .unwrap();
+    request.extensions_mut().insert(ResolvedApiVersion(0));
+    let baggage = client.call(request).await.unwrap();
+    assert_eq!(baggage, "userId=alice,api.version=0");
+}
+
+#[tokio::test]
+async fn test_policy() {
+    struct TeapotErrors;
+
+    impl Policy for TeapotErrors {
+        fn render(&self, error: VersioningError, _request: &Request<Body>) -> Option<Response> {
+            Some((StatusCode::IM_A_TEAPOT, error.reason()).into_response())
+        }
+    }
+
+    let app = Router::new()
+        .route("/api/v0/test", get(ok_0))
+        .route("/api/v1/test", get(ok_1))
+        .route("/api/health", get(ready));
+
+    let mut app = ApiVersionLayer::new("/api", API_VERSIONS)
+        .policy_before(PolicyStage::Filter, |context: &mut PolicyContext<'_>| {
+            if context.uri().path() == "/api/health" {
+                PolicyOutcome::Skip
+            } else {
+                PolicyOutcome::Continue
+            }
+        })
+        .policy_before(PolicyStage::Extract, |context: &mut PolicyContext<'_>| {
+            if context.uri().query() == Some("api-version=0") {
+                context.set_version(0, VersionSource::Policy);
+            }
+            PolicyOutcome::Continue
+        })
+        .replace_policy(PolicyStage::Render, TeapotErrors)
+        .layer(app);
+
+    let request = Request::builder()
+        .uri("/api/health")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let request = Request::builder()
+        .uri("/api/test?api-version=0")
+        .header(&X_API_VERSION, "v1")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(text(response).await, "0");
+
+    let request = Request::builder()
+        .uri("/api/test")
+        .header(&X_API_VERSION, "v1")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(text(response).await, "1");
+
+    let request = Request::builder()
+        .uri("/api/test")
+        .header(&X_API_VERSION, "v7")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::IM_A_TEAPOT);
+    assert_eq!(
+        response.extensions().get::<VersioningError>(),
+        Some(&VersioningError::UnknownVersion(7))
+    );
+    assert_eq!(text(response).await, "unknown-version");
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn test_debug_endpoint() {
+    let app = Router::new()
+        .route("/api/v0/test", get(ok_0))
+        .route("/api/v1/test", get(ok_1));
+
+    let mut app = ApiVersionLayer::new("/api", API_VERSIONS)
+        .timeout(0, Duration::from_secs(1))
+        .debug_endpoint(true)
+        .layer(app);
+
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri("/api/_version-debug")
+        .body(Body::from(
+            r#"{ "path": "/api/test?foo=bar", "headers": { "x-api-version": "v0" } }"#,
+        ))
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let report = serde_json::from_str::<serde_json::Value>(&text(response).await).unwrap();
+    assert_eq!(
+        report,
+        serde_json::json!({
+            "outcome": "negotiated",
+            "version": 0,
+            "source": "Header",
+            "uri": "/api/v0/test?foo=bar",
+            "deprecated": false,
+            "sunset": null,
+            "max_body_size": null,
+            "timeout_ms": 1000
+        })
+    );
+
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri("/api/_version-debug")
+        .body(Body::from(
+            r#"{ "path": "/api/test", "headers": { "x-api-version": "v2" } }"#,
+        ))
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let report = serde_json::from_str::<serde_json::Value>(&text(response).await).unwrap();
+    assert_eq!(
+        report,
+        serde_json::json!({ "outcome": "failed", "status": 404, "message": "unknown version '2'" })
+    );
+
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri("/api/_version-debug")
+        .body(Body::from(r#"{ "path": "/ready" }"#))
+        .unwrap();
+    let response = app.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let report = serde_json::from_str::<serde_json::Value>(&text(response).await).unwrap();
+    assert_eq!(report, serde_json::json!({ "outcome": "skipped" }));
+}
+
+async fn schema(VersionScopedState(schema): VersionScopedState<&'static str>) -> &'static str {
+    schema
+}
+
+#[cfg(feature = "json-transform")]
+async fn echo_json(Json(value): Json<serde_json::Value>) -> impl IntoResponse {
+    Json(value)
+}
+
+async fn user() -> impl IntoResponse {
+    Json(serde_json::json!({ "name": "Jane", "email": "jane@example.com" }))
+}
+
+#[cfg(feature = "json-transform")]
+async fn gzip_user() -> impl IntoResponse {
+    (
+        [
+            (CONTENT_ENCODING, "gzip"),
+            (CONTENT_TYPE, "application/json"),
+        ],
+        Bytes::from_static(&[0x1f, 0x8b]),
+    )
+}
+
+#[cfg(feature = "tokio")]
+async fn current() -> String {
+    format!("{}", current_version().unwrap())
+}
+
+async fn ready() -> impl IntoResponse {
+    "ready"
+}
+
+async fn negotiated_version(
+    NegotiatedVersion {
+        version, source, ..
+    }: NegotiatedVersion,
+) -> impl IntoResponse {
+    format!("{version} {source:?}")
+}
+
+async fn ok_0() -> impl IntoResponse {
+    "0"
+}
+
+async fn ok_1() -> impl IntoResponse {
+    "1"
+}
+
+async fn not_found(
+    Extension(ResolvedApiVersion(version)): Extension<ResolvedApiVersion>,
+    Extension(source): Extension<VersionSource>,
+    Extension(OriginalRequestUri(uri)): Extension<OriginalRequestUri>,
+) -> impl IntoResponse {
+    let source = match source {
+        VersionSource::Header => "header",
+        _ => "other",
+    };
+    (
+        StatusCode::NOT_FOUND,
+        format!("{uri} not found in v{version} ({source})"),
+    )
+}
+
+#[cfg(feature = "json-transform")]
+async fn first_chunk(body: Body) -> String {
+    let chunk = body.into_data_stream().try_next().await.unwrap().unwrap();
+    String::from_utf8(chunk.to_vec()).unwrap()
+}
+
+async fn ok_2() -> impl IntoResponse {
+    "2"
+}
+
+async fn resolved(Extension(ResolvedApiVersion(version)): Extension<ResolvedApiVersion>) -> String {
+    version.to_string()
+}
+
+async fn source(Extension(source): Extension<VersionSource>) -> String {
+    format!("{source:?}")
+}
+
+async fn graphql(headers: HeaderMap, query: String) -> String {
+    let version = headers["x-graphql-api-version"].to_str().unwrap();
+    format!("{version}: {query}")
+}
+
+async fn version_header(headers: HeaderMap) -> String {
+    let versions = headers.get_all(&X_API_VERSION).iter();
+    versions
+        .map(|v| v.to_str().unwrap())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+async fn baggage(headers: HeaderMap) -> String {
+    headers["baggage"].to_str().unwrap().to_owned()
+}
+
+fn versions_text(value: &serde_json::Value) -> Result<String, &'static str> {
+    let versions = value["versions"].as_array().map(Vec::len);
+    versions
+        .map(|versions| format!("versions: {versions}"))
+        .ok_or("no versions")
+}
+
+async fn etag(headers: HeaderMap) -> impl IntoResponse {
+    if headers
+        .get(IF_NONE_MATCH)
+        .is_some_and(|etags| etags == r#""abc""#)
+    {
+        return StatusCode::NOT_MODIFIED.into_response();
+    }
+    ([(CACHE_CONTROL, "max-age=60"), (ETAG, r#""abc""#)], "etag").into_response()
+}
+
+async fn redirect(
+    Extension(ResolvedApiVersion(version)): Extension<ResolvedApiVersion>,
+) -> Redirect {
+    Redirect::permanent(&format!("/api/v{version}/new?foo=bar"))
+}
+
+#[cfg(feature = "tokio")]
+async fn echo(body: String) -> String {
+    body
+}
+
+#[cfg(feature = "tokio")]
+async fn slow() -> impl IntoResponse {
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    "slow"
+}
+
+async fn sse() -> impl IntoResponse {
+    Sse::new(stream::iter([Ok::<_, Infallible>(
+        Event::default().data("1"),
+    )]))
 }
 
 async fn api_doc() -> impl IntoResponse {